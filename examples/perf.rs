@@ -0,0 +1,195 @@
+//! Measures throughput and latency against a local echo server, to compare configurations.
+//!
+//! Each connection sends messages at a fixed rate for a while, and the time each one takes
+//! to come back is recorded:
+//!
+//! ```text
+//! cargo run --release --example perf --features test-util -- \
+//!     --connections 10 --rate 1000 --seconds 5 --size 64 --nodelay
+//! ```
+//!
+//! Options:
+//!
+//! - `--connections N`: number of connections (default 1)
+//! - `--rate M`: messages sent per second on each connection, or 0 to send
+//!   as fast as possible (default 1000)
+//! - `--seconds S`: how long to send for (default 5)
+//! - `--size B`: payload size in bytes, at least 8 (default 64)
+//! - `--batch K`: pack K messages into each frame with `send_batched()` (default 1,
+//!   which sends each message in its own frame)
+//! - `--nodelay`: disable Nagle's algorithm with `tcp_nodelay()`
+//! - `--unmasked`: send frames without masking with `danger_disable_masking()`
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+use websockets::test_util::EchoServer;
+use websockets::{Message, WebSocket, WebSocketError};
+
+#[derive(Debug, Clone, Copy)]
+struct Options {
+    connections: usize,
+    rate: u64,
+    seconds: u64,
+    size: usize,
+    batch: usize,
+    nodelay: bool,
+    unmasked: bool,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut options = Self {
+            connections: 1,
+            rate: 1000,
+            seconds: 5,
+            size: 64,
+            batch: 1,
+            nodelay: false,
+            unmasked: false,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| format!("{} needs a number", arg))
+            };
+            match arg.as_str() {
+                "--connections" => options.connections = value()? as usize,
+                "--rate" => options.rate = value()?,
+                "--seconds" => options.seconds = value()?,
+                "--size" => options.size = value()? as usize,
+                "--batch" => options.batch = value()? as usize,
+                "--nodelay" => options.nodelay = true,
+                "--unmasked" => options.unmasked = true,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        if options.size < 8 {
+            return Err("--size must be at least 8, to fit a timestamp".to_string());
+        }
+        if options.connections == 0 || options.batch == 0 {
+            return Err("--connections and --batch must be at least 1".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Sends messages stamped with the time since `start`, and returns the round trip
+/// latency of each one
+async fn run_connection(
+    url: String,
+    options: Options,
+    start: Instant,
+) -> Result<Vec<Duration>, WebSocketError> {
+    let ws = WebSocket::builder()
+        .tcp_nodelay(options.nodelay)
+        .danger_disable_masking(options.unmasked)
+        .connect(&url)
+        .await?;
+    let (mut read_half, mut write_half) = ws.split();
+
+    let sender = tokio::spawn(async move {
+        let mut ticks = (options.rate > 0).then(|| {
+            // each tick sends a batch, so that the rate of messages stays the same
+            let period = Duration::from_secs_f64(options.batch as f64 / options.rate as f64);
+            time::interval(period.max(Duration::from_nanos(1)))
+        });
+        let deadline = Instant::now() + Duration::from_secs(options.seconds);
+        while Instant::now() < deadline {
+            if let Some(ticks) = &mut ticks {
+                ticks.tick().await;
+            }
+            let messages: Vec<Vec<u8>> = (0..options.batch)
+                .map(|_| {
+                    let mut payload = vec![0; options.size];
+                    let sent_at = start.elapsed().as_nanos() as u64;
+                    payload[..8].copy_from_slice(&sent_at.to_be_bytes());
+                    payload
+                })
+                .collect();
+            if options.batch == 1 {
+                write_half.send_binary(messages.concat()).await?;
+            } else {
+                write_half.send_batched(messages).await?;
+            }
+        }
+        write_half.close(None).await?;
+        // keep the write half until the server's Close frame has been received
+        Ok::<_, WebSocketError>(write_half)
+    });
+
+    let mut latencies = Vec::new();
+    let mut record = |payload: &[u8]| {
+        let mut sent_at = [0; 8];
+        sent_at.copy_from_slice(&payload[..8]);
+        let sent_at = Duration::from_nanos(u64::from_be_bytes(sent_at));
+        latencies.push(start.elapsed() - sent_at);
+    };
+    if options.batch == 1 {
+        while let Some(message) = read_half.receive_message().await? {
+            if let Message::Binary(payload) = message {
+                record(&payload);
+            }
+        }
+    } else {
+        while let Some(messages) = read_half.receive_batched().await? {
+            messages.iter().for_each(|payload| record(payload));
+        }
+    }
+    sender.await.expect("sender panicked")?;
+    Ok(latencies)
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted[index]
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Options::parse().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let server = EchoServer::spawn().await;
+    println!("{:?}", options);
+
+    let start = Instant::now();
+    let connections: Vec<_> = (0..options.connections)
+        .map(|_| tokio::spawn(run_connection(server.url(), options, start)))
+        .collect();
+    let mut latencies = Vec::new();
+    for connection in connections {
+        match connection.await.expect("connection panicked") {
+            Ok(connection_latencies) => latencies.extend(connection_latencies),
+            Err(e) => eprintln!("connection failed: {}", e),
+        }
+    }
+    let elapsed = start.elapsed();
+
+    if latencies.is_empty() {
+        eprintln!("no messages were echoed");
+        process::exit(1);
+    }
+    latencies.sort();
+    let messages = latencies.len() as f64;
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{} messages in {:.2?}: {:.0} msgs/sec, {:.2} MB/sec",
+        latencies.len(),
+        elapsed,
+        messages / seconds,
+        messages * options.size as f64 / seconds / 1_000_000.0
+    );
+    println!(
+        "latency: p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        latencies[latencies.len() - 1]
+    );
+}
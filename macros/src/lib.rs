@@ -0,0 +1,104 @@
+//! Procedural macros for the [websockets](https://docs.rs/websockets) crate.
+//! These are re-exported by websockets when its `macros` feature is enabled,
+//! and should be used through it.
+
+#![forbid(unsafe_code, missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Error, Item, ItemMod};
+
+/// Generates a typed client for a JSON message protocol from a module containing
+/// an `Inbound` enum (messages received from the server) and an `Outbound` enum
+/// (messages sent to the server).
+///
+/// Both enums derive serde's `Serialize` and `Deserialize` (so they must not derive
+/// them again, but may use `#[serde(...)]` attributes to change how they are represented),
+/// and two items are added to the module:
+///
+/// - `Client`, an alias for `websockets::TypedWebSocket<Inbound, Outbound>`
+/// - `connect(url)`, which connects to the URL and returns a `Client`
+///   along with its `websockets::ConnectionDriver`
+///
+/// See the documentation of `websockets::TypedWebSocket` for an example.
+#[proc_macro_attribute]
+pub fn websocket_protocol(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "#[websocket_protocol] does not take any arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let module = parse_macro_input!(item as ItemMod);
+    match expand(module) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(mut module: ItemMod) -> Result<proc_macro2::TokenStream, Error> {
+    let module_span = module.ident.span();
+    let (_brace, items) = module.content.as_mut().ok_or_else(|| {
+        Error::new(
+            module_span,
+            "#[websocket_protocol] must be applied to an inline module (`mod name { ... }`)",
+        )
+    })?;
+
+    let mut found_inbound = false;
+    let mut found_outbound = false;
+    for item in items.iter_mut() {
+        if let Item::Enum(item_enum) = item {
+            if item_enum.ident == "Inbound" {
+                found_inbound = true;
+            } else if item_enum.ident == "Outbound" {
+                found_outbound = true;
+            } else {
+                continue;
+            }
+            item_enum.attrs.insert(
+                0,
+                parse_quote!(#[derive(::websockets::serde::Serialize, ::websockets::serde::Deserialize)]),
+            );
+            item_enum
+                .attrs
+                .insert(1, parse_quote!(#[serde(crate = "::websockets::serde")]));
+        }
+    }
+    if !found_inbound {
+        return Err(Error::new(
+            module_span,
+            "#[websocket_protocol] module must contain an `Inbound` enum",
+        ));
+    }
+    if !found_outbound {
+        return Err(Error::new(
+            module_span,
+            "#[websocket_protocol] module must contain an `Outbound` enum",
+        ));
+    }
+
+    items.push(parse_quote! {
+        /// A WebSocket client which receives [`Inbound`] messages and sends [`Outbound`] messages.
+        pub type Client = ::websockets::TypedWebSocket<Inbound, Outbound>;
+    });
+    items.push(parse_quote! {
+        /// Connects to the URL with the default settings and wraps the connection
+        /// in a [`Client`]. The returned driver must be spawned
+        /// (see `websockets::ConnectionDriver`).
+        pub async fn connect(
+            url: &str,
+        ) -> ::std::result::Result<
+            (Client, ::websockets::ConnectionDriver),
+            ::websockets::WebSocketError,
+        > {
+            let ws = ::websockets::WebSocket::connect(url).await?;
+            ::std::result::Result::Ok(Client::new(ws))
+        }
+    });
+
+    Ok(quote!(#module))
+}
@@ -1,4 +1,9 @@
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::error::ResolveError;
+#[cfg(feature = "tls")]
 use native_tls::Error as NativeTlsError;
+#[cfg(feature = "macros")]
+use serde_json::Error as JsonError;
 use std::io::Error as IoError;
 use thiserror::Error;
 use url::ParseError;
@@ -10,28 +15,103 @@ pub enum WebSocketError {
     /// Error connecting using TCP
     #[error("could not connect using TCP")]
     TcpConnectionError(IoError),
+    /// Error connecting using a custom [`Transport`](crate::Transport)
+    #[error("could not connect using the custom transport")]
+    TransportConnectionError(IoError),
+    /// Error connecting to the Unix domain socket of a `ws+unix` URL
+    #[cfg(unix)]
+    #[error("could not connect to the Unix domain socket")]
+    UnixConnectionError(IoError),
+    /// Error returned by the [plaintext preamble](crate::WebSocketBuilder::plaintext_preamble())
+    #[error("error in plaintext preamble")]
+    PreambleError(IoError),
+    /// Error returned by the [`before_handshake()`](crate::WebSocketBuilder::before_handshake())
+    /// hook
+    #[error("error in before_handshake hook: {0}")]
+    BeforeHandshakeError(Box<dyn std::error::Error + Send + Sync>),
     /// Error connecting using TLS
+    #[cfg(feature = "tls")]
     #[error("could not connect using TLS")]
     TlsConnectionError(NativeTlsError),
     /// Error building WebSocket with given TLS configuration
+    #[cfg(feature = "tls")]
     #[error("could not build WebSocket with given TLS configuration")]
     TlsBuilderError(NativeTlsError),
     /// Error creating a TLS configuration (such as in method calls on
     /// [`TlsCertificate`](crate::secure::TlsCertificate) or
     /// [`TlsIdentity`](crate::secure::TlsIdentity))
+    #[cfg(feature = "tls")]
     #[error("error with TLS configuration")]
     TlsConfigurationError(NativeTlsError),
+    /// A TLS protocol version older than TLS 1.2 was allowed while the `fips` feature
+    /// is enabled
+    #[error("TLS protocol versions older than TLS 1.2 are not allowed in FIPS mode")]
+    FipsProtocolError,
+    /// A TLS protocol version older than TLS 1.2 was allowed without
+    /// [`tls_danger_allow_legacy_protocols()`](crate::WebSocketBuilder::tls_danger_allow_legacy_protocols()),
+    /// so the connection was refused before connecting (the message says which)
+    #[error("TLS connection refused by policy: {0}")]
+    TlsPolicyError(String),
+    /// A `wss` URL was used without the `tls` feature enabled
+    #[error("secure connections require the tls feature")]
+    TlsNotEnabledError,
     /// Attempted to use the WebSocket when it is already closed
     #[error("websocket is already closed")]
     WebSocketClosedError,
+    /// The connection could not be kept open for
+    /// [reuse](crate::WebSocket::close_for_reuse()), because the WebSocket was not
+    /// connected by this library, had already been shut down, was split with a
+    /// [`ConnectionDriver`](crate::ConnectionDriver), or received data after
+    /// the server's Close frame
+    #[error("connection cannot be reused")]
+    ConnectionNotReusableError,
     /// Error shutting down the internal stream
     #[error("error shutting down stream")]
     ShutdownError(IoError),
+    /// The [shutdown token](crate::WebSocketBuilder::shutdown_token()) was cancelled
+    #[error("websocket was shut down by its shutdown token")]
+    CancelledError,
+    /// The server did not respond to a keepalive or health check Ping frame in time
+    #[error("did not receive a pong in time")]
+    PongTimeoutError,
+    /// The server sent more Ping frames than allowed, and the connection was closed
+    /// (see [`PingFloodPolicy::Close`](crate::PingFloodPolicy::Close))
+    #[error("server sent too many ping frames")]
+    PingFloodError,
+    /// The read half could not queue an event for the write half, because
+    /// [`max_queued_events()`](crate::WebSocketBuilder::max_queued_events()) events were
+    /// queued without the write half being flushed (see [`EventQueuePolicy`](crate::EventQueuePolicy))
+    #[error("too many events queued for the write half")]
+    EventQueueFullError,
 
     // handshake errors
+    /// A header or subprotocol added to the [`WebSocketBuilder`](crate::WebSocketBuilder)
+    /// contains invalid characters
+    #[error("invalid name or value for handshake header {0:?}")]
+    InvalidHeaderError(String),
     /// Invalid handshake response from the server
     #[error("invalid handshake response")]
     InvalidHandshakeError,
+    /// The status line of the server's handshake response could not be parsed
+    #[error("invalid status line in handshake response: {0:?}")]
+    InvalidStatusLineError(String),
+    /// When [redialing](crate::ConnectionInfo::redial()), the server accepted a different
+    /// subprotocol than it did for the original connection
+    #[error("server accepted subprotocol {accepted:?} instead of {expected:?} when redialing")]
+    SubprotocolMismatchError {
+        /// The subprotocol accepted for the original connection
+        expected: Option<String>,
+        /// The subprotocol accepted for the new connection
+        accepted: Option<String>,
+    },
+    /// The server accepted none of the subprotocols of a
+    /// [`ProtocolOffer`](crate::ProtocolOffer) which has no fallback
+    #[error("server accepted none of the offered protocols")]
+    NoProtocolAcceptedError,
+    /// The server's handshake response has more headers, or more bytes of headers, than
+    /// [allowed](crate::WebSocketBuilder::max_handshake_headers())
+    #[error("handshake response is too large")]
+    HandshakeTooLargeError,
     /// The server rejected the handshake request
     #[error("server rejected handshake")]
     HandshakeFailedError {
@@ -42,6 +122,18 @@ pub enum WebSocketError {
         /// Body of the server's handshake response, if any
         body: Option<String>,
     },
+    /// The handshake failed while
+    /// [handshake debugging](crate::WebSocketBuilder::debug_handshake()) was enabled
+    #[error(
+        "{error} (raw handshake response: {:?})",
+        String::from_utf8_lossy(raw_response)
+    )]
+    HandshakeDebugError {
+        /// The error which caused the handshake to fail
+        error: Box<WebSocketError>,
+        /// The raw bytes of the server's handshake response that were received
+        raw_response: Vec<u8>,
+    },
 
     // frame errors
     /// Attempted to use a control frame whose payload is more than 125 bytes
@@ -56,13 +148,39 @@ pub enum WebSocketError {
     /// Received a masked frame from the server
     #[error("received masked frame")]
     ReceivedMaskedFrameError,
+    /// Not enough bytes were given to parse a frame
+    /// (see [`Frame::parse()`](crate::Frame::parse()))
+    #[error("more data is needed to parse the frame")]
+    NeedMoreDataError,
+    /// Received a frame whose payload length was not encoded in the fewest bytes possible
+    /// (only rejected in [strict mode](crate::WebSocketBuilder::strict_mode()))
+    #[error("received frame's payload length is not minimally encoded")]
+    NonMinimalLengthError,
+    /// A [`Frame`](crate::Frame) could not be converted to or from a tungstenite `Message`
+    /// (fragmented frames and raw tungstenite frames have no equivalent)
+    #[error("frame could not be converted to or from a tungstenite message")]
+    MessageConversionError,
+    /// Received a batch which ends in the middle of a message, or a Text message where a
+    /// batch was expected (see [`decode_batch()`](crate::decode_batch()))
+    #[error("received batch is invalid")]
+    InvalidBatchError,
+    /// A message could not be serialized to JSON by a
+    /// [`TypedWebSocket`](crate::TypedWebSocket)
+    #[cfg(feature = "macros")]
+    #[error("message could not be serialized")]
+    SerializeError(JsonError),
+    /// A received message could not be deserialized from JSON by a
+    /// [`TypedWebSocket`](crate::TypedWebSocket)
+    #[cfg(feature = "macros")]
+    #[error("received message could not be deserialized")]
+    DeserializeError(JsonError),
 
     // url errors
     /// URL could not be parsed
     #[error("url could not be parsed")]
     ParseError(ParseError),
-    /// URL has invalid WebSocket scheme (use "ws" or "wss")
-    #[error(r#"invalid websocket scheme (use "ws" or "wss")"#)]
+    /// URL has invalid WebSocket scheme (use "ws" or "wss", or "ws+unix" on Unix)
+    #[error(r#"invalid websocket scheme (use "ws" or "wss", or "ws+unix" on Unix)"#)]
     SchemeError,
     /// URL host is invalid or missing
     #[error("invalid or missing host")]
@@ -76,14 +194,48 @@ pub enum WebSocketError {
     /// Could not resolve the URL's domain
     #[error("could not resolve domain")]
     ResolutionError,
+    /// Error looking up the URL's domain with the
+    /// [DNS resolver](crate::WebSocketBuilder::dns_resolver()), including DNSSEC
+    /// validation failures
+    #[cfg(feature = "hickory-dns")]
+    #[error("could not resolve domain: {0}")]
+    DnsError(ResolveError),
+    /// No URLs were given to [`connect_race()`](crate::WebSocketBuilder::connect_race())
+    #[error("no endpoints to connect to")]
+    NoEndpointsError,
 
     // reading and writing
-    /// Error reading from WebSocket
+    /// Error reading from WebSocket, either during the handshake or while waiting for
+    /// (or in the middle of) a frame's header
     #[error("could not read from WebSocket")]
     ReadError(IoError),
+    /// Error reading from WebSocket in the middle of a frame's payload, such as when a proxy
+    /// cuts the connection once a size limit is reached. If the connection was closed,
+    /// the error's kind is [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof).
+    #[error(
+        "could not read from WebSocket after receiving {received} of {expected} bytes \
+        of a frame's payload"
+    )]
+    PayloadReadError {
+        /// The error reading from the connection
+        source: IoError,
+        /// The number of bytes of the payload received
+        received: usize,
+        /// The length of the payload, from the frame's header
+        expected: usize,
+    },
     /// Error writing to WebSocket
     #[error("could not write to WebSocket")]
     WriteError(IoError),
+    /// Error reading the data to be sent, such as the file passed to
+    /// [`WebSocket::send_file()`](crate::WebSocket::send_file())
+    #[error("could not read the data to be sent")]
+    ReadSourceError(IoError),
+    /// No data was received for longer than the
+    /// [read stall timeout](crate::WebSocketBuilder::read_stall_timeout())
+    /// while in the middle of receiving a frame
+    #[error("timed out waiting for the rest of a frame")]
+    ReadStallTimeoutError,
 
     // splitting
     /// Issue with mpsc channel
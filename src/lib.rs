@@ -28,6 +28,110 @@
 //! Use it to connect, send, and receive data.
 //! Data is sent and received through [`Frame`]s.
 //!
+//! ## TLS
+//!
+//! Secure (`wss`) connections are supported through
+//! [`native-tls`](https://docs.rs/native-tls) with the `tls` feature, which is enabled by default.
+//! For plaintext-only builds, disable default features; `native-tls` (and the platform's
+//! TLS library, such as OpenSSL) is then not a dependency, and connecting to a `wss` URL
+//! fails with [`TlsNotEnabledError`](WebSocketError::TlsNotEnabledError).
+//!
+//! ## Dependencies
+//!
+//! Types from other crates that appear in the public API can be used through
+//! this crate's re-exports, so that their versions always match: TLS types are
+//! aggregated in the `tls` module, [`CancellationToken`] is re-exported at the root,
+//! and the `native_tls`, [`tokio`], [`tokio_util`], and [`url`] crates are
+//! re-exported in full.
+//!
+//! ## DNS
+//!
+//! With the `hickory-dns` feature enabled, a
+//! [hickory-dns](https://docs.rs/hickory-resolver) resolver (re-exported as `hickory_resolver`)
+//! can be used to look up hosts instead of the system resolver, including
+//! DNSSEC validation and SRV record lookups with failover between their targets.
+//! See `WebSocketBuilder::dns_resolver()` and `WebSocketBuilder::dns_srv_lookup()`.
+//!
+//! ## Unix domain sockets
+//!
+//! On Unix, `ws+unix` URLs connect to a local server over a Unix domain socket instead
+//! of TCP. The URL is the path of the socket, followed by a colon and the resource
+//! to request (which defaults to `/`), such as `ws+unix:///var/run/app.sock:/events`.
+//! Paths of sockets cannot contain colons.
+//!
+//! ## Logging
+//!
+//! With the `log` feature enabled, abnormal closures, protocol violations,
+//! and handshake failures are logged at the warn level through the
+//! [`log`](https://docs.rs/log) crate. This can be controlled with
+//! `WebSocketBuilder::log_verbosity()`.
+//!
+//! ## Tungstenite interop
+//!
+//! With the `tungstenite` feature enabled, [`Frame`]s can be converted to and
+//! from [`tungstenite::Message`](https://docs.rs/tungstenite/0.28/tungstenite/enum.Message.html)
+//! with `TryFrom`, which eases migrating from (or mixing with) crates built on tungstenite.
+//! `TungsteniteAdapter` also wraps a [`WebSocket`] in the `Stream`/`Sink` interface
+//! of tokio-tungstenite's `WebSocketStream`.
+//!
+//! ## Typed protocols
+//!
+//! With the `macros` feature enabled, the `#[websocket_protocol]` attribute generates
+//! a typed client from a module's `Inbound` and `Outbound` message enums, which
+//! are serialized as JSON with [serde](https://docs.rs/serde). See `TypedWebSocket`
+//! for an example.
+//!
+//! ## Tasks
+//!
+//! With the `task` feature enabled, `WebSocketTask` spawns a task which connects,
+//! runs a handler for each connection, sends keepalive Pings, and reconnects when the
+//! connection is lost, as a batteries-included entry point for applications.
+//!
+//! ## Testing
+//!
+//! With the `test-util` feature enabled, `test_util::EchoServer` runs a WebSocket echo
+//! server on a local port, so that tests of code using this library do not need to
+//! depend on a public echo server, and `test_util::FaultInjector` injects delays,
+//! truncated frames, and dropped connections at random for chaos testing, while
+//! `WebSocketBuilder::throttle()` simulates slow networks. Frames can also be encoded
+//! and decoded without a connection with [`encode_frame()`] and [`FrameDecoder`].
+//!
+//! ## Performance
+//!
+//! The `perf` example (`cargo run --release --example perf --features test-util`)
+//! measures throughput and latency against a local echo server, so that configurations
+//! can be compared. Received payloads are handed out by [`WebSocket::receive_message()`]
+//! without being copied, and small messages can be packed into one frame with
+//! [`WebSocket::send_batched()`]. `WebSocketBuilder::tcp_nodelay()` lowers the latency of
+//! small frames, and `WebSocketBuilder::danger_disable_masking()` skips masking sent
+//! frames, which only servers that allow it (such as ones on a trusted network) accept.
+//!
+//! ## TLS protocol versions
+//!
+//! Secure connections default to a minimum protocol version of TLS 1.2, and connecting
+//! fails with [`TlsPolicyError`](WebSocketError::TlsPolicyError) if an older protocol
+//! version is allowed through `WebSocketBuilder::tls_min_protocol_version()` or
+//! `WebSocketBuilder::tls_max_protocol_version()`, unless
+//! `WebSocketBuilder::tls_danger_allow_legacy_protocols()` is used to connect to
+//! legacy servers.
+//!
+//! ## FIPS mode
+//!
+//! With the `fips` feature enabled, connecting fails with
+//! [`FipsProtocolError`](WebSocketError::FipsProtocolError) if a protocol version older
+//! than TLS 1.2 is allowed, even if legacy protocols are allowed. Cipher suites are chosen by the
+//! platform's TLS library, which must itself be configured for FIPS (for example,
+//! with the OpenSSL 3 FIPS provider).
+//!
+//! ## Timers
+//!
+//! Every timer used by this library (keepalive Pings and their timeouts,
+//! read stall timeouts, and the closing handshake timeout) is based on
+//! [`tokio::time`](https://docs.rs/tokio/1/tokio/time/index.html).
+//! This means that tests can control them by pausing and advancing time with
+//! `tokio::time::pause()` and `tokio::time::advance()` (or
+//! `#[tokio::test(start_paused = true)]`), without waiting in real time.
+//!
 //! ## License
 //!
 //! This project is licensed under the MIT license.
@@ -40,23 +144,86 @@
 )]
 
 mod error;
+#[cfg(feature = "tls")]
 pub mod secure;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "tls")]
+pub mod tls;
 mod websocket;
 
+// crates whose types appear in the public API, re-exported so that
+// their versions always match the ones used by this library
+pub use bytes;
+#[cfg(feature = "hickory-dns")]
+pub use hickory_resolver;
+#[cfg(feature = "tls")]
+pub use native_tls;
+#[cfg(feature = "macros")]
+pub use serde;
+#[cfg(feature = "macros")]
+pub use serde_json;
+pub use tokio;
+pub use tokio_util;
+pub use tokio_util::sync::CancellationToken;
+pub use url;
+
 pub use error::WebSocketError;
-pub use websocket::frame::Frame;
-pub use websocket::split::{WebSocketReadHalf, WebSocketWriteHalf};
+pub use websocket::batch::{decode_batch, encode_batch};
+pub use websocket::capabilities::{capabilities, Capabilities, WEBSOCKET_VERSION};
+pub use websocket::close_code::CloseCode;
+pub use websocket::codec::{encode_frame, encode_payload_len, FrameDecoder, LenEncoding};
+pub use websocket::connect_limiter::{ConnectLimiter, ConnectStats};
+pub use websocket::connection::{ConnectionFuture, WebSocketConnection};
+pub use websocket::connection_info::{ConnectionInfo, OnReconnectedFuture};
+pub use websocket::connection_state::ConnectionState;
+#[cfg(feature = "hickory-dns")]
+pub use websocket::dns::SrvTarget;
+pub use websocket::driver::ConnectionDriver;
+pub use websocket::entropy::EntropyConfig;
+pub use websocket::frame::{Frame, MessageType, OpcodeSet};
+pub use websocket::handshake_request::{BeforeHandshakeFuture, HandshakeRequest, InterimResponse};
+#[cfg(feature = "log")]
+pub use websocket::logging::LogVerbosity;
+pub use websocket::message::{Message, Utf8Bytes};
+pub use websocket::message_reader::MessageReader;
+pub use websocket::negotiation::ProtocolOffer;
+pub use websocket::recording::{FrameDirection, RecordedFrame, SessionRecorder, SessionReplay};
+pub use websocket::resumable::ResumableTransfer;
+pub use websocket::reuse::ConnectionReuseToken;
+pub use websocket::split::{
+    CloseListener, ClosePolicy, EventPump, EventQueuePolicy, InProgressMessage, MessageProgress,
+    PauseHandle, PingFloodPolicy, SendReceipt, WebSocketReadHalf, WebSocketWriteHalf,
+};
+pub use websocket::stats::{
+    close_code_name, close_code_stats, CloseCodeStats, ConnectTimings, DropReason,
+    FrameSizeHistogram, Stats,
+};
+#[cfg(feature = "task")]
+pub use websocket::task::{TaskContext, WebSocketTask};
+pub use websocket::transport::{PreambleFuture, Transport, TransportFuture, TransportStream};
+#[cfg(feature = "tungstenite")]
+pub use websocket::tungstenite_compat::TungsteniteAdapter;
+#[cfg(feature = "macros")]
+pub use websocket::typed::TypedWebSocket;
 pub use websocket::{builder::WebSocketBuilder, WebSocket};
+#[cfg(feature = "macros")]
+pub use websockets_macros::websocket_protocol;
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::test_util::{recording_server, scripted_server, EchoServer};
     use crate::*;
 
     #[tokio::test]
     async fn echo_length_0_to_125() {
-        let mut ws = WebSocket::connect("ws://echo.websocket.org/")
-            .await
-            .unwrap();
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
         let message = "a".repeat(3).to_string();
         ws.send_text(message.clone()).await.unwrap();
         let received_frame = ws.receive().await.unwrap();
@@ -66,9 +233,8 @@ mod tests {
 
     #[tokio::test]
     async fn echo_length_126_to_u16_max() {
-        let mut ws = WebSocket::connect("ws://echo.websocket.org/")
-            .await
-            .unwrap();
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
         let message = "a".repeat(300).to_string();
         ws.send_text(message.clone()).await.unwrap();
         let received_frame = ws.receive().await.unwrap();
@@ -78,9 +244,8 @@ mod tests {
 
     #[tokio::test]
     async fn echo_length_u16_max_to_u64_max() {
-        let mut ws = WebSocket::connect("ws://echo.websocket.org/")
-            .await
-            .unwrap();
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
         let message = "a".repeat(66000).to_string();
         ws.send_text(message.clone()).await.unwrap();
         let received_frame = ws.receive().await.unwrap();
@@ -89,6 +254,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires access to echo.websocket.org"]
     async fn echo_tls() {
         let mut ws = WebSocket::connect("wss://echo.websocket.org/")
             .await
@@ -101,15 +267,87 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn close() {
-        let mut ws = WebSocket::connect("wss://echo.websocket.org")
+    async fn send_receipts() {
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
+        // header of 2 bytes, 2 bytes of extended payload length, and 4 bytes of masking key
+        let receipt = ws.send_text("a".repeat(300)).await.unwrap();
+        assert_eq!(receipt.payload_bytes, 300);
+        assert_eq!(receipt.frame_bytes, 308);
+        let receipt = ws.send_ping(None).await.unwrap();
+        assert_eq!(receipt.payload_bytes, 0);
+        assert_eq!(receipt.frame_bytes, 6);
+    }
+
+    #[tokio::test]
+    async fn connect_race() {
+        let server = EchoServer::spawn().await;
+        // a port with nothing listening on it refuses the connection
+        let refused = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused_url = format!("ws://{}/", refused.local_addr().unwrap());
+        drop(refused);
+        let url = server.url();
+        let mut ws = WebSocket::builder()
+            .connect_race(&[&refused_url, &url])
             .await
             .unwrap();
+        assert_eq!(ws.connection_info().as_ref().unwrap().url(), url);
+        ws.send_text("foo".to_string()).await.unwrap();
+        assert_eq!(ws.receive().await.unwrap().as_text().unwrap().0, "foo");
+
+        let result = WebSocket::builder().connect_race(&[&refused_url]).await;
+        assert!(matches!(result, Err(WebSocketError::TcpConnectionError(_))));
+        let result = WebSocket::builder().connect_race(&[]).await;
+        assert!(matches!(result, Err(WebSocketError::NoEndpointsError)));
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
         ws.close(Some((1000, String::new()))).await.unwrap();
         let status_code = ws.receive().await.unwrap().as_close().unwrap().0;
         assert_eq!(status_code, 1000);
     }
 
+    #[tokio::test]
+    async fn health_check_keeps_frames_when_dropped() {
+        // a server which sends a Text frame and never answers pings
+        let url = scripted_server(b"\x81\x02hi").await;
+
+        let mut ws = WebSocket::connect(&url).await.unwrap();
+        // the health check is given up on after the Text frame has been received
+        let health_check = ws.health_check(Duration::from_secs(10));
+        assert!(time::timeout(Duration::from_millis(200), health_check)
+            .await
+            .is_err());
+        let frame = time::timeout(Duration::from_secs(1), ws.receive())
+            .await
+            .expect("Text frame was lost")
+            .unwrap();
+        assert_eq!(frame.as_text().unwrap().0, "hi");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_after_close_sends_no_second_close_frame() {
+        let (url, opcodes) = recording_server().await;
+        let token = CancellationToken::new();
+        let mut ws = WebSocket::builder()
+            .shutdown_token(token.clone())
+            .connect(&url)
+            .await
+            .unwrap();
+        ws.close(None).await.unwrap();
+        // cancelled while waiting for the server's Close frame
+        token.cancel();
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::CancelledError)
+        ));
+        drop(ws);
+        assert_eq!(opcodes.await.unwrap(), vec![0x8]);
+    }
+
     #[tokio::test]
     async fn bad_scheme() {
         let resp = WebSocket::connect("http://echo.websocket.org").await;
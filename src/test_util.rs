@@ -0,0 +1,461 @@
+//! Utilities for testing code which uses WebSockets, available with the `test-util` feature.
+//!
+//! [`EchoServer`] tests against a real connection on a local port, while [`MockWebSocket`]
+//! tests code written against [`WebSocketConnection`] without any I/O. Timers in this
+//! library are based on `tokio::time`, so they can be controlled in either case by
+//! pausing and advancing time with `tokio::time::pause()` and `tokio::time::advance()`.
+
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+pub use crate::websocket::fault::FaultInjector;
+use crate::websocket::handshake::GUUID;
+use crate::{ConnectionFuture, Frame, SendReceipt, WebSocketConnection, WebSocketError};
+
+/// A WebSocket echo server running in the background on a local port, for hermetic tests
+/// which would otherwise depend on a public echo server.
+///
+/// Every Text, Binary, and continuation frame received is sent back unchanged
+/// (so fragmented messages are echoed fragment by fragment), Ping frames are answered
+/// with Pong frames, and Close frames are echoed before the connection is closed.
+///
+/// ```
+/// # use websockets::test_util::EchoServer;
+/// # use websockets::{WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let server = EchoServer::spawn().await;
+/// let mut ws = WebSocket::connect(&server.url()).await?;
+/// ws.send_text("foo".to_string()).await?;
+/// let frame = ws.receive().await?;
+/// assert_eq!(frame.as_text().unwrap().0, "foo");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The server must be spawned from within a Tokio runtime, and stops
+/// (closing all of its connections) when it is dropped.
+#[derive(Debug)]
+pub struct EchoServer {
+    addr: SocketAddr,
+    shutdown_token: CancellationToken,
+}
+
+impl EchoServer {
+    /// Starts an echo server on a random port of the loopback interface.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the port could not be bound.
+    pub async fn spawn() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("could not bind echo server");
+        let addr = listener.local_addr().expect("could not bind echo server");
+        let shutdown_token = CancellationToken::new();
+        let token = shutdown_token.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = token.cancelled() => return,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(_) => continue,
+                    },
+                };
+                let token = token.clone();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = token.cancelled() => (),
+                        // errors end the connection, like a closed connection
+                        _ = echo(stream) => (),
+                    }
+                });
+            }
+        });
+        Self {
+            addr,
+            shutdown_token,
+        }
+    }
+
+    /// Returns the `ws` URL of the server.
+    pub fn url(&self) -> String {
+        format!("ws://{}/", self.addr)
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.shutdown_token.cancel();
+    }
+}
+
+/// Reads a handshake request from the stream, returning the lines of the request
+/// (without their line endings) and the `Sec-WebSocket-Accept` value to respond with.
+/// No lines are returned if the stream was closed before a request was received.
+pub(crate) async fn read_handshake<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+) -> Result<(Vec<String>, String), IoError> {
+    // https://tools.ietf.org/html/rfc6455#section-4.2
+    let mut lines = Vec::new();
+    let mut key = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Ok((Vec::new(), String::new()));
+        }
+        if line == "\r\n" {
+            break;
+        }
+        let line = line.trim_end_matches("\r\n").to_string();
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = value.trim().to_string();
+            }
+        }
+        lines.push(line);
+    }
+    key.push_str(GUUID);
+    let accept = base64::encode(Sha1::digest(key.as_bytes()));
+    Ok((lines, accept))
+}
+
+/// Reads a handshake request from the stream and accepts it, sending the extra headers
+/// (each ending with `\r\n`) in the response, for test servers of all kinds.
+///
+/// Returns the lines of the request (without their line endings),
+/// or no lines if the stream was closed before a request was received.
+pub(crate) async fn accept_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    extra_headers: &str,
+) -> Result<Vec<String>, IoError> {
+    let (lines, accept) = read_handshake(stream).await?;
+    if lines.is_empty() {
+        return Ok(lines);
+    }
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+        Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n{}\r\n",
+        accept, extra_headers
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(lines)
+}
+
+/// Accepts one connection on a local port, completes the handshake, and sends the frames
+/// (encoded as bytes), then ignores everything the client sends.
+/// Returns the `ws` URL of the server.
+#[cfg(test)]
+pub(crate) async fn scripted_server(frames: &'static [u8]) -> String {
+    scripted_server_with(vec![(String::new(), frames)]).await.0
+}
+
+/// Like [`scripted_server()`], but closes its side of the connection after sending the frames.
+#[cfg(test)]
+pub(crate) async fn closing_server(frames: &'static [u8]) -> String {
+    spawn_scripted_server(vec![(String::new(), frames)], true)
+        .await
+        .0
+}
+
+/// Accepts a connection on a local port for each response, completing its handshake
+/// with the extra headers of the response (each ending with `\r\n`) and sending the frames
+/// of the response, then ignores everything the client sends. Returns the `ws` URL of
+/// the server and a receiver of the lines of each handshake request.
+#[cfg(test)]
+pub(crate) async fn scripted_server_with(
+    responses: Vec<(String, &'static [u8])>,
+) -> (String, tokio::sync::mpsc::UnboundedReceiver<Vec<String>>) {
+    spawn_scripted_server(responses, false).await
+}
+
+#[cfg(test)]
+async fn spawn_scripted_server(
+    responses: Vec<(String, &'static [u8])>,
+    close: bool,
+) -> (String, tokio::sync::mpsc::UnboundedReceiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    let (request_sender, request_receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for (extra_headers, frames) in responses {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let request = accept_handshake(&mut stream, &extra_headers).await.unwrap();
+            let _ = request_sender.send(request);
+            stream.write_all(frames).await.unwrap();
+            if close {
+                stream.get_mut().shutdown().await.unwrap();
+            }
+            tokio::spawn(async move {
+                let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await;
+            });
+        }
+    });
+    (url, request_receiver)
+}
+
+/// Accepts one connection on a local port and completes the handshake, then records
+/// the opcodes of the frames the client sends until it closes the connection.
+/// Returns the `ws` URL of the server and the task which returns the opcodes.
+#[cfg(test)]
+pub(crate) async fn recording_server() -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    let opcodes = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        accept_handshake(&mut stream, "").await.unwrap();
+        let mut bytes = Vec::new();
+        stream.read_to_end(&mut bytes).await.unwrap();
+        let mut opcodes = Vec::new();
+        // masked frames with payloads of up to 125 bytes
+        while !bytes.is_empty() {
+            opcodes.push(bytes[0] & 0b00001111);
+            let payload_len = (bytes[1] & 0b01111111) as usize;
+            bytes.drain(..2 + 4 + payload_len);
+        }
+        opcodes
+    });
+    (url, opcodes)
+}
+
+/// Completes the handshake, then echoes frames until a Close frame is received
+async fn echo(stream: TcpStream) -> Result<(), IoError> {
+    let mut stream = BufReader::new(stream);
+    if accept_handshake(&mut stream, "").await?.is_empty() {
+        return Ok(());
+    }
+
+    // https://tools.ietf.org/html/rfc6455#section-5.2
+    loop {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await?;
+        let fin_and_opcode = header[0];
+        let opcode = fin_and_opcode & 0b00001111;
+        let masked = header[1] & 0b10000000 != 0;
+        let payload_len = match header[1] & 0b01111111 {
+            126 => stream.read_u16().await? as u64,
+            127 => stream.read_u64().await?,
+            payload_len => payload_len as u64,
+        };
+        let mut masking_key = [0; 4];
+        if masked {
+            stream.read_exact(&mut masking_key).await?;
+        }
+        let mut payload = vec![0; payload_len as usize];
+        stream.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= masking_key[i % 4];
+        }
+
+        let reply_fin_and_opcode = match opcode {
+            // continuation, Text, Binary, and Close frames are echoed
+            0x0 | 0x1 | 0x2 | 0x8 => fin_and_opcode,
+            // Ping frames are answered with Pong frames
+            0x9 => 0b10000000 | 0xA,
+            // Pong frames (and unknown opcodes) are ignored
+            _ => continue,
+        };
+        let mut reply = vec![reply_fin_and_opcode];
+        match payload.len() {
+            0..=125 => reply.push(payload.len() as u8),
+            126..=0xFFFF => {
+                reply.push(126);
+                reply.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            }
+            _ => {
+                reply.push(127);
+                reply.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+            }
+        }
+        reply.append(&mut payload);
+        stream.write_all(&reply).await?;
+        if opcode == 0x8 {
+            return stream.shutdown().await;
+        }
+    }
+}
+
+/// A test double for a WebSocket connection, which implements [`WebSocketConnection`]
+/// without any I/O. Frames (and errors) to be received are scripted in advance,
+/// and sent frames are recorded so that tests can make assertions about them.
+///
+/// Received frames are not handled as they are by [`WebSocket::receive()`](crate::WebSocket::receive())
+/// (for example, Ping frames are not answered with Pong frames). Once the script
+/// has been exhausted, or after [`close()`](WebSocketConnection::close()) has been
+/// called, receiving (and sending) fails with
+/// [`WebSocketClosedError`](WebSocketError::WebSocketClosedError).
+///
+/// ```
+/// # use websockets::test_util::MockWebSocket;
+/// # use websockets::{Frame, WebSocketConnection, WebSocketError};
+/// async fn reply_to_greeting(ws: &mut dyn WebSocketConnection) -> Result<(), WebSocketError> {
+///     let greeting = ws.receive().await?;
+///     if greeting.as_text().map_or(false, |(text, _, _)| text == "hello") {
+///         ws.send_text("hi".to_string()).await?;
+///     }
+///     ws.close(None).await
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut mock = MockWebSocket::new();
+/// mock.receive_frame(Frame::text("hello".to_string()));
+/// reply_to_greeting(&mut mock).await.unwrap();
+/// mock.assert_sent(&[Frame::text("hi".to_string()), Frame::close(None)]);
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MockWebSocket {
+    received: VecDeque<Result<Frame, WebSocketError>>,
+    send_errors: VecDeque<WebSocketError>,
+    sent: Vec<Frame>,
+    closed: bool,
+}
+
+impl MockWebSocket {
+    /// Creates a mock connection with nothing to receive.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a frame to the end of the script of received frames.
+    pub fn receive_frame(&mut self, frame: Frame) -> &mut Self {
+        self.received.push_back(Ok(frame));
+        self
+    }
+
+    /// Adds an error to the end of the script of received frames, which is returned
+    /// from [`receive()`](WebSocketConnection::receive()) in place of a frame.
+    pub fn receive_error(&mut self, error: WebSocketError) -> &mut Self {
+        self.received.push_back(Err(error));
+        self
+    }
+
+    /// Makes a future send fail with the error instead of sending the frame.
+    /// Errors are returned in the order they were added, one per send.
+    pub fn fail_send(&mut self, error: WebSocketError) -> &mut Self {
+        self.send_errors.push_back(error);
+        self
+    }
+
+    /// Returns the frames sent so far, including the Close frame sent
+    /// by [`close()`](WebSocketConnection::close()).
+    pub fn sent(&self) -> &[Frame] {
+        &self.sent
+    }
+
+    /// Returns whether [`close()`](WebSocketConnection::close()) has been called.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns whether every scripted frame and error has been received.
+    pub fn is_exhausted(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// Asserts that exactly the expected frames have been sent, in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics (listing both sets of frames) if the sent frames are different.
+    #[track_caller]
+    pub fn assert_sent(&self, expected: &[Frame]) {
+        assert!(
+            self.sent == expected,
+            "sent frames do not match\n  sent: {:?}\n  expected: {:?}",
+            self.sent,
+            expected
+        );
+    }
+}
+
+impl WebSocketConnection for MockWebSocket {
+    fn send(&mut self, frame: Frame) -> ConnectionFuture<'_, SendReceipt> {
+        Box::pin(async move {
+            if self.closed {
+                return Err(WebSocketError::WebSocketClosedError);
+            }
+            if let Some(error) = self.send_errors.pop_front() {
+                return Err(error);
+            }
+            // the receipt of a masked frame, as it would be sent by a client
+            let receipt = SendReceipt {
+                frame_bytes: frame.to_bytes(Some([0; 4]))?.len(),
+                payload_bytes: frame.payload()?.len(),
+            };
+            self.sent.push(frame);
+            Ok(receipt)
+        })
+    }
+
+    fn receive(&mut self) -> ConnectionFuture<'_, Frame> {
+        Box::pin(async move {
+            if self.closed {
+                return Err(WebSocketError::WebSocketClosedError);
+            }
+            self.received
+                .pop_front()
+                .unwrap_or(Err(WebSocketError::WebSocketClosedError))
+        })
+    }
+
+    fn close(&mut self, payload: Option<(u16, String)>) -> ConnectionFuture<'_, ()> {
+        Box::pin(async move {
+            self.send(Frame::close(payload)).await?;
+            self.closed = true;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_replays_script_and_records_sent_frames() {
+        let mut mock = MockWebSocket::new();
+        mock.receive_frame(Frame::text("a".to_string()))
+            .receive_error(WebSocketError::ReadStallTimeoutError)
+            .fail_send(WebSocketError::WebSocketClosedError);
+
+        let ws: &mut dyn WebSocketConnection = &mut mock;
+        assert_eq!(ws.receive().await.unwrap(), Frame::text("a".to_string()));
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::ReadStallTimeoutError)
+        ));
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::WebSocketClosedError)
+        ));
+        assert!(ws.send_text("lost".to_string()).await.is_err());
+        let receipt = ws.send_text("b".to_string()).await.unwrap();
+        assert_eq!(receipt.payload_bytes, 1);
+        assert_eq!(receipt.frame_bytes, 7);
+        ws.close(Some((1000, String::new()))).await.unwrap();
+        assert!(ws.send_text("c".to_string()).await.is_err());
+
+        assert!(mock.is_closed());
+        assert!(mock.is_exhausted());
+        mock.assert_sent(&[
+            Frame::text("b".to_string()),
+            Frame::close(Some((1000, String::new()))),
+        ]);
+    }
+}
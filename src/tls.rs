@@ -0,0 +1,7 @@
+//! Every TLS type that appears in the public API of this library, so that secure
+//! connections can be configured without depending on `native-tls` directly
+//! (which could otherwise lead to mismatched versions).
+
+pub use native_tls::Error as TlsError;
+
+pub use crate::secure::{TlsCertificate, TlsIdentity, TlsProtocol};
@@ -0,0 +1,89 @@
+use std::convert::TryFrom;
+
+use bytes::{Buf, Bytes};
+
+#[allow(unused_imports)] // for intra doc links
+use super::{split::WebSocketWriteHalf, WebSocket};
+use crate::error::WebSocketError;
+
+/// The number of bytes in the length prefix of each message in a batch
+const LEN_PREFIX_BYTES: usize = 4;
+
+/// Packs several application messages into the payload of a single Binary frame,
+/// to reduce the overhead of sending many small messages separately.
+/// This is how [`WebSocket::send_batched()`] and [`WebSocketWriteHalf::send_batched()`]
+/// encode batches, and [`decode_batch()`] unpacks them.
+///
+/// Each message is encoded as its length (as a 32-bit big-endian integer) followed by
+/// its bytes. This layout is not part of the WebSocket protocol, so the server must
+/// expect it.
+///
+/// Fails with [`PayloadTooLargeError`](WebSocketError::PayloadTooLargeError) if a message
+/// is longer than `u32::MAX` bytes.
+///
+/// ```
+/// # use websockets::{decode_batch, encode_batch};
+/// let payload = encode_batch(&["hello", "world"]).unwrap();
+/// assert_eq!(&payload[..9], b"\0\0\0\x05hello");
+/// assert_eq!(decode_batch(payload.into()).unwrap(), vec!["hello", "world"]);
+/// ```
+pub fn encode_batch<I>(messages: I) -> Result<Vec<u8>, WebSocketError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let mut payload = Vec::new();
+    for message in messages {
+        let message = message.as_ref();
+        let len =
+            u32::try_from(message.len()).map_err(|_e| WebSocketError::PayloadTooLargeError)?;
+        payload.extend_from_slice(&len.to_be_bytes());
+        payload.extend_from_slice(message);
+    }
+    Ok(payload)
+}
+
+/// Unpacks the messages of a batch encoded with [`encode_batch()`].
+/// The messages share the memory of `payload`, so they are not copied.
+///
+/// Fails with [`InvalidBatchError`](WebSocketError::InvalidBatchError) if the payload
+/// ends in the middle of a message or of its length.
+pub fn decode_batch(mut payload: Bytes) -> Result<Vec<Bytes>, WebSocketError> {
+    let mut messages = Vec::new();
+    while payload.has_remaining() {
+        if payload.len() < LEN_PREFIX_BYTES {
+            return Err(WebSocketError::InvalidBatchError);
+        }
+        let len = payload.get_u32() as usize;
+        if payload.len() < len {
+            return Err(WebSocketError::InvalidBatchError);
+        }
+        messages.push(payload.split_to(len));
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+
+    #[tokio::test]
+    async fn batches_are_sent_in_one_frame() {
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
+        let receipt = ws.send_batched(&["a", "", "bc"]).await.unwrap();
+        assert_eq!(receipt.payload_bytes, 3 * LEN_PREFIX_BYTES + 3);
+        assert_eq!(
+            ws.receive_batched().await.unwrap().unwrap(),
+            vec!["a", "", "bc"]
+        );
+
+        // a batch which ends in the middle of a message
+        ws.send_binary(b"\0\0\0\x05abc".to_vec()).await.unwrap();
+        assert!(matches!(
+            ws.receive_batched().await,
+            Err(WebSocketError::InvalidBatchError)
+        ));
+    }
+}
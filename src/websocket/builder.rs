@@ -1,28 +1,82 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::{poll_fn, Future};
+#[cfg(feature = "tls")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
 
+#[cfg(feature = "hickory-dns")]
+use hickory_resolver::TokioAsyncResolver;
+#[cfg(feature = "tls")]
 use native_tls::{
     TlsConnector as NativeTlsTlsConnector, TlsConnectorBuilder as NativeTlsTlsConnectorBuilder,
 };
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
-use tokio::io::{self, BufReader, BufWriter};
+use tokio::io::{self, AsyncWrite, BufReader, BufWriter};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::{watch, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
-use super::handshake::Handshake;
+use super::connect_limiter::{ConnectLimiter, ConnectPermit};
+use super::connection_info::{ConnectionInfo, OnReconnected, OnReconnectedFuture};
+use super::connection_state::{ConnectionState, StateTracker};
+use super::dedup::DuplicateFilter;
+#[cfg(feature = "hickory-dns")]
+use super::dns::{DnsResolver, OnSrvTarget, SrvTarget};
+use super::driver::{AppHeartbeat, Keepalive};
+use super::entropy::{EntropyConfig, EntropySource};
+#[cfg(any(test, feature = "test-util"))]
+use super::fault::{FaultInjector, FaultyStream};
+use super::frame::{Frame, OpcodeSet};
+use super::handshake::{self, Handshake, HandshakeLimits};
+use super::handshake_request::{BeforeHandshake, BeforeHandshakeFuture, HandshakeRequest};
+#[cfg(feature = "log")]
+use super::logging::LogVerbosity;
+use super::logging::Logger;
 use super::parsed_addr::ParsedAddr;
-use super::split::{WebSocketReadHalf, WebSocketWriteHalf};
+use super::recording::SessionRecorder;
+use super::reuse::ConnectionReuseToken;
+use super::split::{
+    Activity, ClosePolicy, EventQueuePolicy, PauseHandle, PingFloodPolicy, PingLimiter,
+    WebSocketReadHalf, WebSocketWriteHalf, Writer,
+};
+use super::stats::{ConnectTimings, DropListener, DropReason};
 use super::stream::Stream;
+#[cfg(any(test, feature = "test-util"))]
+use super::throttle::{Throttle, ThrottledStream};
+use super::transport::{Preamble, PreambleFuture, Transport, TransportStream};
+use super::wire_trace::{TracedStream, WireTrace};
 use super::FrameType;
 use super::WebSocket;
 use crate::error::WebSocketError;
+#[cfg(feature = "tls")]
 use crate::secure::{TlsCertificate, TlsIdentity, TlsProtocol};
+#[allow(unused_imports)] // for intra doc links
+use crate::ConnectionDriver;
+
+/// TLS 1.0 and 1.1 are deprecated (https://tools.ietf.org/html/rfc8996),
+/// and FIPS 140 requires TLS 1.2 or newer (NIST SP 800-52r2)
+#[cfg(feature = "tls")]
+const DEFAULT_TLS_MIN_PROTOCOL_VERSION: TlsProtocol = TlsProtocol::Tlsv12;
+
+pub(super) const DEFAULT_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+pub(super) const DEFAULT_MAX_HANDSHAKE_HEADERS: usize = 100;
+pub(super) const DEFAULT_MAX_HANDSHAKE_HEADER_BYTES: usize = 64 * 1024;
+pub(super) const DEFAULT_MAX_QUEUED_EVENTS: usize = 64;
 
 /// A builder used to customize the WebSocket handshake.
 ///
 /// Handshake headers as well as subprotocols can be added and removed.
 /// Methods prefixed with `tls_` allow for the customization of a secure
-/// WebSocket connection.
+/// WebSocket connection (these are only available with the `tls` feature,
+/// which is enabled by default).
 ///
 /// ```
 /// # use websockets::{WebSocket, WebSocketError};
@@ -38,7 +92,48 @@ use crate::secure::{TlsCertificate, TlsIdentity, TlsProtocol};
 pub struct WebSocketBuilder {
     additional_handshake_headers: Vec<(String, String)>,
     subprotocols: Vec<String>,
-    tls_connector_builder: NativeTlsTlsConnectorBuilder,
+    extensions_offer: Option<String>,
+    #[cfg(feature = "tls")]
+    tls: TlsSettings,
+    #[cfg(feature = "hickory-dns")]
+    dns_resolver: Option<TokioAsyncResolver>,
+    #[cfg(feature = "hickory-dns")]
+    dns_srv_lookup: bool,
+    #[cfg(feature = "hickory-dns")]
+    on_srv_target: Option<OnSrvTarget>,
+    keepalive_interval: Option<Duration>,
+    keepalive_timeout: Duration,
+    app_heartbeat: Option<AppHeartbeat>,
+    read_stall_timeout: Option<Duration>,
+    strict_mode: bool,
+    raw_close_reasons: bool,
+    pub(super) shutdown_token: Option<CancellationToken>,
+    pub(super) logger: Logger,
+    debug_handshake: bool,
+    handshake_limits: HandshakeLimits,
+    entropy: EntropyConfig,
+    disable_masking: bool,
+    transport: Option<Arc<dyn Transport>>,
+    plaintext_preamble: Option<Preamble>,
+    before_handshake: Option<BeforeHandshake>,
+    on_reconnected: Option<OnReconnected>,
+    close_policy: ClosePolicy,
+    tcp_nodelay: bool,
+    recorder: Option<SessionRecorder>,
+    wire_trace: Option<WireTrace>,
+    duplicate_filter: Option<DuplicateFilter>,
+    ping_limiter: PingLimiter,
+    max_queued_events: usize,
+    event_queue_policy: EventQueuePolicy,
+    on_dropped: Option<DropListener>,
+    connect_limiter: Option<ConnectLimiter>,
+    #[cfg(any(test, feature = "test-util"))]
+    fault_injector: Option<FaultInjector>,
+    #[cfg(any(test, feature = "test-util"))]
+    throttle: Option<Throttle>,
+    /// The state of the next connection made with this builder
+    state: StateTracker,
+    reuse_token: Option<ConnectionReuseToken>,
 }
 
 impl Debug for WebSocketBuilder {
@@ -52,7 +147,50 @@ impl WebSocketBuilder {
         Self {
             additional_handshake_headers: Vec::new(),
             subprotocols: Vec::new(),
-            tls_connector_builder: NativeTlsTlsConnector::builder(),
+            extensions_offer: None,
+            #[cfg(feature = "tls")]
+            tls: TlsSettings::new(),
+            #[cfg(feature = "hickory-dns")]
+            dns_resolver: None,
+            #[cfg(feature = "hickory-dns")]
+            dns_srv_lookup: false,
+            #[cfg(feature = "hickory-dns")]
+            on_srv_target: None,
+            keepalive_interval: None,
+            keepalive_timeout: DEFAULT_KEEPALIVE_TIMEOUT,
+            app_heartbeat: None,
+            read_stall_timeout: None,
+            strict_mode: false,
+            raw_close_reasons: false,
+            shutdown_token: None,
+            logger: Logger::default(),
+            debug_handshake: false,
+            handshake_limits: HandshakeLimits {
+                max_headers: DEFAULT_MAX_HANDSHAKE_HEADERS,
+                max_header_bytes: DEFAULT_MAX_HANDSHAKE_HEADER_BYTES,
+            },
+            entropy: EntropyConfig::default(),
+            disable_masking: false,
+            transport: None,
+            plaintext_preamble: None,
+            before_handshake: None,
+            on_reconnected: None,
+            close_policy: ClosePolicy::default(),
+            tcp_nodelay: false,
+            recorder: None,
+            wire_trace: None,
+            duplicate_filter: None,
+            ping_limiter: PingLimiter::default(),
+            max_queued_events: DEFAULT_MAX_QUEUED_EVENTS,
+            event_queue_policy: EventQueuePolicy::default(),
+            on_dropped: None,
+            connect_limiter: None,
+            #[cfg(any(test, feature = "test-util"))]
+            fault_injector: None,
+            #[cfg(any(test, feature = "test-util"))]
+            throttle: None,
+            state: StateTracker::new(),
+            reuse_token: None,
         }
     }
 
@@ -62,61 +200,178 @@ impl WebSocketBuilder {
     /// After calling this method, no more methods should be called on this builder.
     pub async fn connect(&mut self, url: &str) -> Result<WebSocket, WebSocketError> {
         let parsed_addr = ParsedAddr::try_from(url)?;
+        let settings = self.settings(&parsed_addr)?;
+        self.state = StateTracker::new();
+        match self.reuse_token.take() {
+            Some(token) if token.matches_addr(&parsed_addr) => {
+                settings.connect_reused(url, parsed_addr, token).await
+            }
+            _ => settings.connect(url, parsed_addr).await,
+        }
+    }
 
-        let stream = Stream::Plain(
-            TcpStream::connect(parsed_addr.addr)
-                .await
-                .map_err(|e| WebSocketError::TcpConnectionError(e))?,
-        );
-        let stream = match &parsed_addr.scheme[..] {
+    /// Builds a [`WebSocket`] using this builder, then dials each of the URLs concurrently
+    /// and performs the WebSocket handshake with the first endpoint to accept the connection
+    /// (including the TLS handshake for `wss` URLs). The other connection attempts are aborted.
+    ///
+    /// This is useful for latency-sensitive clients which may connect to any of a pool
+    /// of equivalent endpoints. If every connection attempt fails, the error of the
+    /// last attempt to fail is returned; if the handshake with the first endpoint
+    /// fails, its error is returned without trying the other endpoints.
+    /// If no URLs are given, [`NoEndpointsError`](WebSocketError::NoEndpointsError) is returned.
+    ///
+    /// ```no_run
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .connect_race(&[
+    ///         "wss://us-east.example.com/feed",
+    ///         "wss://us-west.example.com/feed",
+    ///     ])
+    ///     .await?;
+    /// println!("connected to {}", ws.connection_info().as_ref().unwrap().url());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// After calling this method, no more methods should be called on this builder.
+    pub async fn connect_race(&mut self, urls: &[&str]) -> Result<WebSocket, WebSocketError> {
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            let parsed_addr = ParsedAddr::try_from(*url)?;
+            let settings = self.settings(&parsed_addr)?;
+            endpoints.push((*url, parsed_addr, settings));
+        }
+        let state = std::mem::replace(&mut self.state, StateTracker::new());
+        let dials = endpoints
+            .iter()
+            .enumerate()
+            .map(|(i, (_url, parsed_addr, settings))| {
+                let dial: DialFuture = Box::pin(async move {
+                    let permit = settings.connect_permit().await;
+                    match settings.dial(parsed_addr).await {
+                        Ok(dialed) => (i, Ok((dialed, permit))),
+                        Err(e) => {
+                            let result = Err(e);
+                            if let Some(permit) = permit {
+                                permit.finish(&result);
+                            }
+                            (i, result)
+                        }
+                    }
+                });
+                dial
+            })
+            .collect();
+        let (i, (dialed, permit)) = match first_dialed(dials).await {
+            Ok(dialed) => dialed,
+            Err(e) => {
+                state.set(ConnectionState::Closed);
+                return Err(e);
+            }
+        };
+        let (url, parsed_addr, settings) = &endpoints[i];
+        let result = settings.handshake(url, parsed_addr, dialed).await;
+        if let Some(permit) = permit {
+            permit.finish(&result);
+        }
+        if result.is_err() {
+            state.set(ConnectionState::Closed);
+        }
+        result
+    }
+
+    /// Validates the settings of this builder and copies them for connecting to an address.
+    fn settings(&mut self, parsed_addr: &ParsedAddr) -> Result<ConnectSettings, WebSocketError> {
+        for (header_name, header_value) in &self.additional_handshake_headers {
+            handshake::validate_header(header_name, header_value)?;
+        }
+        for subprotocol in &self.subprotocols {
+            handshake::validate_subprotocol(subprotocol)?;
+        }
+        if let Some(extensions_offer) = &self.extensions_offer {
+            handshake::validate_header("Sec-WebSocket-Extensions", extensions_offer)?;
+        }
+        #[cfg(feature = "tls")]
+        let tls_connector = match &parsed_addr.scheme[..] {
             // https://tools.ietf.org/html/rfc6455#section-11.1.1
-            "ws" => stream,
+            "ws" => None,
+            #[cfg(unix)]
+            "ws+unix" => None,
             // https://tools.ietf.org/html/rfc6455#section-11.1.2
-            "wss" => {
-                let tls_config = self
-                    .tls_connector_builder
-                    .build()
-                    .map_err(|e| WebSocketError::TlsBuilderError(e))?;
-                stream.into_tls(&parsed_addr.host, tls_config).await?
-            }
+            "wss" => Some(self.tls.build_connector(parsed_addr)?),
             _ => return Err(WebSocketError::SchemeError),
         };
-        let (read_half, write_half) = io::split(stream);
-        let (sender, receiver) = flume::unbounded();
-        let mut ws = WebSocket {
-            read_half: WebSocketReadHalf {
-                stream: BufReader::new(read_half),
-                last_frame_type: FrameType::default(),
-                sender,
-            },
-            write_half: WebSocketWriteHalf {
-                shutdown: false,
-                sent_closed: false,
-                stream: BufWriter::new(write_half),
-                rng: ChaCha20Rng::from_entropy(),
-                receiver,
-            },
-            accepted_subprotocol: None,
-            handshake_response_headers: None,
+        #[cfg(not(feature = "tls"))]
+        match &parsed_addr.scheme[..] {
+            "ws" => (),
+            #[cfg(unix)]
+            "ws+unix" => (),
+            "wss" => return Err(WebSocketError::TlsNotEnabledError),
+            _ => return Err(WebSocketError::SchemeError),
+        };
+        #[cfg(feature = "hickory-dns")]
+        let dns_resolver = match (&self.dns_resolver, self.dns_srv_lookup) {
+            (None, false) => None,
+            (resolver, srv_lookup) => Some(DnsResolver::new(
+                resolver.clone(),
+                srv_lookup,
+                self.on_srv_target.clone(),
+            )?),
         };
 
-        // perform opening handshake
-        let handshake = Handshake::new(
-            &parsed_addr,
-            &self.additional_handshake_headers,
-            &self.subprotocols,
-        );
-        handshake.send_request(&mut ws).await?;
-        match handshake.check_response(&mut ws).await {
-            Ok(_) => Ok(ws),
-            Err(e) => {
-                ws.shutdown().await?;
-                Err(e)
-            }
-        }
+        Ok(ConnectSettings {
+            additional_handshake_headers: self.additional_handshake_headers.clone(),
+            subprotocols: self.subprotocols.clone(),
+            extensions_offer: self.extensions_offer.clone(),
+            #[cfg(feature = "tls")]
+            tls_connector,
+            #[cfg(feature = "tls")]
+            tls_server_name: self.tls.server_name.clone(),
+            #[cfg(feature = "hickory-dns")]
+            dns_resolver,
+            keepalive: self.keepalive_interval.map(|interval| Keepalive {
+                interval,
+                timeout: self.keepalive_timeout,
+            }),
+            app_heartbeat: self.app_heartbeat.clone(),
+            read_stall_timeout: self.read_stall_timeout,
+            strict_mode: self.strict_mode,
+            raw_close_reasons: self.raw_close_reasons,
+            shutdown_token: self.shutdown_token.clone(),
+            logger: self.logger,
+            debug_handshake: self.debug_handshake,
+            handshake_limits: self.handshake_limits,
+            entropy: self.entropy.clone(),
+            disable_masking: self.disable_masking,
+            transport: self.transport.clone(),
+            plaintext_preamble: self.plaintext_preamble.clone(),
+            before_handshake: self.before_handshake.clone(),
+            on_reconnected: self.on_reconnected.clone(),
+            close_policy: self.close_policy,
+            tcp_nodelay: self.tcp_nodelay,
+            recorder: self.recorder.clone(),
+            wire_trace: self.wire_trace.clone(),
+            duplicate_filter: self.duplicate_filter.clone(),
+            ping_limiter: self.ping_limiter.clone(),
+            max_queued_events: self.max_queued_events,
+            event_queue_policy: self.event_queue_policy,
+            on_dropped: self.on_dropped.clone(),
+            connect_limiter: self.connect_limiter.clone(),
+            #[cfg(any(test, feature = "test-util"))]
+            fault_injector: self.fault_injector.clone(),
+            #[cfg(any(test, feature = "test-util"))]
+            throttle: self.throttle,
+            state: self.state.clone(),
+        })
     }
 
     /// Adds a header to be sent in the WebSocket handshake.
+    ///
+    /// The header name must be a valid HTTP token and the header value must not contain
+    /// control characters such as CR or LF; otherwise, [`connect()`](WebSocketBuilder::connect())
+    /// will fail with [`InvalidHeaderError`](WebSocketError::InvalidHeaderError).
     pub fn add_header(&mut self, header_name: &str, header_value: &str) -> &mut Self {
         // https://tools.ietf.org/html/rfc6455#section-4.2.2
         self.additional_handshake_headers
@@ -125,10 +380,11 @@ impl WebSocketBuilder {
     }
 
     /// Removes a header which would be sent in the WebSocket handshake.
+    /// Header names are case-insensitive.
     pub fn remove_header(&mut self, header_name: &str) -> &mut Self {
         // https://tools.ietf.org/html/rfc6455#section-4.2.2
         self.additional_handshake_headers
-            .retain(|header| header.0 != header_name);
+            .retain(|header| !header.0.eq_ignore_ascii_case(header_name));
         self
     }
 
@@ -150,9 +406,597 @@ impl WebSocketBuilder {
         self
     }
 
+    /// Sets the raw value of the `Sec-WebSocket-Extensions` header to be sent in the
+    /// WebSocket handshake, for applications implementing their own extensions
+    /// (see [https://tools.ietf.org/html/rfc6455#section-9](https://tools.ietf.org/html/rfc6455#section-9)).
+    /// Whatever extensions and parameters the server accepts can be found using the
+    /// [`WebSocket::accepted_extensions()`] method.
+    ///
+    /// Extensions are not interpreted by this library: RSV bits are ignored on received
+    /// frames and never set on sent frames, and payloads are sent and received as they are,
+    /// so the application is responsible for anything the negotiated extensions require.
+    /// A value of None offers no extensions, in which case a server accepting any
+    /// extensions fails the handshake.
+    /// Defaults to None.
+    pub fn extensions_offer(&mut self, offer: Option<&str>) -> &mut Self {
+        self.extensions_offer = offer.map(str::to_string);
+        self
+    }
+
+    /// Sets the interval at which Ping frames are sent to keep the connection alive.
+    /// Keepalive Pings are sent by the [`ConnectionDriver`] obtained from
+    /// [`WebSocket::split_with_driver()`].
+    /// A value of None disables keepalive Pings.
+    /// Defaults to None.
+    pub fn keepalive_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.keepalive_interval = interval;
+        self
+    }
+
+    /// Sets how long to wait for a Pong frame in response to a keepalive Ping frame
+    /// before the connection is considered dead.
+    /// Defaults to 20 seconds.
+    pub fn keepalive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.keepalive_timeout = timeout;
+        self
+    }
+
+    /// Sets an application-level heartbeat: every `interval`, a frame constructed by
+    /// `frame_factory` is sent. Some protocols require a heartbeat message (such as
+    /// a JSON Text frame) rather than a Ping frame. Like keepalive Pings, heartbeats are
+    /// sent by the [`ConnectionDriver`] obtained from [`WebSocket::split_with_driver()`].
+    /// Defaults to no heartbeat.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use websockets::{Frame, WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let ws = WebSocket::builder()
+    ///     .app_heartbeat(Duration::from_secs(40), || {
+    ///         Frame::text(r#"{"op":1,"d":null}"#.to_string())
+    ///     })
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// let (mut read_half, mut write_half, driver) = ws.split_with_driver();
+    /// tokio::spawn(driver);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn app_heartbeat<F>(&mut self, interval: Duration, frame_factory: F) -> &mut Self
+    where
+        F: Fn() -> Frame + Send + Sync + 'static,
+    {
+        self.app_heartbeat = Some(AppHeartbeat {
+            interval,
+            frame_factory: Arc::new(frame_factory),
+        });
+        self
+    }
+
+    /// Sets how long to wait for more data while in the middle of receiving a frame.
+    /// If no data arrives for this long, receiving fails with
+    /// [`ReadStallTimeoutError`](WebSocketError::ReadStallTimeoutError),
+    /// which protects against servers trickling frames in one byte at a time.
+    /// Waiting for the first byte of a frame is not affected.
+    /// A value of None disables the timeout.
+    /// Defaults to None.
+    pub fn read_stall_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.read_stall_timeout = timeout;
+        self
+    }
+
+    /// Sets whether received frames are checked strictly against
+    /// [RFC 6455](https://tools.ietf.org/html/rfc6455#section-5.2), as conformance test suites
+    /// such as Autobahn expect. In strict mode, receiving fails with
+    /// [`NonMinimalLengthError`](WebSocketError::NonMinimalLengthError) if a frame's payload
+    /// length is not encoded in the fewest bytes possible, and with
+    /// [`InvalidFrameError`](WebSocketError::InvalidFrameError) if the most significant bit
+    /// of a 64-bit payload length is set.
+    /// Defaults to false, for compatibility with lenient servers.
+    pub fn strict_mode(&mut self, strict_mode: bool) -> &mut Self {
+        self.strict_mode = strict_mode;
+        self
+    }
+
+    /// Sets whether received Close frames whose reason is not valid UTF-8 are returned with
+    /// the reason as a [raw reason](Frame::close_raw()) (see [`Frame::close_reason_bytes()`]),
+    /// for servers which send opaque data after the status code. Otherwise, receiving
+    /// them fails with [`InvalidFrameError`](WebSocketError::InvalidFrameError),
+    /// as [RFC 6455](https://tools.ietf.org/html/rfc6455#section-5.5.1) requires.
+    /// Defaults to false.
+    pub fn raw_close_reasons(&mut self, raw_close_reasons: bool) -> &mut Self {
+        self.raw_close_reasons = raw_close_reasons;
+        self
+    }
+
+    /// Sets a token which shuts down the WebSocket connection when cancelled.
+    ///
+    /// Once the token is cancelled, pending and future receives fail with
+    /// [`CancelledError`](WebSocketError::CancelledError).
+    /// [`WebSocket::receive()`] also performs the closing handshake, as does the
+    /// [`ConnectionDriver`] if the WebSocket was split with [`WebSocket::split_with_driver()`].
+    /// A single token can be shared by every connection in an application
+    /// to shut them all down at once.
+    pub fn shutdown_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.shutdown_token = Some(token);
+        self
+    }
+
+    /// Sets how much the WebSocket logs through the [`log`](https://docs.rs/log) crate.
+    /// Defaults to [`LogVerbosity::Warn`].
+    ///
+    /// This method is only available with the `log` feature.
+    #[cfg(feature = "log")]
+    pub fn log_verbosity(&mut self, verbosity: LogVerbosity) -> &mut Self {
+        self.logger.verbosity = verbosity;
+        self
+    }
+
+    /// Controls whether the raw handshake response is captured for debugging.
+    /// If enabled and the handshake fails, the error is wrapped in a
+    /// [`HandshakeDebugError`](WebSocketError::HandshakeDebugError) containing
+    /// the bytes received from the server (the status line, headers, and any part of
+    /// the body that was received), which helps to diagnose unusual servers.
+    /// Defaults to false.
+    pub fn debug_handshake(&mut self, debug_handshake: bool) -> &mut Self {
+        self.debug_handshake = debug_handshake;
+        self
+    }
+
+    /// Sets the maximum number of headers allowed in the server's handshake response.
+    /// If the response has more headers, connecting fails with
+    /// [`HandshakeTooLargeError`](WebSocketError::HandshakeTooLargeError).
+    /// Defaults to 100.
+    pub fn max_handshake_headers(&mut self, max_headers: usize) -> &mut Self {
+        self.handshake_limits.max_headers = max_headers;
+        self
+    }
+
+    /// Sets the maximum number of bytes allowed in the status line and headers of the
+    /// server's handshake response (including any
+    /// [interim responses](WebSocket::interim_responses()) before it).
+    /// If the response is larger, connecting fails with
+    /// [`HandshakeTooLargeError`](WebSocketError::HandshakeTooLargeError).
+    /// This prevents a server from using unbounded memory by sending endless headers.
+    /// Defaults to 64 KiB.
+    pub fn max_handshake_header_bytes(&mut self, max_header_bytes: usize) -> &mut Self {
+        self.handshake_limits.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    /// Sets where the random bytes for the `Sec-WebSocket-Key` handshake header
+    /// and for masking keys come from (see [`EntropyConfig`]).
+    /// Defaults to [`EntropyConfig::Seeded`].
+    pub fn entropy(&mut self, entropy: EntropyConfig) -> &mut Self {
+        self.entropy = entropy;
+        self
+    }
+
+    /// Controls whether the payloads of sent frames are left unmasked.
+    ///
+    /// **This violates the WebSocket protocol**, which requires every frame sent by a client
+    /// to be masked (see [RFC 6455 section 5.3](https://tools.ietf.org/html/rfc6455#section-5.3)),
+    /// and compliant servers close the connection when they receive an unmasked frame.
+    /// Masking protects intermediaries such as proxies from cache poisoning attacks,
+    /// so it should only be disabled for trusted local connections (such as IPC over
+    /// `ws://127.0.0.1` between processes you control) to a server which is known to accept
+    /// unmasked frames, where masking costs CPU time for no benefit.
+    /// Defaults to false.
+    pub fn danger_disable_masking(&mut self, disable_masking: bool) -> &mut Self {
+        self.disable_masking = disable_masking;
+        self
+    }
+
+    /// Controls the use of `TCP_NODELAY`, which disables Nagle's algorithm.
+    ///
+    /// Every frame is already written and flushed as soon as it is sent, but with
+    /// Nagle's algorithm the operating system may hold back a small frame until the
+    /// previous one has been acknowledged. For example, sending two small frames and
+    /// then waiting for a reply takes about 40 ms on Linux (even over loopback) with
+    /// Nagle's algorithm, compared to well under a millisecond without it.
+    /// Enabling this sends small frames immediately, which matters for latency-critical
+    /// workloads such as trading, at the cost of more packets on the network.
+    /// This has no effect when a custom [`Transport`] is used.
+    /// Defaults to false.
+    pub fn tcp_nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Sets what the WebSocket does when it receives a Close frame from the server
+    /// (see [`ClosePolicy`]). Defaults to [`ClosePolicy::ImmediateShutdown`].
+    pub fn close_policy(&mut self, close_policy: ClosePolicy) -> &mut Self {
+        self.close_policy = close_policy;
+        self
+    }
+
+    /// Sets the most Ping frames responded to with Pong frames in a second, so that a
+    /// misbehaving server flooding Pings cannot make the client spend its CPU time and
+    /// bandwidth on Pongs. What happens to the excess Pings is decided by the
+    /// [ping flood policy](WebSocketBuilder::ping_flood_policy()).
+    /// A value of None responds to every Ping.
+    /// Defaults to None.
+    pub fn max_pongs_per_second(&mut self, max_pongs: Option<u32>) -> &mut Self {
+        self.ping_limiter.max_per_second = max_pongs;
+        self
+    }
+
+    /// Sets the largest payload of a Ping frame responded to with a Pong frame.
+    /// Larger Pings are handled according to the
+    /// [ping flood policy](WebSocketBuilder::ping_flood_policy()).
+    /// A value of None responds to Pings of any size (up to 125 bytes, the most allowed
+    /// in a control frame).
+    /// Defaults to None.
+    pub fn max_ping_payload(&mut self, max_payload: Option<usize>) -> &mut Self {
+        self.ping_limiter.max_payload = max_payload;
+        self
+    }
+
+    /// Sets what happens to Ping frames exceeding the limits set with
+    /// [`max_pongs_per_second()`](WebSocketBuilder::max_pongs_per_second()) and
+    /// [`max_ping_payload()`](WebSocketBuilder::max_ping_payload()) (see [`PingFloodPolicy`]).
+    /// Defaults to [`PingFloodPolicy::DropExcess`].
+    pub fn ping_flood_policy(&mut self, policy: PingFloodPolicy) -> &mut Self {
+        self.ping_limiter.policy = policy;
+        self
+    }
+
+    /// Sets the most events (such as Pong frames to send in response to Pings) queued
+    /// by the read half for the write half, which acts on them when it is
+    /// [flushed](WebSocketWriteHalf::flush()). This bounds the memory used if the
+    /// server floods the connection with Pings while the write half is not flushed.
+    /// What happens once the queue is full is decided by the
+    /// [event queue policy](WebSocketBuilder::event_queue_policy()).
+    /// Defaults to 64.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_events` is 0.
+    pub fn max_queued_events(&mut self, max_events: usize) -> &mut Self {
+        assert!(max_events > 0, "at least one event must be queued");
+        self.max_queued_events = max_events;
+        self
+    }
+
+    /// Sets how events are queued for the write half, and what happens once
+    /// [`max_queued_events()`](WebSocketBuilder::max_queued_events()) events are queued
+    /// (see [`EventQueuePolicy`]). Defaults to [`EventQueuePolicy::Coalesce`].
+    pub fn event_queue_policy(&mut self, policy: EventQueuePolicy) -> &mut Self {
+        self.event_queue_policy = policy;
+        self
+    }
+
+    /// Sets a callback which is called with each frame that the library drops instead
+    /// of returning it or sending it, and the reason it was dropped (see [`DropReason`]),
+    /// such as frames removed by the [frame filter](WebSocketReadHalf::set_frame_filter())
+    /// or Pongs not sent because the connection was already closed. Drops are also counted
+    /// in [`Stats::dropped()`](crate::Stats::dropped()). The callback is called on the task which dropped the frame,
+    /// so it should return quickly. Defaults to None.
+    ///
+    /// ```no_run
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .on_dropped(|reason, frame| eprintln!("dropped {:?}: {:?}", reason, frame))
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_dropped<F>(&mut self, on_dropped: F) -> &mut Self
+    where
+        F: Fn(DropReason, &Frame) + Send + Sync + 'static,
+    {
+        self.on_dropped = Some(DropListener(Arc::new(on_dropped)));
+        self
+    }
+
+    /// Records every frame sent and received with a [`SessionRecorder`],
+    /// which can later be replayed with a [`SessionReplay`](crate::SessionReplay).
+    /// The recorder is also used when the WebSocket is [redialed](ConnectionInfo::redial()).
+    /// Defaults to no recording.
+    pub fn record_session(&mut self, recorder: SessionRecorder) -> &mut Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Copies the raw bytes sent and received over the connection (including the
+    /// handshake, and after TLS decryption for `wss` URLs) to `writer`, formatted
+    /// as a hex dump in the style of `hexdump -C`. This is useful for debugging
+    /// interoperability problems, such as with proxies that alter the traffic:
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # use tokio::fs::File;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// # let path = std::env::temp_dir().join("trace.txt");
+    /// let trace = File::create(path).await.expect("could not create trace file");
+    /// let mut ws = WebSocket::builder()
+    ///     .wire_trace(trace)
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The trace is written as the connection is used, and waited for whenever the
+    /// connection is flushed, so a slow writer slows down the connection. If writing
+    /// the trace fails, tracing stops but the connection is unaffected.
+    /// The writer is also used when the WebSocket is [redialed](ConnectionInfo::redial()).
+    /// Defaults to no trace.
+    pub fn wire_trace<W>(&mut self, writer: W) -> &mut Self
+    where
+        W: AsyncWrite + Send + 'static,
+    {
+        self.wire_trace = Some(WireTrace::new(writer));
+        self
+    }
+
+    /// Suppresses received messages which repeat one of the last `window` messages, for
+    /// streaming APIs which replay their most recent messages after a reconnect.
+    /// `sequence_id` is called with the first frame of each received Text or Binary message
+    /// and returns its sequence ID (such as a field of its JSON payload), or None if the
+    /// message has no ID and should never be suppressed:
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     // messages look like "42:payload"
+    ///     .suppress_duplicates(100, |frame| {
+    ///         let (text, _continuation, _fin) = frame.as_text()?;
+    ///         text.split(':').next()?.parse().ok()
+    ///     })
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Suppressed messages (including all of their frames) are skipped like frames removed
+    /// by the [frame filter](WebSocketReadHalf::set_frame_filter()). The sequence IDs are
+    /// remembered across connections [redialed](ConnectionInfo::redial()) from this builder's
+    /// settings, so that messages replayed after resuming are suppressed.
+    /// Defaults to no suppression.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is 0.
+    pub fn suppress_duplicates<F>(&mut self, window: usize, sequence_id: F) -> &mut Self
+    where
+        F: Fn(&Frame) -> Option<u64> + Send + Sync + 'static,
+    {
+        assert!(window > 0, "window must be greater than 0");
+        self.duplicate_filter = Some(DuplicateFilter::new(window, Arc::new(sequence_id)));
+        self
+    }
+
+    /// Sets the [`ConnectLimiter`] which limits the number of connection attempts in progress
+    /// at once (including [redials](ConnectionInfo::redial())), and counts their outcomes.
+    /// Attempts over the limit wait until they may start. Defaults to None.
+    pub fn connect_limiter(&mut self, connect_limiter: ConnectLimiter) -> &mut Self {
+        self.connect_limiter = Some(connect_limiter);
+        self
+    }
+
+    /// Sets the [`FaultInjector`] which injects faults into the connection at random,
+    /// for chaos testing (available with the `test-util` feature).
+    /// The injector is also used when the WebSocket is [redialed](ConnectionInfo::redial()).
+    /// Defaults to None.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn fault_injector(&mut self, fault_injector: FaultInjector) -> &mut Self {
+        self.fault_injector = Some(fault_injector);
+        self
+    }
+
+    /// Throttles the connection to reproduce a slow network in tests (available with the
+    /// `test-util` feature): reads and writes are limited to `bytes_per_sec` (if given),
+    /// and each read or write transfers at most a tenth of a second's worth of bytes,
+    /// so frames arrive in parts and writes apply backpressure. Received bytes are
+    /// also delivered `latency` after they arrive. The throttle applies to every byte
+    /// sent and received, including the handshake and TLS records.
+    ///
+    /// Timers are based on `tokio::time`, so tests with paused time run deterministically
+    /// and without waiting in real time. Defaults to no throttle.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use websockets::test_util::EchoServer;
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let server = EchoServer::spawn().await;
+    /// // a 56k modem with a 100 ms round trip
+    /// let mut ws = WebSocket::builder()
+    ///     .throttle(Some(7_000), Duration::from_millis(100))
+    ///     .connect(&server.url())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is 0.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn throttle(&mut self, bytes_per_sec: Option<u64>, latency: Duration) -> &mut Self {
+        assert!(bytes_per_sec != Some(0), "bytes_per_sec must not be 0");
+        self.throttle = Some(Throttle {
+            bytes_per_sec,
+            latency,
+        });
+        self
+    }
+
+    /// Returns a receiver of the [`ConnectionState`] of the next connection made with
+    /// this builder, so that the first connection attempt can also be watched
+    /// (the receiver starts in the [`Connecting`](ConnectionState::Connecting) state).
+    /// The receiver is the same as the one returned by [`WebSocket::connection_state()`]
+    /// once the connection has been made.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Sets a connection kept open by [`WebSocket::close_for_reuse()`], which
+    /// [`connect()`](WebSocketBuilder::connect()) performs the WebSocket handshake on
+    /// instead of opening a new connection (and performing a new TLS handshake).
+    /// The connection is only used if the URL has the same scheme, host, and port
+    /// (see [`ConnectionReuseToken::matches()`]); otherwise it is closed, and a new
+    /// connection is opened. Connections [redialed](ConnectionInfo::redial()) from
+    /// the WebSocket are always new connections.
+    /// Defaults to opening a new connection.
+    pub fn reuse_connection(&mut self, token: ConnectionReuseToken) -> &mut Self {
+        self.reuse_token = Some(token);
+        self
+    }
+
+    /// Sets the [`Transport`] used to connect to the server, instead of TCP.
+    /// For `wss` URLs, TLS is performed over the stream from the transport.
+    /// `ws+unix` URLs are always connected to over their Unix domain socket.
+    /// Defaults to TCP.
+    pub fn transport<T>(&mut self, transport: T) -> &mut Self
+    where
+        T: Transport + 'static,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Sets a function which exchanges bytes on the plain stream after connecting,
+    /// but before the TLS handshake (for `wss` URLs) and the WebSocket handshake.
+    ///
+    /// This allows connecting through gateways which expect a plaintext preamble
+    /// and then upgrade the same connection to TLS, similar to STARTTLS:
+    ///
+    /// ```
+    /// # use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .plaintext_preamble(|stream| {
+    ///         Box::pin(async move {
+    ///             stream.write_all(b"STARTTLS\r\n").await?;
+    ///             // wait for the gateway to acknowledge, one byte at a time
+    ///             // so that nothing after the acknowledgement is consumed
+    ///             let mut reply = Vec::new();
+    ///             while !reply.ends_with(b"\r\n") {
+    ///                 reply.push(stream.read_u8().await?);
+    ///             }
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The preamble must not read past its own bytes, because anything it reads is
+    /// not seen by the TLS or WebSocket handshakes. The preamble is also run when
+    /// the WebSocket is [redialed](ConnectionInfo::redial()).
+    /// Defaults to no preamble.
+    pub fn plaintext_preamble<F>(&mut self, preamble: F) -> &mut Self
+    where
+        F: for<'a> Fn(&'a mut dyn TransportStream) -> PreambleFuture<'a> + Send + Sync + 'static,
+    {
+        self.plaintext_preamble = Some(Arc::new(preamble));
+        self
+    }
+
+    /// Sets an async function which runs after connecting (and after TLS, for `wss` URLs)
+    /// but before the WebSocket handshake request is sent. The function receives the
+    /// [`HandshakeRequest`], which can be used to inspect the connection and to set the
+    /// final headers of the request.
+    ///
+    /// For example, this can be used to fetch a signed token which includes
+    /// the local address of the connection:
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # async fn fetch_token(_addr: Option<std::net::SocketAddr>) -> Result<String, std::io::Error> {
+    /// #     Ok("token".to_string())
+    /// # }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .before_handshake(|request| {
+    ///         Box::pin(async move {
+    ///             let token = fetch_token(request.local_addr()).await?;
+    ///             request.add_header("Authorization", &format!("Bearer {}", token));
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// If the function returns an error, connecting fails with
+    /// [`BeforeHandshakeError`](WebSocketError::BeforeHandshakeError).
+    /// The function is also run when the WebSocket is [redialed](ConnectionInfo::redial()).
+    /// Defaults to no function.
+    pub fn before_handshake<F>(&mut self, before_handshake: F) -> &mut Self
+    where
+        F: for<'a> Fn(&'a mut HandshakeRequest) -> BeforeHandshakeFuture<'a>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.before_handshake = Some(Arc::new(before_handshake));
+        self
+    }
+
+    /// Sets a function to run each time the WebSocket is [redialed](ConnectionInfo::redial()),
+    /// once the new connection has been established but before it is returned. The function
+    /// receives the write half of the new connection, which can be used to send subscribe
+    /// or authentication messages again before normal traffic resumes:
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .on_reconnected(|write_half| {
+    ///         Box::pin(async move {
+    ///             write_half
+    ///                 .send_text(r#"{"subscribe":"prices"}"#.to_string())
+    ///                 .await?;
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The function is not run for the original connection.
+    /// If it returns an error, redialing fails with that error.
+    /// Defaults to no function.
+    pub fn on_reconnected<F>(&mut self, on_reconnected: F) -> &mut Self
+    where
+        F: for<'a> Fn(&'a mut WebSocketWriteHalf) -> OnReconnectedFuture<'a>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_reconnected = Some(Arc::new(on_reconnected));
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+impl WebSocketBuilder {
     /// Controls the use of certificate validation. Defaults to false.
     pub fn tls_danger_accept_invalid_certs(&mut self, accept_invalid_certs: bool) -> &mut Self {
-        self.tls_connector_builder
+        self.tls
+            .connector_builder
             .danger_accept_invalid_certs(accept_invalid_certs);
         self
     }
@@ -162,7 +1006,8 @@ impl WebSocketBuilder {
         &mut self,
         accept_invalid_hostnames: bool,
     ) -> &mut Self {
-        self.tls_connector_builder
+        self.tls
+            .connector_builder
             .danger_accept_invalid_hostnames(accept_invalid_hostnames);
         self
     }
@@ -172,43 +1017,694 @@ impl WebSocketBuilder {
     /// to that set when communicating with servers not trusted by the system.
     /// Defaults to an empty set.
     pub fn tls_add_root_certificate(&mut self, cert: TlsCertificate) -> &mut Self {
-        self.tls_connector_builder.add_root_certificate(cert.0);
+        self.tls.connector_builder.add_root_certificate(cert.0);
         self
     }
 
     /// Controls the use of built-in system certificates during certificate validation.
     /// Defaults to false -- built-in system certs will be used.
     pub fn tls_disable_built_in_roots(&mut self, disable: bool) -> &mut Self {
-        self.tls_connector_builder.disable_built_in_roots(disable);
+        self.tls.connector_builder.disable_built_in_roots(disable);
         self
     }
 
     /// Sets the identity to be used for client certificate authentication.
     pub fn tls_identity(&mut self, identity: TlsIdentity) -> &mut Self {
-        self.tls_connector_builder.identity(identity.0);
+        self.tls.connector_builder.identity(identity.0);
         self
     }
 
     /// Sets the maximum supported TLS protocol version.
     /// A value of None enables support for the newest protocols supported by the implementation.
     /// Defaults to None.
+    ///
+    /// [`connect()`](WebSocketBuilder::connect()) fails with
+    /// [`TlsPolicyError`](WebSocketError::TlsPolicyError) if this is older than TLS 1.2,
+    /// unless [legacy protocols](WebSocketBuilder::tls_danger_allow_legacy_protocols())
+    /// are allowed. With the `fips` feature, it fails with
+    /// [`FipsProtocolError`](WebSocketError::FipsProtocolError) instead, whether or not
+    /// they are allowed.
     pub fn tls_max_protocol_version(&mut self, protocol: Option<TlsProtocol>) -> &mut Self {
-        self.tls_connector_builder.max_protocol_version(protocol);
+        self.tls.max_protocol_version = protocol;
+        self.tls.connector_builder.max_protocol_version(protocol);
         self
     }
 
     /// Sets the minimum supported TLS protocol version.
     /// A value of None enables support for the oldest protocols supported by the implementation.
-    /// Defaults to Some(Protocol::Tlsv10).
+    /// Defaults to Some(Protocol::Tlsv12).
+    ///
+    /// [`connect()`](WebSocketBuilder::connect()) fails with
+    /// [`TlsPolicyError`](WebSocketError::TlsPolicyError) if this is None or older than
+    /// TLS 1.2, unless [legacy protocols](WebSocketBuilder::tls_danger_allow_legacy_protocols())
+    /// are allowed. With the `fips` feature, it fails with
+    /// [`FipsProtocolError`](WebSocketError::FipsProtocolError) instead, whether or not
+    /// they are allowed.
     pub fn tls_min_protocol_version(&mut self, protocol: Option<TlsProtocol>) -> &mut Self {
-        self.tls_connector_builder.min_protocol_version(protocol);
+        self.tls.min_protocol_version = protocol;
+        self.tls.connector_builder.min_protocol_version(protocol);
+        self
+    }
+
+    /// Controls whether the protocol versions set with
+    /// [`tls_min_protocol_version()`](WebSocketBuilder::tls_min_protocol_version()) and
+    /// [`tls_max_protocol_version()`](WebSocketBuilder::tls_max_protocol_version()) may
+    /// include SSL 3.0, TLS 1.0, and TLS 1.1, which are deprecated
+    /// (see [https://tools.ietf.org/html/rfc8996](https://tools.ietf.org/html/rfc8996)),
+    /// for connecting to legacy servers. This has no effect with the `fips` feature.
+    /// Defaults to false.
+    ///
+    /// ```no_run
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # use websockets::secure::TlsProtocol;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let ws = WebSocket::builder()
+    ///     .tls_danger_allow_legacy_protocols(true)
+    ///     .tls_min_protocol_version(Some(TlsProtocol::Tlsv10))
+    ///     .connect("wss://legacy.example.com")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tls_danger_allow_legacy_protocols(&mut self, allow_legacy_protocols: bool) -> &mut Self {
+        self.tls.allow_legacy_protocols = allow_legacy_protocols;
         self
     }
 
     /// Controls the use of Server Name Indication (SNI).
+    /// SNI is never used when the server name is an IP address.
     /// Defaults to true.
     pub fn tls_use_sni(&mut self, use_sni: bool) -> &mut Self {
-        self.tls_connector_builder.use_sni(use_sni);
+        self.tls.use_sni = use_sni;
+        self
+    }
+
+    /// Sets the name used to verify the server's certificate (and for SNI),
+    /// if it is different from the host in the URL. For example, when connecting
+    /// to `wss://10.0.0.5/`, the certificate can be verified against a DNS name
+    /// with `tls_server_name("example.com")`. The name may also be an IP address,
+    /// in which case the certificate must contain a matching IP address SAN.
+    /// Defaults to the host in the URL.
+    pub fn tls_server_name(&mut self, server_name: &str) -> &mut Self {
+        self.tls.server_name = Some(server_name.to_string());
+        self
+    }
+}
+
+#[cfg(feature = "hickory-dns")]
+impl WebSocketBuilder {
+    /// Sets the [hickory-dns](https://docs.rs/hickory-resolver) resolver used to look up
+    /// the URL's host, instead of the system resolver. Defaults to None.
+    ///
+    /// DNSSEC validation can be enabled in the resolver's options, in which case
+    /// connecting fails with [`DnsError`](WebSocketError::DnsError) if a response
+    /// cannot be validated.
+    ///
+    /// ```no_run
+    /// # use websockets::hickory_resolver::config::{ResolverConfig, ResolverOpts};
+    /// # use websockets::hickory_resolver::TokioAsyncResolver;
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut options = ResolverOpts::default();
+    /// options.validate = true;
+    /// let resolver = TokioAsyncResolver::tokio(ResolverConfig::cloudflare(), options);
+    /// let mut ws = WebSocket::builder()
+    ///     .dns_resolver(resolver)
+    ///     .dns_srv_lookup(true)
+    ///     .connect("wss://example.com/chat")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dns_resolver(&mut self, resolver: TokioAsyncResolver) -> &mut Self {
+        self.dns_resolver = Some(resolver);
+        self
+    }
+
+    /// Controls whether the `_ws._tcp` (or `_wss._tcp`) SRV records of the URL's host are
+    /// looked up before connecting. If the host has SRV records, the connection is made to
+    /// one of their targets, which are tried in order of priority (and randomly, favoring
+    /// greater weights, among targets with the same priority) until a connection can be
+    /// opened, as described in [RFC 2782](https://tools.ietf.org/html/rfc2782).
+    /// The handshake and TLS certificate verification still use the host in the URL.
+    /// Hosts without SRV records are connected to as usual.
+    ///
+    /// SRV records are looked up with the resolver set with
+    /// [`dns_resolver()`](WebSocketBuilder::dns_resolver()), or one using the system's
+    /// DNS configuration. Defaults to false.
+    pub fn dns_srv_lookup(&mut self, srv_lookup: bool) -> &mut Self {
+        self.dns_srv_lookup = srv_lookup;
         self
     }
+
+    /// Sets a callback which is called with the [SRV target](SrvTarget) that a connection
+    /// was opened to, when [`dns_srv_lookup()`](WebSocketBuilder::dns_srv_lookup()) is
+    /// enabled and the host has SRV records. It is also called when the WebSocket
+    /// is [redialed](ConnectionInfo::redial()). Defaults to None.
+    ///
+    /// ```no_run
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .dns_srv_lookup(true)
+    ///     .on_srv_target(|target| println!("connected to {}:{}", target.target, target.port))
+    ///     .connect("wss://xmpp.example.com/xmpp-websocket")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_srv_target<F>(&mut self, on_srv_target: F) -> &mut Self
+    where
+        F: Fn(&SrvTarget) + Send + Sync + 'static,
+    {
+        self.on_srv_target = Some(Arc::new(on_srv_target));
+        self
+    }
+}
+
+/// TLS settings from a [`WebSocketBuilder`]
+#[cfg(feature = "tls")]
+struct TlsSettings {
+    connector_builder: NativeTlsTlsConnectorBuilder,
+    server_name: Option<String>,
+    use_sni: bool,
+    min_protocol_version: Option<TlsProtocol>,
+    max_protocol_version: Option<TlsProtocol>,
+    allow_legacy_protocols: bool,
+}
+
+#[cfg(feature = "tls")]
+impl TlsSettings {
+    fn new() -> Self {
+        let mut connector_builder = NativeTlsTlsConnector::builder();
+        connector_builder.min_protocol_version(Some(DEFAULT_TLS_MIN_PROTOCOL_VERSION));
+        Self {
+            connector_builder,
+            server_name: None,
+            use_sni: true,
+            min_protocol_version: Some(DEFAULT_TLS_MIN_PROTOCOL_VERSION),
+            max_protocol_version: None,
+            allow_legacy_protocols: false,
+        }
+    }
+
+    fn build_connector(
+        &mut self,
+        parsed_addr: &ParsedAddr,
+    ) -> Result<NativeTlsTlsConnector, WebSocketError> {
+        #[cfg(feature = "fips")]
+        check_fips_protocol_versions(self.min_protocol_version, self.max_protocol_version)?;
+        if !self.allow_legacy_protocols {
+            check_protocol_versions(self.min_protocol_version, self.max_protocol_version)?;
+        }
+        let server_name = self
+            .server_name
+            .as_ref()
+            .unwrap_or(&parsed_addr.server_name);
+        // SNI is only for host names, not IP addresses (https://tools.ietf.org/html/rfc6066#section-3)
+        let is_ip = server_name.parse::<IpAddr>().is_ok();
+        self.connector_builder.use_sni(self.use_sni && !is_ip);
+        self.connector_builder
+            .build()
+            .map_err(|e| WebSocketError::TlsBuilderError(e))
+    }
+}
+
+/// Settings from a [`WebSocketBuilder`], which are kept so that
+/// the WebSocket can be [redialed](ConnectionInfo::redial()).
+#[derive(Clone)]
+pub(super) struct ConnectSettings {
+    additional_handshake_headers: Vec<(String, String)>,
+    subprotocols: Vec<String>,
+    extensions_offer: Option<String>,
+    /// Present if the scheme is `wss`
+    #[cfg(feature = "tls")]
+    tls_connector: Option<NativeTlsTlsConnector>,
+    #[cfg(feature = "tls")]
+    tls_server_name: Option<String>,
+    #[cfg(feature = "hickory-dns")]
+    dns_resolver: Option<DnsResolver>,
+    keepalive: Option<Keepalive>,
+    app_heartbeat: Option<AppHeartbeat>,
+    read_stall_timeout: Option<Duration>,
+    strict_mode: bool,
+    raw_close_reasons: bool,
+    shutdown_token: Option<CancellationToken>,
+    logger: Logger,
+    debug_handshake: bool,
+    handshake_limits: HandshakeLimits,
+    entropy: EntropyConfig,
+    disable_masking: bool,
+    transport: Option<Arc<dyn Transport>>,
+    plaintext_preamble: Option<Preamble>,
+    before_handshake: Option<BeforeHandshake>,
+    on_reconnected: Option<OnReconnected>,
+    close_policy: ClosePolicy,
+    tcp_nodelay: bool,
+    recorder: Option<SessionRecorder>,
+    wire_trace: Option<WireTrace>,
+    duplicate_filter: Option<DuplicateFilter>,
+    ping_limiter: PingLimiter,
+    max_queued_events: usize,
+    event_queue_policy: EventQueuePolicy,
+    on_dropped: Option<DropListener>,
+    connect_limiter: Option<ConnectLimiter>,
+    #[cfg(any(test, feature = "test-util"))]
+    fault_injector: Option<FaultInjector>,
+    #[cfg(any(test, feature = "test-util"))]
+    throttle: Option<Throttle>,
+    state: StateTracker,
+}
+
+impl ConnectSettings {
+    /// Returns the hook to run after each successful redial.
+    pub(super) fn on_reconnected(&self) -> Option<&OnReconnected> {
+        self.on_reconnected.as_ref()
+    }
+
+    /// Returns a copy of the settings for redialing, which publishes
+    /// the [`Reconnecting`](ConnectionState::Reconnecting) state.
+    pub(super) fn reconnecting(&self) -> Self {
+        Self {
+            state: self.state.reconnecting(),
+            ..self.clone()
+        }
+    }
+
+    /// Publishes the state of the connection made with these settings.
+    pub(super) fn set_state(&self, state: ConnectionState) {
+        self.state.set(state);
+    }
+
+    /// Connects to the URL (and performs the WebSocket handshake).
+    pub(super) async fn connect(
+        &self,
+        url: &str,
+        parsed_addr: ParsedAddr,
+    ) -> Result<WebSocket, WebSocketError> {
+        let permit = self.connect_permit().await;
+        let result = match self.dial(&parsed_addr).await {
+            Ok(dialed) => self.handshake(url, &parsed_addr, dialed).await,
+            Err(e) => Err(e),
+        };
+        if let Some(permit) = permit {
+            permit.finish(&result);
+        }
+        if result.is_err() {
+            self.state.set(ConnectionState::Closed);
+        }
+        result
+    }
+
+    /// Performs the WebSocket handshake on a connection kept open for reuse.
+    async fn connect_reused(
+        &self,
+        url: &str,
+        parsed_addr: ParsedAddr,
+        token: ConnectionReuseToken,
+    ) -> Result<WebSocket, WebSocketError> {
+        let dialed = (token.stream, token.addr, ConnectTimings::default());
+        let result = self.handshake(url, &parsed_addr, dialed).await;
+        if result.is_err() {
+            self.state.set(ConnectionState::Closed);
+        }
+        result
+    }
+
+    /// Waits until the [connect limiter](WebSocketBuilder::connect_limiter()),
+    /// if any, allows a connection attempt to start.
+    async fn connect_permit(&self) -> Option<ConnectPermit> {
+        match &self.connect_limiter {
+            Some(connect_limiter) => Some(connect_limiter.acquire().await),
+            None => None,
+        }
+    }
+
+    /// Connects to the address, returning the stream to perform the WebSocket handshake on
+    /// (after the plaintext preamble and TLS handshake, if any).
+    ///
+    /// The TLS handshake is started as soon as the connection is open (and the preamble,
+    /// if any, has been exchanged), and the phases are timed for
+    /// [`WebSocket::connect_timings()`].
+    async fn dial(&self, parsed_addr: &ParsedAddr) -> Result<Dialed, WebSocketError> {
+        let started = Instant::now();
+        let ((mut stream, connect), addr) = self.resolve_and_open(parsed_addr).await?;
+        let mut timings = ConnectTimings {
+            resolve: started.elapsed() - connect,
+            connect,
+            ..ConnectTimings::default()
+        };
+        #[cfg(any(test, feature = "test-util"))]
+        if let Some(throttle) = self.throttle {
+            stream = Stream::Throttled(Box::new(ThrottledStream::new(stream, throttle)));
+        }
+        if let Some(plaintext_preamble) = &self.plaintext_preamble {
+            let exchanging = Instant::now();
+            plaintext_preamble(&mut stream)
+                .await
+                .map_err(WebSocketError::PreambleError)?;
+            timings.preamble = exchanging.elapsed();
+        }
+        #[cfg(feature = "tls")]
+        let stream = match &self.tls_connector {
+            Some(tls_connector) => {
+                let server_name = self
+                    .tls_server_name
+                    .as_ref()
+                    .unwrap_or(&parsed_addr.server_name);
+                let negotiating = Instant::now();
+                let stream = stream.into_tls(server_name, tls_connector.clone()).await?;
+                timings.tls = negotiating.elapsed();
+                stream
+            }
+            None => stream,
+        };
+        #[cfg(any(test, feature = "test-util"))]
+        let stream = match &self.fault_injector {
+            Some(fault_injector) => {
+                Stream::Faulty(Box::new(FaultyStream::new(stream, fault_injector.clone())))
+            }
+            None => stream,
+        };
+        let stream = match &self.wire_trace {
+            Some(wire_trace) => {
+                Stream::Traced(Box::new(TracedStream::new(stream, wire_trace.clone())))
+            }
+            None => stream,
+        };
+        Ok((stream, addr, timings))
+    }
+
+    /// Resolves the host and opens a connection to it, returning the stream along with
+    /// the time spent opening the connection, and the address it was opened to
+    /// (if it is not a Unix domain socket).
+    async fn resolve_and_open(
+        &self,
+        parsed_addr: &ParsedAddr,
+    ) -> Result<((Stream, Duration), Option<SocketAddr>), WebSocketError> {
+        // the time spent opening the connection is measured separately from resolving,
+        // since a resolver may try several addresses
+        let open = |addr| async move {
+            let opening = Instant::now();
+            let stream = self.open(addr).await?;
+            Ok((stream, opening.elapsed()))
+        };
+        #[cfg(unix)]
+        if let Some(unix_socket) = &parsed_addr.unix_socket {
+            let opening = Instant::now();
+            let unix_stream = UnixStream::connect(unix_socket)
+                .await
+                .map_err(WebSocketError::UnixConnectionError)?;
+            return Ok(((Stream::Unix(unix_stream), opening.elapsed()), None));
+        }
+        #[cfg(feature = "hickory-dns")]
+        if let Some(dns_resolver) = &self.dns_resolver {
+            let (opened, addr) = dns_resolver.connect(parsed_addr, open).await?;
+            return Ok((opened, Some(addr)));
+        }
+        let addr = parsed_addr.resolve().await?;
+        Ok((open(addr).await?, Some(addr)))
+    }
+
+    /// Opens a connection to the address with the transport (or TCP).
+    async fn open(&self, addr: SocketAddr) -> Result<Stream, WebSocketError> {
+        match &self.transport {
+            Some(transport) => Ok(Stream::Custom(
+                transport
+                    .connect(addr)
+                    .await
+                    .map_err(WebSocketError::TransportConnectionError)?,
+            )),
+            None => {
+                let tcp_stream = TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| WebSocketError::TcpConnectionError(e))?;
+                tcp_stream
+                    .set_nodelay(self.tcp_nodelay)
+                    .map_err(WebSocketError::TcpConnectionError)?;
+                Ok(Stream::Plain(tcp_stream))
+            }
+        }
+    }
+
+    /// Performs the WebSocket handshake on a stream returned by [`dial()`](ConnectSettings::dial()).
+    async fn handshake(
+        &self,
+        url: &str,
+        parsed_addr: &ParsedAddr,
+        (stream, addr, mut timings): Dialed,
+    ) -> Result<WebSocket, WebSocketError> {
+        let started = Instant::now();
+        let mut additional_handshake_headers = self.additional_handshake_headers.clone();
+        if let Some(before_handshake) = &self.before_handshake {
+            let mut request = HandshakeRequest {
+                url: url.to_string(),
+                path: parsed_addr.path.clone(),
+                peer_addr: addr,
+                local_addr: stream.local_addr(),
+                headers: additional_handshake_headers,
+                subprotocols: self.subprotocols.clone(),
+            };
+            before_handshake(&mut request)
+                .await
+                .map_err(WebSocketError::BeforeHandshakeError)?;
+            for (header_name, header_value) in &request.headers {
+                handshake::validate_header(header_name, header_value)?;
+            }
+            additional_handshake_headers = request.headers;
+        }
+        let mut rng = EntropySource::new(&self.entropy);
+        let handshake = Handshake::new(
+            parsed_addr,
+            &additional_handshake_headers,
+            &self.subprotocols,
+            self.extensions_offer.as_deref(),
+            self.debug_handshake,
+            self.handshake_limits,
+            &mut rng,
+        );
+        let (read_half, write_half) = io::split(stream);
+        let (sender, receiver) = flume::bounded(self.max_queued_events);
+        let (pause_handle, paused) = PauseHandle::new();
+        let activity = Arc::new(Activity::new(
+            parsed_addr.host.clone(),
+            self.recorder.clone(),
+            self.state.clone(),
+            self.on_dropped.clone(),
+        ));
+        let mut ws = WebSocket {
+            read_half: WebSocketReadHalf {
+                stream: BufReader::new(read_half),
+                buffer: Vec::new(),
+                pending_frames: VecDeque::new(),
+                last_frame_type: FrameType::default(),
+                in_progress_message: None,
+                receive_progress: watch::channel(None).0,
+                activity: activity.clone(),
+                frame_filter: OpcodeSet::ALL,
+                duplicate_filter: self.duplicate_filter.clone(),
+                ping_limiter: self.ping_limiter.clone(),
+                event_queue_policy: self.event_queue_policy,
+                read_stall_timeout: self.read_stall_timeout,
+                strict_mode: self.strict_mode,
+                raw_close_reasons: self.raw_close_reasons,
+                shutdown_token: self.shutdown_token.clone(),
+                logger: self.logger,
+                sender,
+                event_pump: None,
+                pause_handle,
+                paused,
+            },
+            write_half: WebSocketWriteHalf {
+                writer: Arc::new(Mutex::new(Writer {
+                    shutdown: false,
+                    sent_closed: false,
+                    stream: BufWriter::new(write_half),
+                    rng,
+                    mask_frames: !self.disable_masking,
+                    activity: activity.clone(),
+                    close_policy: self.close_policy,
+                    sent_close_frame: false,
+                    sending_fragmented_message: false,
+                    deferred_close: None,
+                })),
+                receiver,
+                send_progress: watch::channel(None).0,
+                activity,
+            },
+            accepted_subprotocol: None,
+            accepted_extensions: None,
+            handshake_response_headers: None,
+            interim_responses: Vec::new(),
+            connection_info: Some(ConnectionInfo::new(url, parsed_addr, addr, self.clone())),
+            health_checks: 0,
+            keepalive: self.keepalive,
+            app_heartbeat: self.app_heartbeat.clone(),
+            connect_timings: ConnectTimings::default(),
+        };
+
+        // perform opening handshake
+        handshake.send_request(&mut ws).await?;
+        match handshake.check_response(&mut ws).await {
+            Ok(_) => {
+                if let Some(connection_info) = &mut ws.connection_info {
+                    connection_info.accepted_subprotocol = ws.accepted_subprotocol.clone();
+                }
+                timings.handshake = started.elapsed();
+                ws.connect_timings = timings;
+                self.state.set(ConnectionState::Open);
+                Ok(ws)
+            }
+            Err(e) => {
+                self.logger.warn(format_args!(
+                    "websocket handshake with {} failed: {}",
+                    parsed_addr.host, e
+                ));
+                ws.shutdown().await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A connection attempt made by [`WebSocketBuilder::connect_race()`], with the index of its URL
+type DialFuture<'a> =
+    Pin<Box<dyn Future<Output = (usize, Result<RaceDialed, WebSocketError>)> + Send + 'a>>;
+
+/// A connection opened by [`ConnectSettings::dial()`], with its address
+/// and the timings of the phases so far
+type Dialed = (Stream, Option<SocketAddr>, ConnectTimings);
+
+/// A connection opened during a race, with the permit of its connection attempt
+type RaceDialed = (Dialed, Option<ConnectPermit>);
+
+/// Polls the connection attempts concurrently until one succeeds, dropping (and so aborting)
+/// the others. If every attempt fails, the error of the last attempt to fail is returned.
+async fn first_dialed(
+    mut dials: Vec<DialFuture<'_>>,
+) -> Result<(usize, RaceDialed), WebSocketError> {
+    let mut last_error = WebSocketError::NoEndpointsError;
+    poll_fn(|cx| {
+        let mut i = 0;
+        while i < dials.len() {
+            match dials[i].as_mut().poll(cx) {
+                Poll::Ready((index, Ok(dialed))) => return Poll::Ready(Ok((index, dialed))),
+                Poll::Ready((_index, Err(e))) => {
+                    last_error = e;
+                    drop(dials.swap_remove(i));
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        if dials.is_empty() {
+            Poll::Ready(Err(std::mem::replace(
+                &mut last_error,
+                WebSocketError::NoEndpointsError,
+            )))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Returns whether a protocol version is older than TLS 1.2
+#[cfg(feature = "tls")]
+fn is_legacy_protocol(protocol: TlsProtocol) -> bool {
+    matches!(
+        protocol,
+        TlsProtocol::Sslv3 | TlsProtocol::Tlsv10 | TlsProtocol::Tlsv11
+    )
+}
+
+/// Checks that only TLS 1.2 or newer can be negotiated, unless legacy protocols are allowed
+#[cfg(feature = "tls")]
+fn check_protocol_versions(
+    min: Option<TlsProtocol>,
+    max: Option<TlsProtocol>,
+) -> Result<(), WebSocketError> {
+    match (min, max) {
+        (None, _) => Err(WebSocketError::TlsPolicyError(
+            "no minimum protocol version would allow protocols older than TLS 1.2".to_string(),
+        )),
+        (Some(protocol), _) | (_, Some(protocol)) if is_legacy_protocol(protocol) => Err(
+            WebSocketError::TlsPolicyError(format!("{:?} is older than TLS 1.2", protocol)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that only TLS 1.2 or newer can be negotiated, as required in FIPS mode
+#[cfg(feature = "fips")]
+fn check_fips_protocol_versions(
+    min: Option<TlsProtocol>,
+    max: Option<TlsProtocol>,
+) -> Result<(), WebSocketError> {
+    match (min, max) {
+        // no minimum would allow the oldest protocols supported by the implementation
+        (None, _) => Err(WebSocketError::FipsProtocolError),
+        (Some(min), None) if !is_legacy_protocol(min) => Ok(()),
+        (Some(min), Some(max)) if !is_legacy_protocol(min) && !is_legacy_protocol(max) => Ok(()),
+        _ => Err(WebSocketError::FipsProtocolError),
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_removed_regardless_of_case() {
+        let mut builder = WebSocket::builder();
+        builder
+            .add_header("Authorization", "Bearer a")
+            .add_header("X-Client", "1")
+            .remove_header("authorization");
+        assert_eq!(
+            builder.additional_handshake_headers,
+            vec![("X-Client".to_string(), "1".to_string())]
+        );
+    }
+
+    #[test]
+    fn legacy_protocol_versions_are_refused() {
+        assert!(check_protocol_versions(Some(TlsProtocol::Tlsv12), None).is_ok());
+        assert!(matches!(
+            check_protocol_versions(Some(TlsProtocol::Tlsv10), None),
+            Err(WebSocketError::TlsPolicyError(_))
+        ));
+        assert!(check_protocol_versions(None, Some(TlsProtocol::Tlsv12)).is_err());
+        assert!(
+            check_protocol_versions(Some(TlsProtocol::Tlsv12), Some(TlsProtocol::Tlsv11)).is_err()
+        );
+
+        let mut tls = TlsSettings::new();
+        tls.min_protocol_version = Some(TlsProtocol::Tlsv11);
+        tls.allow_legacy_protocols = true;
+        let parsed_addr = ParsedAddr::try_from("wss://legacy.example.com").unwrap();
+        // legacy protocols are never allowed in FIPS mode
+        assert_eq!(
+            tls.build_connector(&parsed_addr).is_ok(),
+            !cfg!(feature = "fips")
+        );
+    }
+
+    #[cfg(feature = "fips")]
+    #[test]
+    fn fips_protocol_versions() {
+        assert!(check_fips_protocol_versions(Some(TlsProtocol::Tlsv12), None).is_ok());
+        assert!(
+            check_fips_protocol_versions(Some(TlsProtocol::Tlsv12), Some(TlsProtocol::Tlsv12))
+                .is_ok()
+        );
+        assert!(check_fips_protocol_versions(None, None).is_err());
+        assert!(check_fips_protocol_versions(Some(TlsProtocol::Tlsv10), None).is_err());
+        assert!(
+            check_fips_protocol_versions(Some(TlsProtocol::Tlsv12), Some(TlsProtocol::Tlsv11))
+                .is_err()
+        );
+    }
 }
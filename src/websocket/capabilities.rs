@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::builder::{
+    DEFAULT_KEEPALIVE_TIMEOUT, DEFAULT_MAX_HANDSHAKE_HEADERS, DEFAULT_MAX_HANDSHAKE_HEADER_BYTES,
+};
+
+/// The version of the WebSocket protocol spoken by this library, which is sent in the
+/// `Sec-WebSocket-Version` handshake header
+/// (see [https://tools.ietf.org/html/rfc6455#section-4.1](https://tools.ietf.org/html/rfc6455#section-4.1)).
+pub const WEBSOCKET_VERSION: u16 = 13;
+
+/// What this build of the library supports, generated from [`capabilities()`].
+///
+/// This allows higher-level libraries to adapt at runtime instead of
+/// probing cargo features at compile time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The versions of the WebSocket protocol that are supported
+    pub websocket_versions: &'static [u16],
+    /// The WebSocket extensions that can be negotiated (such as `permessage-deflate`)
+    pub extensions: &'static [&'static str],
+    /// Whether secure (`wss`) connections are supported (the `tls` feature)
+    pub tls: bool,
+    /// The library used for TLS (currently always `native-tls`),
+    /// or `None` if secure connections are not supported
+    pub tls_backend: Option<&'static str>,
+    /// Whether TLS is restricted to protocol versions approved for FIPS 140
+    /// (the `fips` feature)
+    pub fips: bool,
+    /// Whether events are logged through the `log` crate (the `log` feature)
+    pub log: bool,
+    /// Whether conversions to and from tungstenite types are available
+    /// (the `tungstenite` feature)
+    pub tungstenite: bool,
+    /// Whether the `#[websocket_protocol]` macro and typed clients are available
+    /// (the `macros` feature)
+    pub macros: bool,
+    /// Whether `WebSocketTask` is available (the `task` feature)
+    pub task: bool,
+    /// Whether connections can be made through HTTP or SOCKS proxies
+    pub proxy: bool,
+    /// Whether connections can be made over Unix domain sockets (`ws+unix` URLs)
+    pub unix_sockets: bool,
+    /// The default for [`WebSocketBuilder::keepalive_timeout()`]
+    pub default_keepalive_timeout: Duration,
+    /// The default for [`WebSocketBuilder::max_handshake_headers()`]
+    pub default_max_handshake_headers: usize,
+    /// The default for [`WebSocketBuilder::max_handshake_header_bytes()`]
+    pub default_max_handshake_header_bytes: usize,
+    /// The largest payload allowed in a control frame
+    /// (see [https://tools.ietf.org/html/rfc6455#section-5.5](https://tools.ietf.org/html/rfc6455#section-5.5))
+    pub max_control_frame_payload: usize,
+}
+
+/// Reports what this build of the library supports (see [`Capabilities`]).
+///
+/// ```
+/// let capabilities = websockets::capabilities();
+/// assert!(capabilities.websocket_versions.contains(&13));
+/// if !capabilities.tls {
+///     println!("only ws:// URLs are supported");
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        websocket_versions: &[WEBSOCKET_VERSION],
+        extensions: &[],
+        tls: cfg!(feature = "tls"),
+        tls_backend: if cfg!(feature = "tls") {
+            Some("native-tls")
+        } else {
+            None
+        },
+        fips: cfg!(feature = "fips"),
+        log: cfg!(feature = "log"),
+        tungstenite: cfg!(feature = "tungstenite"),
+        macros: cfg!(feature = "macros"),
+        task: cfg!(feature = "task"),
+        proxy: false,
+        unix_sockets: cfg!(unix),
+        default_keepalive_timeout: DEFAULT_KEEPALIVE_TIMEOUT,
+        default_max_handshake_headers: DEFAULT_MAX_HANDSHAKE_HEADERS,
+        default_max_handshake_header_bytes: DEFAULT_MAX_HANDSHAKE_HEADER_BYTES,
+        max_control_frame_payload: 125,
+    }
+}
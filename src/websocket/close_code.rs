@@ -0,0 +1,173 @@
+use std::fmt::{Display, Error as FmtError, Formatter};
+
+#[allow(unused_imports)] // for intra doc links
+use super::frame::Frame;
+use super::stats::close_code_name;
+use crate::error::WebSocketError;
+
+/// The status code of a Close frame, with helpers for treating status codes consistently
+/// (in the spirit of HTTP status classes) on both ends of a connection.
+///
+/// When closing, `CloseCode::from(&error)` chooses the status code for a [`WebSocketError`],
+/// and the constants can be used for application errors. When a Close frame is received,
+/// [`Frame::close_code()`] returns its status code, so that, for example,
+/// [`is_retryable()`](CloseCode::is_retryable()) can decide whether to reconnect.
+///
+/// ```
+/// # use websockets::{CloseCode, Frame};
+/// let frame = Frame::close(Some((CloseCode::TRY_AGAIN_LATER.0, "overloaded".to_string())));
+/// let code = frame.close_code().unwrap();
+/// assert!(code.is_retryable());
+/// assert!(!code.is_client_error());
+/// assert_eq!(code.to_string(), "1013 (Try Again Later)");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CloseCode(pub u16);
+
+// https://tools.ietf.org/html/rfc6455#section-7.4.1
+// https://www.iana.org/assignments/websocket/websocket.xhtml#close-code-number
+impl CloseCode {
+    /// 1000: the purpose of the connection has been fulfilled
+    pub const NORMAL_CLOSURE: Self = Self(1000);
+    /// 1001: an endpoint is going away, such as a server going down
+    pub const GOING_AWAY: Self = Self(1001);
+    /// 1002: an endpoint received a frame which violated the protocol
+    pub const PROTOCOL_ERROR: Self = Self(1002);
+    /// 1003: an endpoint received a type of data it cannot accept
+    pub const UNSUPPORTED_DATA: Self = Self(1003);
+    /// 1005: a Close frame was received without a status code (never sent)
+    pub const NO_STATUS_RECEIVED: Self = Self(1005);
+    /// 1006: the connection was closed without a Close frame (never sent)
+    pub const ABNORMAL_CLOSURE: Self = Self(1006);
+    /// 1007: an endpoint received data inconsistent with the type of the message,
+    /// such as a Text message which is not valid UTF-8
+    pub const INVALID_FRAME_PAYLOAD_DATA: Self = Self(1007);
+    /// 1008: an endpoint received a message which violates its policy
+    pub const POLICY_VIOLATION: Self = Self(1008);
+    /// 1009: an endpoint received a message too big for it to process
+    pub const MESSAGE_TOO_BIG: Self = Self(1009);
+    /// 1010: the server did not negotiate an extension the client requires
+    pub const MANDATORY_EXTENSION: Self = Self(1010);
+    /// 1011: the server encountered an unexpected condition
+    pub const INTERNAL_ERROR: Self = Self(1011);
+    /// 1012: the server is restarting
+    pub const SERVICE_RESTART: Self = Self(1012);
+    /// 1013: the server is overloaded, and the client should try again later
+    pub const TRY_AGAIN_LATER: Self = Self(1013);
+    /// 1014: a gateway or proxy received an invalid response from the upstream server
+    pub const BAD_GATEWAY: Self = Self(1014);
+    /// 1015: the TLS handshake failed (never sent)
+    pub const TLS_HANDSHAKE: Self = Self(1015);
+
+    /// Returns the name of the status code if it is registered with IANA
+    /// (see [`close_code_name()`](crate::close_code_name())).
+    pub fn name(self) -> Option<&'static str> {
+        close_code_name(self.0)
+    }
+
+    /// Returns whether the status code indicates a temporary condition, after which
+    /// reconnecting (perhaps after a delay) is expected to succeed: 1001 (Going Away),
+    /// 1006 (Abnormal Closure), 1011 (Internal Error), 1012 (Service Restart),
+    /// 1013 (Try Again Later), and 1014 (Bad Gateway).
+    pub fn is_retryable(self) -> bool {
+        matches!(self.0, 1001 | 1006 | 1011..=1014)
+    }
+
+    /// Returns whether the status code indicates that the closing endpoint rejected what
+    /// it received from its peer, like a 4xx HTTP status code, so that repeating it
+    /// (for example, after reconnecting) would fail the same way: 1002 (Protocol Error),
+    /// 1003 (Unsupported Data), 1007 (Invalid Frame Payload Data), 1008 (Policy Violation),
+    /// 1009 (Message Too Big), and 1010 (Mandatory Extension).
+    pub fn is_client_error(self) -> bool {
+        matches!(self.0, 1002 | 1003 | 1007..=1010)
+    }
+
+    /// Returns whether the status code indicates a failure of the closing endpoint itself,
+    /// like a 5xx HTTP status code: 1011 (Internal Error), 1012 (Service Restart),
+    /// 1013 (Try Again Later), and 1014 (Bad Gateway).
+    pub fn is_server_error(self) -> bool {
+        matches!(self.0, 1011..=1014)
+    }
+
+    /// Returns whether the status code is reserved for use by applications and libraries
+    /// (3000 to 3999, registered with IANA) or for private use (4000 to 4999).
+    pub fn is_application_defined(self) -> bool {
+        matches!(self.0, 3000..=4999)
+    }
+}
+
+impl Display for CloseCode {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self.name() {
+            Some(name) => write!(f, "{} ({})", self.0, name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(status_code: u16) -> Self {
+        Self(status_code)
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(close_code: CloseCode) -> Self {
+        close_code.0
+    }
+}
+
+/// Chooses the status code to close the connection with after an error:
+/// protocol violations by the server map to 1002 (Protocol Error), payloads which are
+/// too large map to 1009 (Message Too Big), cancellation maps to 1001 (Going Away), and
+/// every other error maps to 1011 (Internal Error).
+impl From<&WebSocketError> for CloseCode {
+    fn from(error: &WebSocketError) -> Self {
+        match error {
+            WebSocketError::InvalidFrameError
+            | WebSocketError::ReceivedMaskedFrameError
+            | WebSocketError::NonMinimalLengthError
+            | WebSocketError::ControlFrameTooLargeError => Self::PROTOCOL_ERROR,
+            WebSocketError::PayloadTooLargeError => Self::MESSAGE_TOO_BIG,
+            #[cfg(feature = "macros")]
+            WebSocketError::DeserializeError(_) => Self::INVALID_FRAME_PAYLOAD_DATA,
+            WebSocketError::CancelledError => Self::GOING_AWAY,
+            _ => Self::INTERNAL_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+
+    #[test]
+    fn status_codes_are_classified() {
+        let retryable: Vec<_> = (1000..=1015)
+            .filter(|&code| CloseCode(code).is_retryable())
+            .collect();
+        assert_eq!(retryable, [1001, 1006, 1011, 1012, 1013, 1014]);
+        let client_errors: Vec<_> = (1000..=1015)
+            .filter(|&code| CloseCode(code).is_client_error())
+            .collect();
+        assert_eq!(client_errors, [1002, 1003, 1007, 1008, 1009, 1010]);
+        assert!(CloseCode(4000).is_application_defined());
+        assert!(!CloseCode(4000).is_retryable());
+
+        assert_eq!(
+            CloseCode::from(&WebSocketError::PayloadTooLargeError),
+            CloseCode::MESSAGE_TOO_BIG
+        );
+        assert_eq!(
+            CloseCode::from(&WebSocketError::ReadStallTimeoutError),
+            CloseCode::INTERNAL_ERROR
+        );
+        assert_eq!(
+            Frame::close(None).close_code(),
+            Some(CloseCode::NO_STATUS_RECEIVED)
+        );
+        assert_eq!(Frame::text(String::new()).close_code(), None);
+        assert_eq!(CloseCode(4321).to_string(), "4321");
+    }
+}
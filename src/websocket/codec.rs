@@ -0,0 +1,422 @@
+use std::convert::TryFrom;
+
+use super::frame::{Frame, FrameHeader};
+use super::FrameType;
+#[allow(unused_imports)] // for intra doc links
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+/// Encodes a frame, masking its payload with `masking_key` if one is given
+/// (see [`Frame::to_bytes()`]).
+pub fn encode_frame(
+    frame: &Frame,
+    masking_key: Option<[u8; 4]>,
+) -> Result<Vec<u8>, WebSocketError> {
+    frame.to_bytes(masking_key)
+}
+
+/// How the length of a frame's payload is encoded in the frame's header
+/// (see [https://tools.ietf.org/html/rfc6455#section-5.2](https://tools.ietf.org/html/rfc6455#section-5.2)).
+///
+/// The length must be encoded in the fewest bytes possible, as [`encode_payload_len()`] does,
+/// and the most significant bit of a 64-bit length must be 0:
+///
+/// ```
+/// # use websockets::{encode_payload_len, LenEncoding};
+/// assert_eq!(encode_payload_len(125), LenEncoding::SevenBit(125));
+/// assert_eq!(encode_payload_len(126), LenEncoding::SixteenBit(126));
+/// assert_eq!(encode_payload_len(65535), LenEncoding::SixteenBit(65535));
+/// assert_eq!(encode_payload_len(65536), LenEncoding::SixtyFourBit(65536));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LenEncoding {
+    /// The length is encoded in the 7 bits after the mask bit
+    SevenBit(u8),
+    /// The 7 bits after the mask bit are 126, and the length is encoded in the next 16 bits
+    SixteenBit(u16),
+    /// The 7 bits after the mask bit are 127, and the length is encoded in the next 64 bits
+    SixtyFourBit(u64),
+}
+
+impl LenEncoding {
+    /// The largest payload length which is encoded in 7 bits
+    pub const MAX_SEVEN_BIT: usize = 125;
+    /// The largest payload length which is encoded in 16 bits
+    pub const MAX_SIXTEEN_BIT: usize = u16::MAX as usize;
+
+    /// Returns the encoded payload length.
+    pub fn payload_len(self) -> u64 {
+        match self {
+            Self::SevenBit(len) => len as u64,
+            Self::SixteenBit(len) => len as u64,
+            Self::SixtyFourBit(len) => len,
+        }
+    }
+
+    /// Returns whether the payload length is encoded in the fewest bytes possible.
+    pub fn is_minimal(self) -> bool {
+        match usize::try_from(self.payload_len()) {
+            Ok(len) => encode_payload_len(len) == self,
+            // too long to be encoded any other way
+            Err(_) => true,
+        }
+    }
+
+    /// Returns the number of bytes taken up by the length in the header
+    /// (including the byte shared with the mask bit).
+    pub fn len_bytes(self) -> usize {
+        match self {
+            Self::SevenBit(_) => 1,
+            Self::SixteenBit(_) => 3,
+            Self::SixtyFourBit(_) => 9,
+        }
+    }
+
+    /// Appends the encoded length to `buf`, setting the mask bit if `masked` is true.
+    pub(super) fn write(self, buf: &mut Vec<u8>, masked: bool) {
+        let mask_bit = if masked { 0b10000000 } else { 0 };
+        match self {
+            Self::SevenBit(len) => buf.push(mask_bit | len),
+            Self::SixteenBit(len) => {
+                buf.push(mask_bit | 126);
+                buf.extend_from_slice(&len.to_be_bytes());
+            }
+            Self::SixtyFourBit(len) => {
+                buf.push(mask_bit | 127);
+                buf.extend_from_slice(&len.to_be_bytes());
+            }
+        }
+    }
+
+    /// Reads the encoded length at the start of `buf` (which starts with the byte
+    /// shared with the mask bit), returning None if `buf` does not contain all of it yet.
+    pub(super) fn read(buf: &[u8]) -> Option<Self> {
+        match buf.first()? & 0b01111111 {
+            126 => {
+                let len = buf.get(1..3)?;
+                Some(Self::SixteenBit(u16::from_be_bytes([len[0], len[1]])))
+            }
+            127 => {
+                let mut len = [0; 8];
+                len.copy_from_slice(buf.get(1..9)?);
+                Some(Self::SixtyFourBit(u64::from_be_bytes(len)))
+            }
+            len => Some(Self::SevenBit(len)),
+        }
+    }
+}
+
+/// Returns the shortest encoding of a payload length, as it must be sent.
+pub fn encode_payload_len(len: usize) -> LenEncoding {
+    if len <= LenEncoding::MAX_SEVEN_BIT {
+        LenEncoding::SevenBit(len as u8)
+    } else if len <= LenEncoding::MAX_SIXTEEN_BIT {
+        LenEncoding::SixteenBit(len as u16)
+    } else {
+        // payloads are never longer than isize::MAX bytes,
+        // so the most significant bit is always 0 as required
+        LenEncoding::SixtyFourBit(len as u64)
+    }
+}
+
+/// Decodes frames from bytes without a connection (for example, in tests, proxies,
+/// or custom transports), using the same codec as [`WebSocket`].
+///
+/// The decoder keeps track of the type of the last data frame,
+/// so that continuation frames are decoded as Text or Binary frames.
+///
+/// ```
+/// # use websockets::{encode_frame, Frame, FrameDecoder, WebSocketError};
+/// # fn main() -> Result<(), WebSocketError> {
+/// let bytes = encode_frame(&Frame::text("foo".to_string()), None)?;
+/// assert_eq!(bytes, b"\x81\x03foo");
+///
+/// let mut decoder = FrameDecoder::new();
+/// let (frame, len) = decoder.decode(&bytes)?.unwrap();
+/// assert_eq!(frame, Frame::text("foo".to_string()));
+/// assert_eq!(len, bytes.len());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    last_frame_type: FrameType,
+    allow_masked: bool,
+    strict: bool,
+    raw_close_reasons: bool,
+}
+
+impl FrameDecoder {
+    /// Constructs a decoder for frames sent by a server, which rejects masked frames
+    /// with [`ReceivedMaskedFrameError`](WebSocketError::ReceivedMaskedFrameError).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a decoder which also accepts masked frames (as sent by a client),
+    /// unmasking their payloads.
+    pub fn allowing_masked() -> Self {
+        Self {
+            allow_masked: true,
+            ..Self::default()
+        }
+    }
+
+    /// Sets whether frames whose payload length is not encoded in the fewest bytes possible
+    /// are rejected with [`NonMinimalLengthError`](WebSocketError::NonMinimalLengthError),
+    /// and frames whose 64-bit payload length has its most significant bit set are rejected
+    /// with [`InvalidFrameError`](WebSocketError::InvalidFrameError) (see [`LenEncoding`]),
+    /// as RFC 6455 requires.
+    /// Defaults to false, for compatibility with lenient encoders.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets whether Close frames whose reason is not valid UTF-8 are decoded with
+    /// the reason as a [raw reason](Frame::close_raw()), instead of being rejected with
+    /// [`InvalidFrameError`](WebSocketError::InvalidFrameError).
+    /// Defaults to false.
+    pub fn raw_close_reasons(&mut self, raw_close_reasons: bool) -> &mut Self {
+        self.raw_close_reasons = raw_close_reasons;
+        self
+    }
+
+    /// Decodes the frame at the start of `buf`, returning it along with the number of bytes
+    /// it took up, or None if `buf` does not contain the entire frame yet.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<Option<(Frame, usize)>, WebSocketError> {
+        let header = match FrameHeader::parse(buf, self.allow_masked, self.strict)? {
+            Some(header) if buf.len() >= header.frame_len() => header,
+            _ => return Ok(None),
+        };
+        let payload = buf[header.header_len..header.frame_len()].to_vec();
+        let frame = Frame::decode(
+            &header,
+            payload,
+            &self.last_frame_type,
+            self.raw_close_reasons,
+        )?;
+        // remember last data frame type in case we get continuation frames (https://tools.ietf.org/html/rfc6455#section-5.2)
+        match frame {
+            Frame::Text { .. } => self.last_frame_type = FrameType::Text,
+            Frame::Binary { .. } => self.last_frame_type = FrameType::Binary,
+            _ => (),
+        };
+        Ok(Some((frame, header.frame_len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Payload lengths which are encoded in 7 bits, in 16 bits, or in 64 bits,
+    /// concentrated around the boundaries between them
+    fn payload_len() -> impl Strategy<Value = usize> {
+        prop_oneof![0..=130_usize, 65_530..=65_540_usize]
+    }
+
+    fn control_payload() -> impl Strategy<Value = Option<Vec<u8>>> {
+        // an empty payload is received as None
+        prop_oneof![Just(None), vec(any::<u8>(), 1..=125).prop_map(Some)]
+    }
+
+    fn data_frame() -> impl Strategy<Value = Frame> {
+        let text = payload_len()
+            .prop_flat_map(|len| vec(b'a'..=b'z', len))
+            .prop_map(|payload| Frame::text(String::from_utf8(payload).unwrap()));
+        let binary = payload_len()
+            .prop_flat_map(|len| vec(any::<u8>(), len))
+            .prop_map(Frame::binary);
+        (prop_oneof![text, binary], any::<bool>()).prop_map(|(frame, fin)| frame.set_fin(fin))
+    }
+
+    fn control_frame() -> impl Strategy<Value = Frame> {
+        prop_oneof![
+            Just(Frame::close(None)),
+            (any::<u16>(), "[a-zé]{0,60}")
+                .prop_map(|(status_code, reason)| Frame::close(Some((status_code, reason)))),
+            control_payload().prop_map(|payload| Frame::Ping { payload }),
+            control_payload().prop_map(|payload| Frame::Pong { payload }),
+        ]
+    }
+
+    fn masking_key() -> impl Strategy<Value = Option<[u8; 4]>> {
+        proptest::option::of(any::<[u8; 4]>())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn frames_round_trip(
+            frame in prop_oneof![data_frame(), control_frame()],
+            masking_key in masking_key(),
+        ) {
+            let bytes = encode_frame(&frame, masking_key).unwrap();
+            let mut decoder = FrameDecoder::allowing_masked();
+            decoder.strict(true);
+            let (decoded, len) = decoder.decode(&bytes).unwrap().unwrap();
+            prop_assert_eq!(decoded, frame);
+            prop_assert_eq!(len, bytes.len());
+            // a truncated frame is incomplete
+            let cut = bytes.len() / 2;
+            prop_assert!(FrameDecoder::allowing_masked().decode(&bytes[..cut]).unwrap().is_none());
+        }
+
+        #[test]
+        fn fragmented_messages_round_trip(
+            first in data_frame(),
+            rest in vec((data_frame(), control_frame()), 0..4),
+            masking_key in masking_key(),
+        ) {
+            // a message's frames after the first are continuation frames,
+            // which may be interleaved with control frames
+            let mut frames = vec![first.set_fin(rest.is_empty())];
+            let last = rest.len();
+            for (i, (data_frame, control_frame)) in rest.into_iter().enumerate() {
+                frames.push(control_frame);
+                let data_frame = match (&frames[0], data_frame) {
+                    (Frame::Text { .. }, Frame::Binary { payload, .. }) => {
+                        Frame::text(String::from_utf8_lossy(&payload).into_owned())
+                    }
+                    (Frame::Binary { .. }, Frame::Text { payload, .. }) => {
+                        Frame::binary(payload.into_bytes())
+                    }
+                    (_, data_frame) => data_frame,
+                };
+                frames.push(data_frame.set_continuation(true).set_fin(i + 1 == last));
+            }
+
+            let mut bytes = Vec::new();
+            for frame in &frames {
+                bytes.append(&mut encode_frame(frame, masking_key).unwrap());
+            }
+            let mut decoder = FrameDecoder::allowing_masked();
+            let mut decoded = Vec::new();
+            let mut offset = 0;
+            while let Some((frame, len)) = decoder.decode(&bytes[offset..]).unwrap() {
+                decoded.push(frame);
+                offset += len;
+            }
+            prop_assert_eq!(offset, bytes.len());
+            prop_assert_eq!(decoded, frames);
+        }
+    }
+
+    #[test]
+    fn length_encoding_boundaries() {
+        for (len, header_len) in [
+            (0, 2),
+            (125, 2),
+            (126, 4),
+            (65_534, 4),
+            (65_535, 4),
+            (65_536, 10),
+        ] {
+            let bytes = encode_frame(&Frame::binary(vec![0; len]), None).unwrap();
+            assert_eq!(bytes.len(), header_len + len, "payload of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn payload_lens_are_minimally_encoded() {
+        use LenEncoding::*;
+        let cases = [
+            (0, SevenBit(0)),
+            (1, SevenBit(1)),
+            (124, SevenBit(124)),
+            (125, SevenBit(125)),
+            (126, SixteenBit(126)),
+            (127, SixteenBit(127)),
+            (65_534, SixteenBit(65_534)),
+            (65_535, SixteenBit(65_535)),
+            (65_536, SixtyFourBit(65_536)),
+            (usize::MAX >> 1, SixtyFourBit((usize::MAX >> 1) as u64)),
+        ];
+        for (len, expected) in cases {
+            let len_encoding = encode_payload_len(len);
+            assert_eq!(len_encoding, expected, "payload of {} bytes", len);
+            assert!(len_encoding.is_minimal());
+            assert_eq!(len_encoding.payload_len(), len as u64);
+
+            let mut buf = Vec::new();
+            len_encoding.write(&mut buf, false);
+            assert_eq!(buf.len(), len_encoding.len_bytes());
+            assert_eq!(LenEncoding::read(&buf), Some(len_encoding));
+            assert_eq!(LenEncoding::read(&buf[..buf.len() - 1]), None);
+        }
+
+        assert!(!SixteenBit(125).is_minimal());
+        assert!(!SixtyFourBit(0).is_minimal());
+        assert!(!SixtyFourBit(65_535).is_minimal());
+    }
+
+    #[test]
+    fn non_minimal_lens_are_rejected_in_strict_mode() {
+        // "foo" with its length encoded in 16 bits and in 64 bits
+        let frames: [&[u8]; 2] = [
+            b"\x81\x7e\x00\x03foo",
+            b"\x81\x7f\x00\x00\x00\x00\x00\x00\x00\x03foo",
+        ];
+        for frame in frames {
+            let (decoded, _len) = FrameDecoder::new().decode(frame).unwrap().unwrap();
+            assert_eq!(decoded, Frame::text("foo".to_string()));
+            assert!(matches!(
+                FrameDecoder::new().strict(true).decode(frame),
+                Err(WebSocketError::NonMinimalLengthError)
+            ));
+        }
+    }
+
+    #[test]
+    fn high_bit_lens_are_rejected_in_strict_mode() {
+        let frame = b"\x82\x7f\x80\x00\x00\x00\x00\x00\x00\x00";
+        assert!(matches!(
+            FrameDecoder::new().strict(true).decode(frame),
+            Err(WebSocketError::InvalidFrameError)
+        ));
+    }
+
+    #[test]
+    fn masked_frames_are_rejected_by_default() {
+        let bytes = encode_frame(&Frame::text("foo".to_string()), Some([1, 2, 3, 4])).unwrap();
+        assert!(matches!(
+            FrameDecoder::new().decode(&bytes),
+            Err(WebSocketError::ReceivedMaskedFrameError)
+        ));
+    }
+
+    #[test]
+    fn large_control_frames_are_not_encoded() {
+        assert!(matches!(
+            encode_frame(&Frame::ping(Some(vec![0; 126])), None),
+            Err(WebSocketError::ControlFrameTooLargeError)
+        ));
+    }
+
+    #[test]
+    fn raw_close_reasons_are_decoded_when_allowed() {
+        let frame = Frame::close_raw(4000, vec![0xff; 123]);
+        let bytes = encode_frame(&frame, None).unwrap();
+        assert!(matches!(
+            FrameDecoder::new().decode(&bytes),
+            Err(WebSocketError::InvalidFrameError)
+        ));
+        let (decoded, _len) = FrameDecoder::new()
+            .raw_close_reasons(true)
+            .decode(&bytes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, frame);
+        assert_eq!(decoded.into_close_raw(), Some((4000, vec![0xff; 123])));
+        // only the length is checked
+        assert!(matches!(
+            encode_frame(&Frame::close_raw(4000, vec![0xff; 124]), None),
+            Err(WebSocketError::ControlFrameTooLargeError)
+        ));
+    }
+}
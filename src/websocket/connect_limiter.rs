@@ -0,0 +1,208 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+#[allow(unused_imports)] // for intra doc links
+use super::connection_info::ConnectionInfo;
+use crate::error::WebSocketError;
+
+/// Limits the number of connection attempts in progress at once, and counts their outcomes.
+/// Set with [`WebSocketBuilder::connect_limiter()`].
+///
+/// A limiter is shared by every builder it is set on (it can be cloned cheaply), and by
+/// every connection made with those builders' settings, including connections
+/// [redialed](ConnectionInfo::redial()) after a disconnect. This protects servers from
+/// connection storms, such as when many clients reconnect at once after a mass disconnect.
+/// An attempt lasts from resolving the URL's host until the WebSocket handshake completes;
+/// attempts over the limit wait for an earlier attempt to finish.
+///
+/// ```
+/// # use websockets::{ConnectLimiter, WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let limiter = ConnectLimiter::new(4);
+/// let mut ws = WebSocket::builder()
+///     .connect_limiter(limiter.clone())
+///     .connect("wss://echo.websocket.org")
+///     .await?;
+/// assert_eq!(limiter.stats().established, 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConnectLimiter(Arc<LimiterState>);
+
+impl Debug for ConnectLimiter {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("ConnectLimiter")
+            .field("max_in_flight", &self.0.max_in_flight)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+struct LimiterState {
+    semaphore: Arc<Semaphore>,
+    max_in_flight: usize,
+    /// Attempts which have started and not finished, including those waiting for a permit
+    attempts: AtomicUsize,
+    established: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// A snapshot of the connection attempts made with a [`ConnectLimiter`],
+/// generated from [`ConnectLimiter::stats()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectStats {
+    /// The number of attempts waiting for another attempt to finish
+    pub pending: usize,
+    /// The number of attempts in progress
+    pub in_flight: usize,
+    /// The total number of attempts which established a connection
+    pub established: u64,
+    /// The total number of attempts which failed
+    pub failed: u64,
+}
+
+impl ConnectLimiter {
+    /// Creates a limiter which allows at most `max_in_flight` connection attempts
+    /// to be in progress at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_in_flight` is 0 or greater than [`Semaphore::MAX_PERMITS`].
+    pub fn new(max_in_flight: usize) -> Self {
+        assert!(
+            max_in_flight > 0 && max_in_flight <= Semaphore::MAX_PERMITS,
+            "max_in_flight must be between 1 and Semaphore::MAX_PERMITS"
+        );
+        Self(Arc::new(LimiterState {
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            attempts: AtomicUsize::new(0),
+            established: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns the number of attempts waiting and in progress, and the outcomes
+    /// of the attempts which have finished. Attempts which were cancelled (such as those
+    /// which lost a [race](WebSocketBuilder::connect_race())) are not counted as failed.
+    pub fn stats(&self) -> ConnectStats {
+        let in_flight = self.0.max_in_flight - self.0.semaphore.available_permits();
+        ConnectStats {
+            pending: self
+                .0
+                .attempts
+                .load(Ordering::Relaxed)
+                .saturating_sub(in_flight),
+            in_flight,
+            established: self.0.established.load(Ordering::Relaxed),
+            failed: self.0.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Waits until a connection attempt may start.
+    pub(super) async fn acquire(&self) -> ConnectPermit {
+        let attempt = Attempt::new(self.0.clone());
+        let permit = self
+            .0
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            // the semaphore is never closed
+            .expect("connect limiter semaphore closed");
+        ConnectPermit {
+            _permit: permit,
+            attempt,
+        }
+    }
+}
+
+/// Counts an attempt as started until it is dropped, so that cancelled attempts are not
+/// counted forever
+struct Attempt(Arc<LimiterState>);
+
+impl Attempt {
+    fn new(state: Arc<LimiterState>) -> Self {
+        state.attempts.fetch_add(1, Ordering::Relaxed);
+        Self(state)
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        self.0.attempts.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Allows a connection attempt to proceed until it is dropped.
+pub(super) struct ConnectPermit {
+    _permit: OwnedSemaphorePermit,
+    attempt: Attempt,
+}
+
+impl Debug for ConnectPermit {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("ConnectPermit")
+    }
+}
+
+impl ConnectPermit {
+    /// Records the outcome of the attempt, and ends it.
+    pub(super) fn finish<T>(self, result: &Result<T, WebSocketError>) {
+        let counter = match result {
+            Ok(_) => &self.attempt.0.established,
+            Err(_) => &self.attempt.0.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn attempts_over_the_limit_wait() {
+        let limiter = ConnectLimiter::new(1);
+        let first = limiter.acquire().await;
+        let mut second = Box::pin(limiter.acquire());
+        assert!(time::timeout(Duration::from_millis(10), second.as_mut())
+            .await
+            .is_err());
+        assert_eq!(
+            limiter.stats(),
+            ConnectStats {
+                pending: 1,
+                in_flight: 1,
+                established: 0,
+                failed: 0,
+            }
+        );
+
+        first.finish(&Ok::<_, WebSocketError>(()));
+        let second = second.await;
+        second.finish(&Err::<(), _>(WebSocketError::ResolutionError));
+        // a cancelled attempt is not counted
+        drop(limiter.acquire().await);
+        assert_eq!(
+            limiter.stats(),
+            ConnectStats {
+                pending: 0,
+                in_flight: 0,
+                established: 1,
+                failed: 1,
+            }
+        );
+    }
+}
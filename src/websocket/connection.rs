@@ -0,0 +1,87 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use super::frame::Frame;
+use super::split::SendReceipt;
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+/// The future returned by the methods of [`WebSocketConnection`].
+pub type ConnectionFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, WebSocketError>> + Send + 'a>>;
+
+/// A WebSocket connection which frames can be sent over and received from.
+///
+/// This trait is object safe, so application code can depend on a
+/// `Box<dyn WebSocketConnection>` (or a generic parameter) instead of a [`WebSocket`],
+/// and tests can substitute a test double for a real connection.
+///
+/// ```
+/// # use websockets::{WebSocket, WebSocketConnection, WebSocketError};
+/// async fn greet(ws: &mut dyn WebSocketConnection) -> Result<String, WebSocketError> {
+///     ws.send_text("hello".to_string()).await?;
+///     let frame = ws.receive().await?;
+///     Ok(frame.as_text().map(|(text, _, _)| text.clone()).unwrap_or_default())
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let mut ws = WebSocket::connect("wss://echo.websocket.org").await?;
+/// greet(&mut ws).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait WebSocketConnection: Send {
+    /// Sends an already constructed [`Frame`] (see [`WebSocket::send()`]).
+    fn send(&mut self, frame: Frame) -> ConnectionFuture<'_, SendReceipt>;
+
+    /// Receives a [`Frame`], handling incoming frames (see [`WebSocket::receive()`]).
+    fn receive(&mut self) -> ConnectionFuture<'_, Frame>;
+
+    /// Sends a Close frame and closes the connection (see [`WebSocket::close()`]).
+    fn close(&mut self, payload: Option<(u16, String)>) -> ConnectionFuture<'_, ()>;
+
+    /// Sends a Text frame (see [`WebSocket::send_text()`]).
+    fn send_text(&mut self, payload: String) -> ConnectionFuture<'_, SendReceipt> {
+        self.send(Frame::text(payload))
+    }
+
+    /// Sends a Binary frame (see [`WebSocket::send_binary()`]).
+    fn send_binary(&mut self, payload: Vec<u8>) -> ConnectionFuture<'_, SendReceipt> {
+        self.send(Frame::binary(payload))
+    }
+}
+
+impl WebSocketConnection for WebSocket {
+    fn send(&mut self, frame: Frame) -> ConnectionFuture<'_, SendReceipt> {
+        Box::pin(WebSocket::send(self, frame))
+    }
+
+    fn receive(&mut self) -> ConnectionFuture<'_, Frame> {
+        Box::pin(WebSocket::receive(self))
+    }
+
+    fn close(&mut self, payload: Option<(u16, String)>) -> ConnectionFuture<'_, ()> {
+        Box::pin(WebSocket::close(self, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+
+    #[tokio::test]
+    async fn websocket_is_a_connection() {
+        let server = EchoServer::spawn().await;
+        let mut ws: Box<dyn WebSocketConnection> =
+            Box::new(WebSocket::connect(&server.url()).await.unwrap());
+        ws.send_binary(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(
+            ws.receive().await.unwrap().as_binary().unwrap().0,
+            &[1, 2, 3]
+        );
+        ws.close(Some((1000, String::new()))).await.unwrap();
+        assert_eq!(ws.receive().await.unwrap().as_close().unwrap().0, 1000);
+    }
+}
@@ -0,0 +1,282 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use super::builder::ConnectSettings;
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::connection_state::ConnectionState;
+use super::parsed_addr::ParsedAddr;
+use super::split::WebSocketWriteHalf;
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+/// The future returned by an [`on_reconnected()`](WebSocketBuilder::on_reconnected()) hook.
+pub type OnReconnectedFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<(), WebSocketError>> + Send + 'a>>;
+
+/// Runs after each successful redial, before the new WebSocket is returned.
+pub(super) type OnReconnected =
+    Arc<dyn for<'a> Fn(&'a mut WebSocketWriteHalf) -> OnReconnectedFuture<'a> + Send + Sync>;
+
+/// Information about the URL a [`WebSocket`] connected to,
+/// generated from [`WebSocket::connection_info()`].
+///
+/// The settings from the [`WebSocketBuilder`] that were used to connect are
+/// also kept, so that the WebSocket can be [redialed](ConnectionInfo::redial()).
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    url: String,
+    scheme: String,
+    host: String,
+    path: String,
+    addr: Option<SocketAddr>,
+    settings: ConnectSettings,
+    /// Set once the handshake has completed
+    pub(super) accepted_subprotocol: Option<String>,
+}
+
+impl Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        // settings are left out since they may contain secrets (such as authorization headers)
+        f.debug_struct("ConnectionInfo")
+            .field("url", &self.url)
+            .field("scheme", &self.scheme)
+            .field("host", &self.host)
+            .field("path", &self.path)
+            .field("addr", &self.addr)
+            .field("accepted_subprotocol", &self.accepted_subprotocol)
+            .finish()
+    }
+}
+
+impl ConnectionInfo {
+    pub(super) fn new(
+        url: &str,
+        parsed_addr: &ParsedAddr,
+        addr: Option<SocketAddr>,
+        settings: ConnectSettings,
+    ) -> Self {
+        Self {
+            url: url.to_string(),
+            scheme: parsed_addr.scheme.clone(),
+            host: parsed_addr.host.clone(),
+            path: parsed_addr.path.clone(),
+            addr,
+            settings,
+            accepted_subprotocol: None,
+        }
+    }
+
+    /// Connects to the same URL again (and performs a new WebSocket handshake),
+    /// returning a new [`WebSocket`]. The handshake headers, subprotocols, TLS configuration,
+    /// and other settings from the [`WebSocketBuilder`] that created the original
+    /// connection are reused. The host is resolved again, so its address may change.
+    ///
+    /// Since the application may depend on the subprotocol that was accepted by the server,
+    /// the same subprotocol is required again: if the server accepts a different subprotocol
+    /// (or none where one was accepted before, or vice versa), the new connection is shut down
+    /// and [`SubprotocolMismatchError`](WebSocketError::SubprotocolMismatchError) is returned.
+    /// Then the [`on_reconnected()`](WebSocketBuilder::on_reconnected()) hook is run,
+    /// if there is one.
+    ///
+    /// This is a building block for reconnecting after the connection is lost:
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::builder()
+    ///     .add_header("Authorization", "Bearer token")
+    ///     .connect("wss://echo.websocket.org")
+    ///     .await?;
+    /// let connection_info = ws.connection_info().clone().unwrap();
+    /// // ... the connection is lost ...
+    /// let mut ws = connection_info.redial().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn redial(&self) -> Result<WebSocket, WebSocketError> {
+        let parsed_addr = ParsedAddr::try_from(&self.url[..])?;
+        let settings = self.settings.reconnecting();
+        let mut ws = settings.connect(&self.url, parsed_addr).await?;
+        // https://tools.ietf.org/html/rfc6455#section-1.9
+        if ws.accepted_subprotocol != self.accepted_subprotocol {
+            let accepted = ws.accepted_subprotocol.take();
+            ws.shutdown().await?;
+            return Err(WebSocketError::SubprotocolMismatchError {
+                expected: self.accepted_subprotocol.clone(),
+                accepted,
+            });
+        }
+        if let Some(on_reconnected) = settings.on_reconnected() {
+            if let Err(e) = on_reconnected(&mut ws.write_half).await {
+                settings.set_state(ConnectionState::Closed);
+                return Err(e);
+            }
+        }
+        Ok(ws)
+    }
+
+    /// Returns the subprotocol that was accepted by the server during the handshake,
+    /// if any, which is required again when [redialing](ConnectionInfo::redial()).
+    pub fn accepted_subprotocol(&self) -> Option<&str> {
+        self.accepted_subprotocol.as_deref()
+    }
+
+    /// Returns the URL that was used to connect, exactly as it was given.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the scheme of the URL (`ws`, `wss`, or `ws+unix`).
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Returns the host of the URL. IPv6 addresses are enclosed in brackets.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the port that was connected to, either from the URL
+    /// or the default port for the scheme (or `None` for `ws+unix` URLs).
+    pub fn port(&self) -> Option<u16> {
+        self.addr.map(|addr| addr.port())
+    }
+
+    /// Returns the path (and query, if any) of the URL, which was
+    /// requested in the handshake.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the socket address that the host was resolved to,
+    /// or `None` for connections to Unix domain sockets (`ws+unix` URLs).
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::test_util::scripted_server_with;
+
+    /// Accepts a connection for each subprotocol, accepting that subprotocol
+    /// (or none) in the handshake, and reports the subprotocols offered by the client.
+    async fn server(
+        subprotocols: Vec<Option<&'static str>>,
+    ) -> (String, mpsc::UnboundedReceiver<String>) {
+        let responses = subprotocols
+            .into_iter()
+            .map(|subprotocol| match subprotocol {
+                Some(subprotocol) => format!("Sec-WebSocket-Protocol: {}\r\n", subprotocol),
+                None => String::new(),
+            })
+            .map(|extra_headers| (extra_headers, &b""[..]))
+            .collect();
+        let (url, mut requests) = scripted_server_with(responses).await;
+        let (offered_sender, offered_receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                for line in request {
+                    if let Some(value) = line.strip_prefix("Sec-WebSocket-Protocol:") {
+                        offered_sender.send(value.trim().to_string()).unwrap();
+                    }
+                }
+            }
+        });
+        (url, offered_receiver)
+    }
+
+    #[tokio::test]
+    async fn redial_requires_accepted_subprotocol() {
+        let (url, mut offered) = server(vec![Some("v2"), Some("v2"), Some("v1"), None]).await;
+        let ws = WebSocket::builder()
+            .add_subprotocol("v1")
+            .add_subprotocol("v2")
+            .connect(&url)
+            .await
+            .unwrap();
+        assert_eq!(offered.recv().await.unwrap(), "v1, v2");
+        let connection_info = ws.connection_info().clone().unwrap();
+        assert_eq!(connection_info.accepted_subprotocol(), Some("v2"));
+
+        let ws = connection_info.redial().await.unwrap();
+        assert_eq!(offered.recv().await.unwrap(), "v1, v2");
+        assert_eq!(ws.accepted_subprotocol().as_deref(), Some("v2"));
+
+        for accepted in [Some("v1"), None] {
+            match connection_info.redial().await {
+                Err(WebSocketError::SubprotocolMismatchError {
+                    expected,
+                    accepted: actual,
+                }) => {
+                    assert_eq!(expected.as_deref(), Some("v2"));
+                    assert_eq!(actual.as_deref(), accepted);
+                }
+                result => panic!("expected subprotocol mismatch, got {:?}", result),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn on_reconnected_runs_after_redial() {
+        let (url, _offered) = server(vec![None, None, None]).await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hook_calls = calls.clone();
+        let ws = WebSocket::builder()
+            .on_reconnected(move |write_half| {
+                let calls = hook_calls.clone();
+                Box::pin(async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    write_half.send_text("subscribe".to_string()).await?;
+                    Ok(())
+                })
+            })
+            .connect(&url)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let connection_info = ws.connection_info().clone().unwrap();
+        connection_info.redial().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        connection_info.redial().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn connection_state_follows_redials() {
+        let (url, _offered) = server(vec![None, None]).await;
+        let mut builder = WebSocket::builder();
+        let state = builder.connection_state();
+        assert_eq!(*state.borrow(), ConnectionState::Connecting);
+        let mut ws = builder.connect(&url).await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Open);
+
+        ws.close(None).await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Closing);
+        let mut redialed = ws
+            .connection_info()
+            .clone()
+            .unwrap()
+            .redial()
+            .await
+            .unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Open);
+        // the replaced connection no longer changes the state
+        ws.shutdown().await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Open);
+        redialed.shutdown().await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Closed);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+#[allow(unused_imports)] // for intra doc links
+use super::connection_info::ConnectionInfo;
+#[allow(unused_imports)] // for intra doc links
+use super::WebSocket;
+
+/// The state of a WebSocket connection, watched with [`WebSocket::connection_state()`]
+/// (or [`WebSocketBuilder::connection_state()`], to also watch the first connection attempt).
+///
+/// The state is shared by a connection and the connections [redialed](ConnectionInfo::redial())
+/// from it, so a single receiver follows the connection across reconnects:
+///
+/// ```
+/// # use websockets::{ConnectionState, WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+/// let mut state = ws.connection_state();
+/// tokio::spawn(async move {
+///     while state.changed().await.is_ok() {
+///         let current = *state.borrow();
+///         println!("connection is now {:?}", current);
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The first connection attempt is in progress
+    Connecting,
+    /// The WebSocket handshake has completed, and frames can be sent and received
+    Open,
+    /// A Close frame has been sent, and the server's Close frame has not been received yet
+    Closing,
+    /// The connection has terminated, or the connection attempt failed
+    Closed,
+    /// A [redial](ConnectionInfo::redial()) is in progress
+    Reconnecting,
+}
+
+/// Publishes the [`ConnectionState`] of a connection and the connections redialed from it.
+///
+/// Each connection attempt starts a new generation, and updates from connections of
+/// earlier generations are ignored, so that a connection which was replaced by a redial
+/// (but not dropped yet) cannot change the state of its replacement.
+#[derive(Clone)]
+pub(super) struct StateTracker {
+    sender: Arc<watch::Sender<ConnectionState>>,
+    current_generation: Arc<AtomicU64>,
+    generation: u64,
+}
+
+impl Debug for StateTracker {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("StateTracker")
+            .field("state", &*self.sender.borrow())
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl StateTracker {
+    pub(super) fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(ConnectionState::Connecting).0),
+            current_generation: Arc::new(AtomicU64::new(0)),
+            generation: 0,
+        }
+    }
+
+    pub(super) fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.sender.subscribe()
+    }
+
+    /// Starts a new generation for a redial, returning the tracker for it.
+    pub(super) fn reconnecting(&self) -> Self {
+        let generation = self.current_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let tracker = Self {
+            generation,
+            ..self.clone()
+        };
+        tracker.set(ConnectionState::Reconnecting);
+        tracker
+    }
+
+    /// Publishes a new state, unless the connection has been replaced by a redial
+    /// or the transition does not make sense (only open connections can start closing).
+    pub(super) fn set(&self, state: ConnectionState) {
+        if self.generation != self.current_generation.load(Ordering::Relaxed) {
+            return;
+        }
+        self.sender.send_if_modified(|current| {
+            if *current == state
+                || (state == ConnectionState::Closing && *current != ConnectionState::Open)
+            {
+                return false;
+            }
+            *current = state;
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_connections_cannot_change_the_state() {
+        let tracker = StateTracker::new();
+        let state = tracker.subscribe();
+        tracker.set(ConnectionState::Open);
+        let redialed = tracker.reconnecting();
+        assert_eq!(*state.borrow(), ConnectionState::Reconnecting);
+        // the replaced connection is shut down after the redial started
+        tracker.set(ConnectionState::Closed);
+        assert_eq!(*state.borrow(), ConnectionState::Reconnecting);
+        redialed.set(ConnectionState::Open);
+        redialed.set(ConnectionState::Closed);
+        // a connection which has closed cannot start closing
+        redialed.set(ConnectionState::Closing);
+        assert_eq!(*state.borrow(), ConnectionState::Closed);
+    }
+}
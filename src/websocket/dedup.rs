@@ -0,0 +1,130 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::sync::{Arc, Mutex as StdMutex};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::frame::Frame;
+
+/// Extracts the sequence ID of a received message from its first frame
+type SequenceId = Arc<dyn Fn(&Frame) -> Option<u64> + Send + Sync>;
+
+/// The duplicate suppression set with [`WebSocketBuilder::suppress_duplicates()`], shared by
+/// every connection made with the builder's settings (including redialed connections).
+#[derive(Clone)]
+pub(super) struct DuplicateFilter {
+    sequence_id: SequenceId,
+    window: usize,
+    state: Arc<StdMutex<FilterState>>,
+}
+
+impl Debug for DuplicateFilter {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("DuplicateFilter")
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct FilterState {
+    /// The most recent sequence IDs, oldest first
+    recent: VecDeque<u64>,
+    /// The same IDs as `recent`, for fast lookups
+    seen: HashSet<u64>,
+    /// Whether the continuation frames of the current message are being suppressed
+    suppressing_message: bool,
+}
+
+impl DuplicateFilter {
+    pub(super) fn new(window: usize, sequence_id: SequenceId) -> Self {
+        Self {
+            sequence_id,
+            window,
+            state: Arc::new(StdMutex::new(FilterState::default())),
+        }
+    }
+
+    /// Returns whether a received frame belongs to a message whose sequence ID
+    /// is in the window, remembering the sequence ID otherwise.
+    pub(super) fn is_duplicate(&self, frame: &Frame) -> bool {
+        let (continuation, fin) = match frame.data_frame_info() {
+            Some((_message_type, _payload_len, continuation, fin)) => (continuation, fin),
+            // control frames are never suppressed
+            None => return false,
+        };
+        let mut state = self.state.lock().unwrap();
+        // https://tools.ietf.org/html/rfc6455#section-5.4
+        if continuation {
+            let duplicate = state.suppressing_message;
+            if fin {
+                state.suppressing_message = false;
+            }
+            return duplicate;
+        }
+        let duplicate = match (self.sequence_id)(frame) {
+            Some(id) if state.seen.contains(&id) => true,
+            Some(id) => {
+                state.recent.push_back(id);
+                state.seen.insert(id);
+                if state.recent.len() > self.window {
+                    if let Some(oldest) = state.recent.pop_front() {
+                        state.seen.remove(&oldest);
+                    }
+                }
+                false
+            }
+            None => false,
+        };
+        state.suppressing_message = duplicate && !fin;
+        duplicate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(frame: &Frame) -> Option<u64> {
+        frame.as_text()?.0.split(':').next()?.parse().ok()
+    }
+
+    #[test]
+    fn duplicates_in_window_are_suppressed() {
+        let filter = DuplicateFilter::new(2, Arc::new(id));
+        let duplicate = |payload: &str| filter.is_duplicate(&Frame::text(payload.to_string()));
+        assert!(!duplicate("1:a"));
+        assert!(!duplicate("2:b"));
+        assert!(duplicate("1:a"));
+        assert!(duplicate("2:b"));
+        assert!(!duplicate("no id"));
+        assert!(!duplicate("no id"));
+        assert!(!duplicate("3:c"));
+        // 1 has left the window
+        assert!(!duplicate("1:a"));
+        assert!(!filter.is_duplicate(&Frame::ping(None)));
+    }
+
+    #[test]
+    fn fragments_of_duplicates_are_suppressed() {
+        let filter = DuplicateFilter::new(8, Arc::new(id));
+        let fragments = |first: &str| {
+            [
+                Frame::text(first.to_string()).set_fin(false),
+                Frame::text("b".to_string())
+                    .set_continuation(true)
+                    .set_fin(false),
+                Frame::text("c".to_string()).set_continuation(true),
+            ]
+        };
+        for frame in &fragments("1:a") {
+            assert!(!filter.is_duplicate(frame));
+        }
+        for frame in &fragments("1:a") {
+            assert!(filter.is_duplicate(frame));
+        }
+        for frame in &fragments("2:a") {
+            assert!(!filter.is_duplicate(frame));
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use rand::Rng;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::parsed_addr::ParsedAddr;
+use crate::error::WebSocketError;
+
+/// A target of the SRV records of a URL's host, which the connection may be made to.
+/// See [`WebSocketBuilder::dns_srv_lookup()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    /// The domain name of the target
+    pub target: String,
+    /// The port the target is listening on
+    pub port: u16,
+    /// The priority of the target; targets with lower priorities are tried first
+    pub priority: u16,
+    /// The weight of the target, relative to other targets with the same priority
+    pub weight: u16,
+}
+
+/// Called with the SRV target that the connection was made to
+pub(super) type OnSrvTarget = Arc<dyn Fn(&SrvTarget) + Send + Sync>;
+
+/// The resolver set with [`WebSocketBuilder::dns_resolver()`] (or the system's
+/// configuration, if only [`WebSocketBuilder::dns_srv_lookup()`] was set).
+#[derive(Clone)]
+pub(super) struct DnsResolver {
+    resolver: TokioAsyncResolver,
+    srv_lookup: bool,
+    on_srv_target: Option<OnSrvTarget>,
+}
+
+impl Debug for DnsResolver {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("DnsResolver")
+            .field("srv_lookup", &self.srv_lookup)
+            .finish()
+    }
+}
+
+impl DnsResolver {
+    pub(super) fn new(
+        resolver: Option<TokioAsyncResolver>,
+        srv_lookup: bool,
+        on_srv_target: Option<OnSrvTarget>,
+    ) -> Result<Self, WebSocketError> {
+        let resolver = match resolver {
+            Some(resolver) => resolver,
+            None => {
+                TokioAsyncResolver::tokio_from_system_conf().map_err(WebSocketError::DnsError)?
+            }
+        };
+        Ok(Self {
+            resolver,
+            srv_lookup,
+            on_srv_target,
+        })
+    }
+
+    /// Resolves the host and opens a connection to it with `open`. If SRV lookups are enabled
+    /// and the host has SRV records, each target is tried in turn until a connection
+    /// is opened, and the error of the last target is returned if none of them can be.
+    pub(super) async fn connect<S, F, Fut>(
+        &self,
+        parsed_addr: &ParsedAddr,
+        open: F,
+    ) -> Result<(S, SocketAddr), WebSocketError>
+    where
+        F: Fn(SocketAddr) -> Fut,
+        Fut: Future<Output = Result<S, WebSocketError>>,
+    {
+        // IP addresses are used as they are
+        if let Ok(ip) = parsed_addr.server_name.parse::<IpAddr>() {
+            let addr = SocketAddr::new(ip, parsed_addr.port);
+            return Ok((open(addr).await?, addr));
+        }
+        let targets = match self.srv_lookup {
+            true => self.lookup_srv(parsed_addr).await?,
+            false => Vec::new(),
+        };
+        if targets.is_empty() {
+            let addr = self
+                .lookup(&parsed_addr.server_name, parsed_addr.port)
+                .await?;
+            return Ok((open(addr).await?, addr));
+        }
+        let mut last_error = WebSocketError::ResolutionError;
+        for target in targets {
+            let addr = match self.lookup(&target.target, target.port).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    last_error = e;
+                    continue;
+                }
+            };
+            match open(addr).await {
+                Ok(stream) => {
+                    if let Some(on_srv_target) = &self.on_srv_target {
+                        on_srv_target(&target);
+                    }
+                    return Ok((stream, addr));
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Resolves a host name to a socket address.
+    async fn lookup(&self, host: &str, port: u16) -> Result<SocketAddr, WebSocketError> {
+        let ip = self
+            .resolver
+            .lookup_ip(host)
+            .await
+            .map_err(WebSocketError::DnsError)?
+            .iter()
+            .next()
+            .ok_or(WebSocketError::ResolutionError)?;
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    /// Looks up the `_ws._tcp` or `_wss._tcp` SRV records of the host, returning
+    /// their targets in the order they should be tried (which is empty if the
+    /// host has no SRV records).
+    async fn lookup_srv(&self, parsed_addr: &ParsedAddr) -> Result<Vec<SrvTarget>, WebSocketError> {
+        // https://tools.ietf.org/html/rfc2782
+        let name = format!(
+            "_{}._tcp.{}.",
+            parsed_addr.scheme,
+            parsed_addr.server_name.trim_end_matches('.')
+        );
+        let lookup = match self.resolver.srv_lookup(name).await {
+            Ok(lookup) => lookup,
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(WebSocketError::DnsError(e)),
+        };
+        let targets: Vec<_> = lookup
+            .iter()
+            // a target of "." means that the service is not available at the domain
+            .filter(|srv| !srv.target().is_root())
+            .map(|srv| SrvTarget {
+                target: srv.target().to_utf8(),
+                port: srv.port(),
+                priority: srv.priority(),
+                weight: srv.weight(),
+            })
+            .collect();
+        if targets.is_empty() {
+            return Err(WebSocketError::ResolutionError);
+        }
+        Ok(order_targets(targets, &mut rand::thread_rng()))
+    }
+}
+
+/// Orders SRV targets by priority, and randomly by weight among targets with the same priority,
+/// so that targets with greater weights are more likely to be tried first.
+fn order_targets<R: Rng>(mut targets: Vec<SrvTarget>, rng: &mut R) -> Vec<SrvTarget> {
+    // https://tools.ietf.org/html/rfc2782 ("Usage rules")
+    // zero weight targets are placed first, so that they are only chosen when
+    // the random number is 0
+    targets.sort_by_key(|target| (target.priority, target.weight != 0));
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].priority;
+        let same_priority = targets
+            .iter()
+            .take_while(|target| target.priority == priority)
+            .count();
+        let total_weight: u32 = targets[..same_priority]
+            .iter()
+            .map(|target| target.weight as u32)
+            .sum();
+        let chosen = rng.gen_range(0, total_weight + 1);
+        let mut running_weight = 0;
+        let i = targets[..same_priority]
+            .iter()
+            .position(|target| {
+                running_weight += target.weight as u32;
+                running_weight >= chosen
+            })
+            .unwrap_or(0);
+        ordered.push(targets.remove(i));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+
+    fn target(name: &str, priority: u16, weight: u16) -> SrvTarget {
+        SrvTarget {
+            target: name.to_string(),
+            port: 443,
+            priority,
+            weight,
+        }
+    }
+
+    #[test]
+    fn targets_are_ordered_by_priority_then_weight() {
+        let targets = vec![
+            target("backup", 20, 0),
+            target("heavy", 10, 90),
+            target("light", 10, 10),
+            target("unweighted", 10, 0),
+        ];
+        let mut rng = ChaCha20Rng::from_seed([0; 32]);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let ordered = order_targets(targets.clone(), &mut rng);
+            assert_eq!(ordered.len(), 4);
+            assert_eq!(ordered[3].target, "backup");
+            if ordered[0].target == "heavy" {
+                heavy_first += 1;
+            }
+        }
+        // heavy has 90 of the 101 chances to be chosen first
+        assert!((800..980).contains(&heavy_first), "{}", heavy_first);
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use flume::Receiver;
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant, Interval};
+use tokio_util::sync::CancellationToken;
+
+use super::frame::Frame;
+use super::logging::Logger;
+use super::split::{Activity, Event, Writer};
+#[allow(unused_imports)] // for intra doc links
+use super::{builder::WebSocketBuilder, WebSocket};
+use crate::error::WebSocketError;
+
+/// Keepalive settings, configured on the [`WebSocketBuilder`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Keepalive {
+    pub(super) interval: Duration,
+    pub(super) timeout: Duration,
+}
+
+/// Application-level heartbeat settings, configured on the [`WebSocketBuilder`].
+#[derive(Clone)]
+pub(super) struct AppHeartbeat {
+    pub(super) interval: Duration,
+    pub(super) frame_factory: Arc<dyn Fn() -> Frame + Send + Sync>,
+}
+
+impl Debug for AppHeartbeat {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("AppHeartbeat")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Drives a split WebSocket connection, generated from [`WebSocket::split_with_driver()`].
+///
+/// The driver flushes events from the read half (sending Pong frames and echoing
+/// Close frames) so that the write half does not need to. If a
+/// [keepalive interval](WebSocketBuilder::keepalive_interval()) was set, the driver
+/// also sends Ping frames at that interval, and resolves with a
+/// [`PongTimeoutError`](WebSocketError::PongTimeoutError) if the server does not
+/// respond with a Pong frame within the [keepalive timeout](WebSocketBuilder::keepalive_timeout()).
+/// If an [application heartbeat](WebSocketBuilder::app_heartbeat()) was set,
+/// the driver sends the heartbeat frames at its interval.
+/// If a [shutdown token](WebSocketBuilder::shutdown_token()) was set, the driver
+/// sends a Close frame and shuts down the WebSocket once the token is cancelled.
+///
+/// The driver does nothing unless it is polled, so it should be spawned:
+///
+/// ```
+/// # use std::time::Duration;
+/// # use websockets::{WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let ws = WebSocket::builder()
+///     .keepalive_interval(Some(Duration::from_secs(30)))
+///     .connect("wss://echo.websocket.org")
+///     .await?;
+/// let (mut read_half, mut write_half, driver) = ws.split_with_driver();
+/// tokio::spawn(driver);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The driver resolves once the WebSocket has been shut down
+/// or the read half has been dropped.
+pub struct ConnectionDriver {
+    future: Pin<Box<dyn Future<Output = Result<(), WebSocketError>> + Send>>,
+}
+
+impl Debug for ConnectionDriver {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("ConnectionDriver")
+    }
+}
+
+impl ConnectionDriver {
+    pub(super) fn new(
+        writer: Arc<Mutex<Writer>>,
+        receiver: Receiver<Event>,
+        activity: Arc<Activity>,
+        keepalive: Option<Keepalive>,
+        app_heartbeat: Option<AppHeartbeat>,
+        shutdown_token: Option<CancellationToken>,
+        logger: Logger,
+    ) -> Self {
+        Self {
+            future: Box::pin(drive(
+                writer,
+                receiver,
+                activity,
+                keepalive,
+                app_heartbeat,
+                shutdown_token,
+                logger,
+            )),
+        }
+    }
+}
+
+impl Future for ConnectionDriver {
+    type Output = Result<(), WebSocketError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+async fn drive(
+    writer: Arc<Mutex<Writer>>,
+    receiver: Receiver<Event>,
+    activity: Arc<Activity>,
+    keepalive: Option<Keepalive>,
+    app_heartbeat: Option<AppHeartbeat>,
+    shutdown_token: Option<CancellationToken>,
+    logger: Logger,
+) -> Result<(), WebSocketError> {
+    let mut ping_interval = keepalive.map(|keepalive| {
+        time::interval_at(Instant::now() + keepalive.interval, keepalive.interval)
+    });
+    let mut heartbeat_interval = app_heartbeat.as_ref().map(|app_heartbeat| {
+        time::interval_at(
+            Instant::now() + app_heartbeat.interval,
+            app_heartbeat.interval,
+        )
+    });
+    // set while a keepalive ping is waiting for its pong
+    let mut pong_deadline: Option<Instant> = None;
+    let mut pongs = activity.pong_listener();
+
+    loop {
+        tokio::select! {
+            event = receiver.recv_async() => match event {
+                Ok(event) => writer.lock().await.handle_event(event).await?,
+                // read half was dropped, so there will be no more events
+                Err(_) => return Ok(()),
+            },
+            // the activity (and so the sender) outlives the driver
+            Ok(()) = pongs.changed() => pong_deadline = None,
+            _ = tick(&mut ping_interval) => {
+                // don't stack up pings if the previous one is still unanswered
+                if pong_deadline.is_none() {
+                    let mut writer = writer.lock().await;
+                    if writer.shutdown || writer.sent_closed {
+                        return Ok(());
+                    }
+                    // only Pongs received from now on answer this Ping
+                    pongs.borrow_and_update();
+                    // https://tools.ietf.org/html/rfc6455#section-5.5.2
+                    Frame::Ping { payload: None }.send(&mut writer).await?;
+                    if let Some(keepalive) = keepalive {
+                        pong_deadline = Some(Instant::now() + keepalive.timeout);
+                    }
+                }
+            },
+            _ = tick(&mut heartbeat_interval) => {
+                if let Some(app_heartbeat) = &app_heartbeat {
+                    let mut writer = writer.lock().await;
+                    if writer.shutdown || writer.sent_closed {
+                        return Ok(());
+                    }
+                    (app_heartbeat.frame_factory)().send(&mut writer).await?;
+                }
+            },
+            _ = sleep_until(pong_deadline) => {
+                logger.warn(format_args!("websocket server did not respond to keepalive ping"));
+                return Err(WebSocketError::PongTimeoutError);
+            },
+            _ = cancelled(&shutdown_token) => {
+                let mut writer = writer.lock().await;
+                if !writer.shutdown && !writer.sent_closed {
+                    if !writer.sent_close_frame {
+                        // https://tools.ietf.org/html/rfc6455#section-7.4.1
+                        Frame::close(Some((1001, String::new())))
+                        .send(&mut writer)
+                        .await?;
+                    }
+                    writer.shutdown().await?;
+                }
+                return Ok(());
+            },
+        }
+
+        let writer = writer.lock().await;
+        if writer.shutdown || writer.sent_closed {
+            return Ok(());
+        }
+    }
+}
+
+/// Waits for the next tick of the interval, or forever if there is no interval.
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits until the token is cancelled, or forever if there is no token.
+async fn cancelled(shutdown_token: &Option<CancellationToken>) {
+    match shutdown_token {
+        Some(shutdown_token) => shutdown_token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits until the deadline, or forever if there is no deadline.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_util::{recording_server, scripted_server, EchoServer};
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_times_out_with_paused_clock() {
+        let url = scripted_server(b"").await;
+        let ws = WebSocket::builder()
+            .keepalive_interval(Some(Duration::from_secs(30)))
+            .keepalive_timeout(Duration::from_secs(10))
+            .connect(&url)
+            .await
+            .unwrap();
+        let (_read_half, _write_half, driver) = ws.split_with_driver();
+
+        let start = Instant::now();
+        let result = driver.await;
+        assert!(matches!(result, Err(WebSocketError::PongTimeoutError)));
+        assert_eq!(start.elapsed(), Duration::from_secs(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn app_heartbeat_is_sent_at_interval() {
+        let url = scripted_server(b"").await;
+        let heartbeats = Arc::new(AtomicUsize::new(0));
+        let counter = heartbeats.clone();
+        let ws = WebSocket::builder()
+            .app_heartbeat(Duration::from_secs(10), move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Frame::text("heartbeat".to_string())
+            })
+            .connect(&url)
+            .await
+            .unwrap();
+        let (_read_half, _write_half, driver) = ws.split_with_driver();
+        tokio::spawn(driver);
+
+        time::sleep(Duration::from_secs(25)).await;
+        assert_eq!(heartbeats.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn cancelling_after_close_sends_no_second_close_frame() {
+        let (url, opcodes) = recording_server().await;
+        let token = CancellationToken::new();
+        let ws = WebSocket::builder()
+            .shutdown_token(token.clone())
+            .connect(&url)
+            .await
+            .unwrap();
+        let (read_half, mut write_half, driver) = ws.split_with_driver();
+        write_half.close(None).await.unwrap();
+        token.cancel();
+        driver.await.unwrap();
+        drop((read_half, write_half));
+        assert_eq!(opcodes.await.unwrap(), vec![0x8]);
+    }
+
+    /// Sends and receives on the halves for a second, while the driver sends
+    /// keepalive Pings which must be answered within the timeout
+    async fn exchange_with_keepalive(respond_control: bool) {
+        let server = EchoServer::spawn().await;
+        let ws = WebSocket::builder()
+            .keepalive_interval(Some(Duration::from_millis(300)))
+            .keepalive_timeout(Duration::from_millis(200))
+            .connect(&server.url())
+            .await
+            .unwrap();
+        let (mut read_half, mut write_half, driver) = ws.split_with_driver();
+        if respond_control {
+            read_half.respond_control(Some(write_half.event_pump()));
+        }
+        let driver = tokio::spawn(driver);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            write_half.send_text("hello".to_string()).await.unwrap();
+            loop {
+                let frame = read_half.receive().await.unwrap();
+                // flush before the driver can run, which must not take the Pong away from it
+                write_half.flush().await.unwrap();
+                if frame.is_text() {
+                    break;
+                }
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!driver.is_finished());
+        driver.abort();
+    }
+
+    #[tokio::test]
+    async fn keepalive_pongs_are_seen_while_sending() {
+        exchange_with_keepalive(false).await;
+    }
+
+    #[tokio::test]
+    async fn keepalive_pongs_are_seen_with_an_event_pump() {
+        exchange_with_keepalive(true).await;
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::sync::Arc;
+
+use rand::rngs::OsRng;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+
+type FillBytes = Arc<dyn Fn(&mut [u8]) + Send + Sync>;
+
+/// Where the random bytes for the `Sec-WebSocket-Key` handshake header and
+/// for masking keys come from. Set with [`WebSocketBuilder::entropy()`].
+///
+/// A new `Sec-WebSocket-Key` is generated for every handshake
+/// (including [redials](crate::ConnectionInfo::redial())); keys are never reused.
+#[derive(Clone, Default)]
+pub enum EntropyConfig {
+    /// A ChaCha20 generator for each connection, seeded from the operating system's
+    /// entropy source. This is the default.
+    #[default]
+    Seeded,
+    /// The operating system's entropy source, used directly for every key
+    Os,
+    /// The `rand` crate's thread-local generator
+    ThreadRng,
+    /// A ChaCha20 generator seeded with the given seed. Every connection generates
+    /// the same keys, so this should only be used for testing.
+    FixedSeed([u8; 32]),
+    /// A function which fills the given buffer with random bytes, which can be used to
+    /// route randomness through an approved source
+    Custom(FillBytes),
+}
+
+impl Debug for EntropyConfig {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self {
+            Self::Seeded => f.write_str("Seeded"),
+            Self::Os => f.write_str("Os"),
+            Self::ThreadRng => f.write_str("ThreadRng"),
+            // don't reveal the seed
+            Self::FixedSeed(_) => f.write_str("FixedSeed"),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Generates random bytes for a connection as configured by an [`EntropyConfig`]
+pub(super) enum EntropySource {
+    ChaCha20(Box<ChaCha20Rng>),
+    Os,
+    ThreadRng,
+    Custom(FillBytes),
+}
+
+impl Debug for EntropySource {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("EntropySource")
+    }
+}
+
+impl EntropySource {
+    pub(super) fn new(config: &EntropyConfig) -> Self {
+        match config {
+            EntropyConfig::Seeded => Self::ChaCha20(Box::new(ChaCha20Rng::from_entropy())),
+            EntropyConfig::Os => Self::Os,
+            EntropyConfig::ThreadRng => Self::ThreadRng,
+            EntropyConfig::FixedSeed(seed) => {
+                Self::ChaCha20(Box::new(ChaCha20Rng::from_seed(*seed)))
+            }
+            EntropyConfig::Custom(fill_bytes) => Self::Custom(fill_bytes.clone()),
+        }
+    }
+
+    pub(super) fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::ChaCha20(rng) => rng.fill_bytes(dest),
+            Self::Os => OsRng.fill_bytes(dest),
+            Self::ThreadRng => rand::thread_rng().fill_bytes(dest),
+            Self::Custom(fill_bytes) => fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_seed_is_deterministic() {
+        let config = EntropyConfig::FixedSeed([7; 32]);
+        let mut a = [0; 16];
+        let mut b = [0; 16];
+        EntropySource::new(&config).fill_bytes(&mut a);
+        EntropySource::new(&config).fill_bytes(&mut b);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn custom_source_is_used() {
+        let config = EntropyConfig::Custom(Arc::new(|dest: &mut [u8]| {
+            dest.iter_mut().for_each(|b| *b = 0xAB)
+        }));
+        let mut key = [0; 4];
+        EntropySource::new(&config).fill_bytes(&mut key);
+        assert_eq!(key, [0xAB; 4]);
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Sleep};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+#[allow(unused_imports)] // for intra doc links
+use super::connection_info::ConnectionInfo;
+use super::stream::Stream;
+
+/// Injects faults into connections at random, for chaos testing how an application copes
+/// with unreliable networks. Set with [`WebSocketBuilder::fault_injector()`]
+/// (available with the `test-util` feature).
+///
+/// Each fault has a probability of being injected into each read from or write to the
+/// connection (after the TLS handshake, if any, so TLS sessions see the faults as
+/// corrupted or lost traffic). The random numbers are generated from a seed,
+/// so a failing test can be run again with the same faults.
+/// An injector is shared by every connection made with the builder's settings
+/// (including connections [redialed](ConnectionInfo::redial()) after a fault),
+/// and by every clone of it.
+///
+/// ```
+/// # use websockets::test_util::{EchoServer, FaultInjector};
+/// # use websockets::{WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let server = EchoServer::spawn().await;
+/// let faults = FaultInjector::new(42);
+/// let mut ws = WebSocket::builder()
+///     .fault_injector(faults.clone())
+///     .connect(&server.url())
+///     .await?;
+/// // the connection is lost in the middle of the next frame sent
+/// faults.truncate_frames(1.0);
+/// assert!(ws.send_text("hello".to_string()).await.is_err());
+/// assert_eq!(faults.faults_injected(), 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FaultInjector(Arc<StdMutex<InjectorState>>);
+
+impl Debug for FaultInjector {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        let state = self.0.lock().unwrap();
+        f.debug_struct("FaultInjector")
+            .field("delay_writes", &state.delay_writes)
+            .field("truncate_frames", &state.truncate_frames)
+            .field("drop_connection", &state.drop_connection)
+            .field("faults_injected", &state.faults_injected)
+            .finish()
+    }
+}
+
+struct InjectorState {
+    delay_writes: Option<(f64, Duration)>,
+    truncate_frames: f64,
+    drop_connection: f64,
+    rng: ChaCha20Rng,
+    faults_injected: u64,
+}
+
+impl InjectorState {
+    /// Returns whether a fault with the given probability should be injected,
+    /// counting it if so.
+    fn roll(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        let inject = self.rng.gen_bool(probability);
+        if inject {
+            self.faults_injected += 1;
+        }
+        inject
+    }
+}
+
+impl FaultInjector {
+    /// Creates an injector which generates its random numbers from `seed`,
+    /// and injects no faults until they are configured.
+    pub fn new(seed: u64) -> Self {
+        Self(Arc::new(StdMutex::new(InjectorState {
+            delay_writes: None,
+            truncate_frames: 0.0,
+            drop_connection: 0.0,
+            rng: ChaCha20Rng::seed_from_u64(seed),
+            faults_injected: 0,
+        })))
+    }
+
+    /// Delays writes by `delay` with the given probability. Defaults to 0.
+    ///
+    /// Like the other probabilities, this can be changed while connections are open,
+    /// for example to inject faults only after the handshake has completed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0 and 1.
+    pub fn delay_writes(&self, probability: f64, delay: Duration) -> &Self {
+        check_probability(probability);
+        self.0.lock().unwrap().delay_writes = Some((probability, delay));
+        self
+    }
+
+    /// Writes only part of the bytes given to a write with the given probability,
+    /// then drops the connection, as if it was lost in the middle of sending a frame.
+    /// Defaults to 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0 and 1.
+    pub fn truncate_frames(&self, probability: f64) -> &Self {
+        check_probability(probability);
+        self.0.lock().unwrap().truncate_frames = probability;
+        self
+    }
+
+    /// Drops the connection before a read or write with the given probability.
+    /// Every later read and write fails with a connection reset error. Defaults to 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0 and 1.
+    pub fn drop_connection(&self, probability: f64) -> &Self {
+        check_probability(probability);
+        self.0.lock().unwrap().drop_connection = probability;
+        self
+    }
+
+    /// Returns the number of faults injected so far.
+    pub fn faults_injected(&self) -> u64 {
+        self.0.lock().unwrap().faults_injected
+    }
+
+    /// Returns whether the connection should be dropped now.
+    fn roll_drop(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        let probability = state.drop_connection;
+        state.roll(probability)
+    }
+
+    /// Decides the fault for a write.
+    fn roll_write(&self) -> WriteFault {
+        let mut state = self.0.lock().unwrap();
+        if let Some((probability, delay)) = state.delay_writes {
+            if state.roll(probability) {
+                return WriteFault::Delay(Box::pin(time::sleep(delay)));
+            }
+        }
+        let probability = state.truncate_frames;
+        match state.roll(probability) {
+            true => WriteFault::Truncate,
+            false => WriteFault::None,
+        }
+    }
+
+    /// Chooses how many of `len` bytes a truncated write writes.
+    fn truncated_len(&self, len: usize) -> usize {
+        self.0.lock().unwrap().rng.gen_range(0, len)
+    }
+}
+
+fn check_probability(probability: f64) {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability must be between 0 and 1"
+    );
+}
+
+fn dropped_error() -> IoError {
+    IoError::new(
+        ErrorKind::ConnectionReset,
+        "connection dropped by fault injector",
+    )
+}
+
+/// The fault decided for a write, which holds until the write completes
+/// (since a write may be polled several times)
+#[derive(Debug)]
+enum WriteFault {
+    None,
+    Delay(Pin<Box<Sleep>>),
+    Truncate,
+}
+
+/// A stream which injects faults from a [`FaultInjector`] into another stream
+#[derive(Debug)]
+pub(super) struct FaultyStream {
+    stream: Stream,
+    injector: FaultInjector,
+    /// The fault for the write in progress, if it has been decided
+    write_fault: Option<WriteFault>,
+    dropped: bool,
+}
+
+impl FaultyStream {
+    pub(super) fn new(stream: Stream, injector: FaultInjector) -> Self {
+        Self {
+            stream,
+            injector,
+            write_fault: None,
+            dropped: false,
+        }
+    }
+
+    pub(super) fn get_ref(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Fails if the connection has been dropped, or if the injector decides to drop it now.
+    fn check_dropped(&mut self) -> Result<(), IoError> {
+        if !self.dropped && self.injector.roll_drop() {
+            self.dropped = true;
+        }
+        match self.dropped {
+            true => Err(dropped_error()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        if this.dropped {
+            return Poll::Ready(Err(dropped_error()));
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.stream).poll_read(cx, buf))?;
+        if buf.filled().len() > filled {
+            // the bytes which were read are lost along with the connection
+            if let Err(e) = this.check_dropped() {
+                buf.set_filled(filled);
+                return Poll::Ready(Err(e));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for FaultyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        if this.write_fault.is_none() {
+            this.check_dropped()?;
+            this.write_fault = Some(this.injector.roll_write());
+        }
+        let result = match this.write_fault.as_mut() {
+            Some(WriteFault::Delay(sleep)) => {
+                ready!(sleep.as_mut().poll(cx));
+                this.write_fault = Some(WriteFault::None);
+                Pin::new(&mut this.stream).poll_write(cx, buf)
+            }
+            Some(WriteFault::Truncate) if !buf.is_empty() => {
+                let len = this.injector.truncated_len(buf.len());
+                let result = match len {
+                    0 => Poll::Ready(Err(dropped_error())),
+                    len => Pin::new(&mut this.stream).poll_write(cx, &buf[..len]),
+                };
+                if result.is_ready() {
+                    this.dropped = true;
+                }
+                result
+            }
+            _ => Pin::new(&mut this.stream).poll_write(cx, buf),
+        };
+        if result.is_ready() {
+            this.write_fault = None;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        if this.dropped {
+            return Poll::Ready(Err(dropped_error()));
+        }
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+    use crate::{WebSocket, WebSocketError};
+
+    #[tokio::test(start_paused = true)]
+    async fn faults_are_injected_into_open_connections() {
+        let server = EchoServer::spawn().await;
+        let faults = FaultInjector::new(7);
+        let mut ws = WebSocket::builder()
+            .fault_injector(faults.clone())
+            .connect(&server.url())
+            .await
+            .unwrap();
+
+        faults.delay_writes(1.0, Duration::from_secs(5));
+        let started = time::Instant::now();
+        ws.send_text("slow".to_string()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(5));
+
+        faults
+            .delay_writes(0.0, Duration::ZERO)
+            .drop_connection(1.0);
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::ReadError(_))
+        ));
+        assert_eq!(faults.faults_injected(), 2);
+    }
+}
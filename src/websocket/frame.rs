@@ -1,17 +1,111 @@
-use std::convert::TryInto;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+use std::ops::{BitOr, BitOrAssign, Not};
 
-use rand::RngCore;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
-use super::split::{WebSocketReadHalf, WebSocketWriteHalf};
+use super::close_code::CloseCode;
+use super::codec::{encode_payload_len, FrameDecoder, LenEncoding};
+use super::connection_state::ConnectionState;
+use super::recording::FrameDirection;
+use super::split::{SendReceipt, Writer};
 use super::FrameType;
 #[allow(unused_imports)] // for intra doc links
 use super::WebSocket;
 use crate::error::WebSocketError;
 
-const U16_MAX_MINUS_ONE: usize = (u16::MAX - 1) as usize;
-const U16_MAX: usize = u16::MAX as usize;
-const U64_MAX_MINUS_ONE: usize = (u64::MAX - 1) as usize;
+/// The type of a message, which is made up of one or more data frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A message made up of Text frames
+    Text,
+    /// A message made up of Binary frames
+    Binary,
+}
+
+/// A set of frame types, used to filter which frames are returned when receiving
+/// (see [`WebSocketReadHalf::set_frame_filter()`](crate::WebSocketReadHalf::set_frame_filter())).
+///
+/// Sets can be combined with `|`:
+///
+/// ```
+/// # use websockets::OpcodeSet;
+/// let set = OpcodeSet::TEXT | OpcodeSet::BINARY;
+/// assert_eq!(set, OpcodeSet::DATA);
+/// assert!(!set.contains(OpcodeSet::PING));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpcodeSet(u8);
+
+impl OpcodeSet {
+    /// No frame types
+    pub const EMPTY: Self = Self(0);
+    /// Text frames, including Text continuation frames
+    pub const TEXT: Self = Self(1 << 0);
+    /// Binary frames, including Binary continuation frames
+    pub const BINARY: Self = Self(1 << 1);
+    /// Close frames
+    pub const CLOSE: Self = Self(1 << 2);
+    /// Ping frames
+    pub const PING: Self = Self(1 << 3);
+    /// Pong frames
+    pub const PONG: Self = Self(1 << 4);
+    /// Text and Binary frames
+    pub const DATA: Self = Self(Self::TEXT.0 | Self::BINARY.0);
+    /// Close, Ping, and Pong frames
+    pub const CONTROL: Self = Self(Self::CLOSE.0 | Self::PING.0 | Self::PONG.0);
+    /// All frame types
+    pub const ALL: Self = Self(Self::DATA.0 | Self::CONTROL.0);
+
+    /// Returns whether every frame type in `other` is in this set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns whether the type of `frame` is in this set.
+    pub fn matches(self, frame: &Frame) -> bool {
+        self.contains(Self::of(frame))
+    }
+
+    /// Returns the set containing only the type of `frame`.
+    pub fn of(frame: &Frame) -> Self {
+        match frame {
+            Frame::Text { .. } => Self::TEXT,
+            Frame::Binary { .. } => Self::BINARY,
+            Frame::Close { .. } | Frame::CloseRaw { .. } => Self::CLOSE,
+            Frame::Ping { .. } => Self::PING,
+            Frame::Pong { .. } => Self::PONG,
+        }
+    }
+}
+
+impl Default for OpcodeSet {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl BitOr for OpcodeSet {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for OpcodeSet {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl Not for OpcodeSet {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0 & Self::ALL.0)
+    }
+}
 
 // https://tools.ietf.org/html/rfc6455#section-5.2
 /// Data which is sent and received through the WebSocket connection.
@@ -53,7 +147,22 @@ const U64_MAX_MINUS_ONE: usize = (u64::MAX - 1) as usize;
 /// `false` and `fin` set to `false`, all other frames except the last frame should
 /// have `continuation` set to `true` and `fin` set to `false`, and the last frame should
 /// have `continuation` set to `true` and `fin` set to `true`.
-#[derive(Debug, Clone)]
+///
+/// # Cloning
+///
+/// The payloads of Frames are owned `String`s and `Vec`s, so cloning a Frame
+/// copies its entire payload. To share a large received Frame between several
+/// consumers without copying it, wrap it in an [`Arc`](std::sync::Arc),
+/// which can be cloned in constant time:
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use websockets::Frame;
+/// let frame = Arc::new(Frame::binary(vec![0; 1 << 20]));
+/// let for_consumer = Arc::clone(&frame); // does not copy the payload
+/// assert_eq!(for_consumer.as_binary().unwrap().0.len(), 1 << 20);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Frame {
     /// A Text frame
     Text {
@@ -78,6 +187,12 @@ pub enum Frame {
         /// The payload for the Close frame
         payload: Option<(u16, String)>,
     },
+    /// A Close frame whose reason is raw bytes, which need not be UTF-8
+    /// (see [`Frame::close_raw()`])
+    CloseRaw {
+        /// The status code and reason for the Close frame
+        payload: (u16, Vec<u8>),
+    },
     /// A Ping frame
     Ping {
         /// The payload for the Ping frame
@@ -211,9 +326,32 @@ impl Frame {
         Self::Close { payload }
     }
 
-    /// Returns whether the frame is a Close frame.
+    /// Constructs a Close frame with the given status code, followed by `reason` as is,
+    /// for peers which expect opaque data rather than a UTF-8 reason.
+    /// Only the length of the payload is checked when the frame is sent,
+    /// which cannot exceed 125 bytes (including the 2 byte status code).
+    ///
+    /// ```
+    /// # use websockets::Frame;
+    /// let frame = Frame::close_raw(4000, vec![0xde, 0xad, 0xbe, 0xef]);
+    /// assert!(frame.is_close());
+    /// assert_eq!(frame.close_reason_bytes(), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+    /// ```
+    pub fn close_raw(status_code: u16, reason: Vec<u8>) -> Self {
+        Self::CloseRaw {
+            payload: (status_code, reason),
+        }
+    }
+
+    /// Returns whether the frame is a Close frame with a payload
+    /// (including a [raw reason](Frame::close_raw())).
     pub fn is_close(&self) -> bool {
-        self.as_close().is_some()
+        self.as_close().is_some() || matches!(self, Self::CloseRaw { .. })
+    }
+
+    /// Returns whether the frame is a Close frame, with or without a payload.
+    pub(super) fn is_any_close(&self) -> bool {
+        matches!(self, Self::Close { .. } | Self::CloseRaw { .. })
     }
 
     /// Attempts to interpret the frame as a Close frame,
@@ -236,6 +374,25 @@ impl Frame {
         }
     }
 
+    /// Returns the status code of a Close frame, or None if the frame is not a Close frame.
+    /// Close frames without a payload have the status code 1005 (No Status Received).
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            // https://tools.ietf.org/html/rfc6455#section-7.1.5
+            Self::Close { payload } => Some(
+                payload
+                    .as_ref()
+                    .map_or(CloseCode::NO_STATUS_RECEIVED, |(status_code, _)| {
+                        CloseCode(*status_code)
+                    }),
+            ),
+            Self::CloseRaw {
+                payload: (status_code, _),
+            } => Some(CloseCode(*status_code)),
+            _ => None,
+        }
+    }
+
     /// Attempts to interpret the frame as a Close frame,
     /// consuming and returning the underlying data if it is,
     /// and returning None otherwise.
@@ -246,6 +403,35 @@ impl Frame {
         }
     }
 
+    /// Returns the reason of a Close frame as bytes, whether it is a UTF-8 reason
+    /// or a [raw reason](Frame::close_raw()), or None if the frame is not a Close frame
+    /// or has no payload.
+    pub fn close_reason_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Close {
+                payload: Some((_, reason)),
+            } => Some(reason.as_bytes()),
+            Self::CloseRaw {
+                payload: (_, reason),
+            } => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Attempts to interpret the frame as a Close frame,
+    /// consuming and returning its status code and reason as bytes
+    /// (see [`close_reason_bytes()`](Frame::close_reason_bytes())) if it is,
+    /// and returning None otherwise.
+    pub fn into_close_raw(self) -> Option<(u16, Vec<u8>)> {
+        match self {
+            Self::Close {
+                payload: Some((status_code, reason)),
+            } => Some((status_code, reason.into_bytes())),
+            Self::CloseRaw { payload } => Some(payload),
+            _ => None,
+        }
+    }
+
     /// Constructs a Ping frame from the given payload.
     pub fn ping(payload: Option<Vec<u8>>) -> Self {
         Self::Ping { payload }
@@ -370,79 +556,167 @@ impl Frame {
         }
     }
 
-    pub(super) async fn send(
-        self,
-        write_half: &mut WebSocketWriteHalf,
-    ) -> Result<(), WebSocketError> {
-        // calculate before moving payload out of self
-        let is_control = self.is_control();
-        let opcode = self.opcode();
-        let fin = self.fin();
-
-        let mut payload = match self {
-            // https://tools.ietf.org/html/rfc6455#section-5.6
-            Self::Text { payload, .. } => payload.into_bytes(),
-            Self::Binary { payload, .. } => payload,
-            // https://tools.ietf.org/html/rfc6455#section-5.5.1
-            Self::Close {
-                payload: Some((status_code, reason)),
-            } => {
-                let mut payload = status_code.to_be_bytes().to_vec();
-                payload.append(&mut reason.into_bytes());
-                payload
-            }
-            Self::Close { payload: None } => Vec::new(),
-            // https://tools.ietf.org/html/rfc6455#section-5.5.2
-            Self::Ping { payload } => payload.unwrap_or(Vec::new()),
-            // https://tools.ietf.org/html/rfc6455#section-5.5.3
-            Self::Pong { payload } => payload.unwrap_or(Vec::new()),
-        };
-        // control frame cannot be longer than 125 bytes: https://tools.ietf.org/html/rfc6455#section-5.5
-        if is_control && payload.len() > 125 {
-            return Err(WebSocketError::ControlFrameTooLargeError);
-        }
+    /// Serializes the frame into bytes as it would be sent over a connection, for use
+    /// over transports which this library does not manage (such as WebRTC data channels
+    /// or message queues carrying WebSocket frames).
+    ///
+    /// The payload is masked with `masking_key` if one is given. Frames sent by a client
+    /// must be masked with a new random key for each frame, and frames sent by a server
+    /// must not be masked
+    /// (see [https://tools.ietf.org/html/rfc6455#section-5.3](https://tools.ietf.org/html/rfc6455#section-5.3)).
+    /// Fails with [`ControlFrameTooLargeError`](WebSocketError::ControlFrameTooLargeError)
+    /// if the frame is a control frame with a payload longer than 125 bytes.
+    ///
+    /// ```
+    /// # use websockets::{Frame, WebSocketError};
+    /// # fn main() -> Result<(), WebSocketError> {
+    /// let frame = Frame::text("hi".to_string());
+    /// assert_eq!(frame.to_bytes(None)?, b"\x81\x02hi");
+    /// assert_eq!(
+    ///     frame.to_bytes(Some([1, 2, 3, 4]))?,
+    ///     [0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_bytes(&self, masking_key: Option<[u8; 4]>) -> Result<Vec<u8>, WebSocketError> {
+        let payload = self.payload()?;
 
         // set payload len: https://tools.ietf.org/html/rfc6455#section-5.2
         let mut raw_frame = Vec::with_capacity(payload.len() + 14);
-        raw_frame.push(opcode + fin);
-        let mut payload_len_data = match payload.len() {
-            0..=125 => (payload.len() as u8).to_be_bytes().to_vec(),
-            126..=U16_MAX_MINUS_ONE => {
-                let mut payload_len_data = vec![126];
-                payload_len_data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
-                payload_len_data
+        raw_frame.push(self.opcode() + self.fin());
+        let len_encoding = encode_payload_len(payload.len());
+
+        match masking_key {
+            // payload masking: https://tools.ietf.org/html/rfc6455#section-5.3
+            Some(masking_key) => {
+                len_encoding.write(&mut raw_frame, true);
+                raw_frame.extend_from_slice(&masking_key);
+                raw_frame.extend(
+                    payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ masking_key[i % 4]),
+                );
             }
-            U16_MAX..=U64_MAX_MINUS_ONE => {
-                let mut payload_len_data = vec![127];
-                payload_len_data.extend_from_slice(&(payload.len() as u64).to_be_bytes());
-                payload_len_data
+            None => {
+                len_encoding.write(&mut raw_frame, false);
+                raw_frame.extend_from_slice(&payload);
             }
-            _ => return Err(WebSocketError::PayloadTooLargeError),
-        };
-        payload_len_data[0] += 0b10000000; // set masking bit: https://tools.ietf.org/html/rfc6455#section-5.3
-        raw_frame.append(&mut payload_len_data);
-
-        // payload masking: https://tools.ietf.org/html/rfc6455#section-5.3
-        let mut masking_key = vec![0; 4];
-        write_half.rng.fill_bytes(&mut masking_key);
-        for (i, byte) in payload.iter_mut().enumerate() {
-            *byte = *byte ^ (masking_key[i % 4]);
         }
-        raw_frame.append(&mut masking_key);
+        Ok(raw_frame)
+    }
+
+    /// Parses the frame at the start of `buf`, returning it along with the number of bytes
+    /// it took up, for use with bytes from transports which this library does not manage
+    /// or from packet captures. Masked frames (as sent by a client) are unmasked.
+    ///
+    /// Fails with [`NeedMoreDataError`](WebSocketError::NeedMoreDataError) if `buf` does
+    /// not contain the entire frame yet. Continuation frames can only be parsed along with
+    /// the frames before them, so use a [`FrameDecoder`](crate::FrameDecoder) to parse
+    /// fragmented messages.
+    ///
+    /// ```
+    /// # use websockets::{Frame, WebSocketError};
+    /// # fn main() -> Result<(), WebSocketError> {
+    /// let (frame, len) = Frame::parse(b"\x81\x02hi\x89\x00")?;
+    /// assert_eq!(frame, Frame::text("hi".to_string()));
+    /// assert_eq!(len, 4);
+    ///
+    /// assert!(matches!(
+    ///     Frame::parse(b"\x81\x02h"),
+    ///     Err(WebSocketError::NeedMoreDataError)
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse(buf: &[u8]) -> Result<(Self, usize), WebSocketError> {
+        FrameDecoder::allowing_masked()
+            .decode(buf)?
+            .ok_or(WebSocketError::NeedMoreDataError)
+    }
+
+    pub(super) async fn send(self, writer: &mut Writer) -> Result<SendReceipt, WebSocketError> {
+        let is_control = self.is_control();
+        let fin = self.fin();
+        // is_close() is false for Close frames without a payload
+        let is_close = self.is_any_close();
+        let payload = self.payload()?;
+        let payload_len = payload.len();
+        writer
+            .activity
+            .record(FrameDirection::Outbound, fin != 0, self.opcode(), &payload);
 
-        raw_frame.append(&mut payload);
+        // payload masking: https://tools.ietf.org/html/rfc6455#section-5.3
+        let masking_key = match writer.mask_frames {
+            true => {
+                let mut masking_key = [0; 4];
+                writer.rng.fill_bytes(&mut masking_key);
+                Some(masking_key)
+            }
+            false => None,
+        };
+        let raw_frame = self.to_bytes(masking_key)?;
 
-        write_half
+        writer.activity.set_write_blocked(true);
+        writer
             .stream
             .write_all(&raw_frame)
             .await
             .map_err(|e| WebSocketError::WriteError(e))?;
-        write_half
+        writer
             .stream
             .flush()
             .await
             .map_err(|e| WebSocketError::WriteError(e))?;
-        Ok(())
+        writer.activity.set_write_blocked(false);
+        writer.activity.sent(payload_len);
+        if is_close {
+            writer.sent_close_frame = true;
+            writer.activity.set_state(ConnectionState::Closing);
+        } else if !is_control {
+            writer.sending_fragmented_message = fin == 0;
+        }
+        Ok(SendReceipt {
+            frame_bytes: raw_frame.len(),
+            payload_bytes: payload_len,
+        })
+    }
+
+    /// Returns the payload as it is sent. Fails if the frame is a control frame
+    /// with too large a payload.
+    pub(crate) fn payload(&self) -> Result<Cow<'_, [u8]>, WebSocketError> {
+        let payload = match self {
+            // https://tools.ietf.org/html/rfc6455#section-5.6
+            Self::Text { payload, .. } => Cow::Borrowed(payload.as_bytes()),
+            Self::Binary { payload, .. } => Cow::Borrowed(&payload[..]),
+            // https://tools.ietf.org/html/rfc6455#section-5.5.1
+            Self::Close {
+                payload: Some((status_code, reason)),
+            } => {
+                let mut payload = status_code.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.as_bytes());
+                Cow::Owned(payload)
+            }
+            Self::Close { payload: None } => Cow::Borrowed(&[][..]),
+            Self::CloseRaw {
+                payload: (status_code, reason),
+            } => {
+                let mut payload = status_code.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason);
+                Cow::Owned(payload)
+            }
+            // https://tools.ietf.org/html/rfc6455#section-5.5.2
+            // https://tools.ietf.org/html/rfc6455#section-5.5.3
+            Self::Ping { payload } | Self::Pong { payload } => {
+                Cow::Borrowed(payload.as_deref().unwrap_or(&[]))
+            }
+        };
+        // control frame cannot be longer than 125 bytes: https://tools.ietf.org/html/rfc6455#section-5.5
+        if self.is_control() && payload.len() > 125 {
+            return Err(WebSocketError::ControlFrameTooLargeError);
+        }
+        Ok(payload)
     }
 
     fn is_control(&self) -> bool {
@@ -451,11 +725,30 @@ impl Frame {
             Self::Text { .. } => false,
             Self::Binary { .. } => false,
             Self::Close { .. } => true,
+            Self::CloseRaw { .. } => true,
             Self::Ping { .. } => true,
             Self::Pong { .. } => true,
         }
     }
 
+    /// Returns the message type, payload length, `continuation`, and `fin`
+    /// of a Text or Binary frame, or None for a control frame.
+    pub(super) fn data_frame_info(&self) -> Option<(MessageType, usize, bool, bool)> {
+        match self {
+            Self::Text {
+                payload,
+                continuation,
+                fin,
+            } => Some((MessageType::Text, payload.len(), *continuation, *fin)),
+            Self::Binary {
+                payload,
+                continuation,
+                fin,
+            } => Some((MessageType::Binary, payload.len(), *continuation, *fin)),
+            _ => None,
+        }
+    }
+
     fn opcode(&self) -> u8 {
         // opcodes: https://tools.ietf.org/html/rfc6455#section-5.2
         match self {
@@ -473,7 +766,7 @@ impl Frame {
                     0x2
                 }
             }
-            Self::Close { .. } => 0x8,
+            Self::Close { .. } | Self::CloseRaw { .. } => 0x8,
             Self::Ping { .. } => 0x9,
             Self::Pong { .. } => 0xA,
         }
@@ -485,58 +778,36 @@ impl Frame {
             Self::Text { fin, .. } => (*fin as u8) << 7,
             Self::Binary { fin, .. } => (*fin as u8) << 7,
             Self::Close { .. } => 0b10000000,
+            Self::CloseRaw { .. } => 0b10000000,
             Self::Ping { .. } => 0b10000000,
             Self::Pong { .. } => 0b10000000,
         }
     }
 
-    pub(super) async fn read_from_websocket(
-        read_half: &mut WebSocketReadHalf,
+    /// Constructs a received frame from its header and payload. `last_frame_type`
+    /// is the type of the last received data frame, used for continuation frames.
+    /// Close frames whose reason is not UTF-8 are rejected unless `raw_close_reasons`
+    /// is set, in which case they are returned as [`Frame::CloseRaw`] frames.
+    pub(super) fn decode(
+        header: &FrameHeader,
+        mut payload: Vec<u8>,
+        last_frame_type: &FrameType,
+        raw_close_reasons: bool,
     ) -> Result<Self, WebSocketError> {
-        // https://tools.ietf.org/html/rfc6455#section-5.2
-        let fin_and_opcode = read_half
-            .stream
-            .read_u8()
-            .await
-            .map_err(|e| WebSocketError::ReadError(e))?;
-        let fin: bool = fin_and_opcode & 0b10000000_u8 != 0;
-        let opcode = fin_and_opcode & 0b00001111_u8;
-
-        let mask_and_payload_len_first_byte = read_half
-            .stream
-            .read_u8()
-            .await
-            .map_err(|e| WebSocketError::ReadError(e))?;
-        let masked = mask_and_payload_len_first_byte & 0b10000000_u8 != 0;
-        if masked {
-            // server to client frames should not be masked
-            return Err(WebSocketError::ReceivedMaskedFrameError);
+        let FrameHeader {
+            fin,
+            opcode,
+            masking_key,
+            payload_len,
+            ..
+        } = *header;
+        if let Some(masking_key) = masking_key {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= masking_key[i % 4];
+            }
         }
-        let payload_len_first_byte = mask_and_payload_len_first_byte & 0b01111111_u8;
-        let payload_len = match payload_len_first_byte {
-            0..=125 => payload_len_first_byte as usize,
-            126 => read_half
-                .stream
-                .read_u16()
-                .await
-                .map_err(|e| WebSocketError::ReadError(e))? as usize,
-            127 => read_half
-                .stream
-                .read_u64()
-                .await
-                .map_err(|e| WebSocketError::ReadError(e))? as usize,
-            _ => unreachable!(),
-        };
-
-        let mut payload = vec![0; payload_len];
-        read_half
-            .stream
-            .read_exact(&mut payload)
-            .await
-            .map_err(|e| WebSocketError::ReadError(e))?;
-
         match opcode {
-            0x0 => match read_half.last_frame_type {
+            0x0 => match last_frame_type {
                 FrameType::Text => Ok(Self::Text {
                     payload: String::from_utf8(payload)
                         .map_err(|_e| WebSocketError::InvalidFrameError)?,
@@ -573,13 +844,11 @@ impl Frame {
                         .try_into()
                         .map_err(|_e| WebSocketError::InvalidFrameError)?,
                 );
-                Ok(Self::Close {
-                    payload: Some((
-                        status_code,
-                        String::from_utf8(reason.to_vec())
-                            .map_err(|_e| WebSocketError::InvalidFrameError)?,
-                    )),
-                })
+                match String::from_utf8(reason.to_vec()) {
+                    Ok(reason) => Ok(Self::close(Some((status_code, reason)))),
+                    Err(e) if raw_close_reasons => Ok(Self::close_raw(status_code, e.into_bytes())),
+                    Err(_e) => Err(WebSocketError::InvalidFrameError),
+                }
             }
             0x9 if payload_len == 0 => Ok(Self::Ping { payload: None }),
             0x9 => Ok(Self::Ping {
@@ -595,6 +864,86 @@ impl Frame {
     }
 }
 
+/// The header of a received frame.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FrameHeader {
+    pub(super) fin: bool,
+    pub(super) opcode: u8,
+    /// Only frames sent by a client are masked
+    pub(super) masking_key: Option<[u8; 4]>,
+    /// Length of the header itself, in bytes
+    pub(super) header_len: usize,
+    pub(super) payload_len: usize,
+}
+
+impl FrameHeader {
+    /// Parses the frame header at the start of `buf`,
+    /// returning None if `buf` does not contain the entire header yet.
+    ///
+    /// Masked frames (as sent by a client) are only accepted if `allow_masked` is true,
+    /// and payload lengths which break the rules of [`LenEncoding`] are only
+    /// rejected if `strict` is true.
+    pub(super) fn parse(
+        buf: &[u8],
+        allow_masked: bool,
+        strict: bool,
+    ) -> Result<Option<Self>, WebSocketError> {
+        // https://tools.ietf.org/html/rfc6455#section-5.2
+        let (fin_and_opcode, mask_and_payload_len_first_byte) = match buf {
+            [first, second, ..] => (*first, *second),
+            _ => return Ok(None),
+        };
+        let fin: bool = fin_and_opcode & 0b10000000_u8 != 0;
+        let opcode = fin_and_opcode & 0b00001111_u8;
+
+        let masked = mask_and_payload_len_first_byte & 0b10000000_u8 != 0;
+        if masked && !allow_masked {
+            // server to client frames should not be masked
+            return Err(WebSocketError::ReceivedMaskedFrameError);
+        }
+        let len_encoding = match LenEncoding::read(&buf[1..]) {
+            Some(len_encoding) => len_encoding,
+            None => return Ok(None),
+        };
+        if strict {
+            if !len_encoding.is_minimal() {
+                return Err(WebSocketError::NonMinimalLengthError);
+            }
+            // the most significant bit of a 64-bit length must be 0
+            if len_encoding.payload_len() >> 63 != 0 {
+                return Err(WebSocketError::InvalidFrameError);
+            }
+        }
+        let header_len = 1 + len_encoding.len_bytes();
+        let payload_len = usize::try_from(len_encoding.payload_len())
+            .map_err(|_e| WebSocketError::PayloadTooLargeError)?;
+        // https://tools.ietf.org/html/rfc6455#section-5.3
+        let (header_len, masking_key) = if masked {
+            match buf.get(header_len..header_len + 4) {
+                Some(key) => (header_len + 4, Some([key[0], key[1], key[2], key[3]])),
+                None => return Ok(None),
+            }
+        } else {
+            (header_len, None)
+        };
+        if payload_len.checked_add(header_len).is_none() {
+            return Err(WebSocketError::PayloadTooLargeError);
+        }
+        Ok(Some(Self {
+            fin,
+            opcode,
+            masking_key,
+            header_len,
+            payload_len,
+        }))
+    }
+
+    /// Length of the entire frame (header and payload), in bytes
+    pub(super) fn frame_len(&self) -> usize {
+        self.header_len + self.payload_len
+    }
+}
+
 impl From<String> for Frame {
     fn from(s: String) -> Self {
         Self::text(s)
@@ -606,3 +955,50 @@ impl From<Vec<u8>> for Frame {
         Self::binary(v)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header_needs_more_data() {
+        assert!(FrameHeader::parse(&[], false, false).unwrap().is_none());
+        assert!(FrameHeader::parse(&[0x81], false, false).unwrap().is_none());
+        assert!(FrameHeader::parse(&[0x82, 126, 0x01], false, false)
+            .unwrap()
+            .is_none());
+        assert!(FrameHeader::parse(&[0x82, 127, 0, 0, 0, 0], false, false)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn parse_header_lengths() {
+        let header = FrameHeader::parse(&[0x81, 5], false, false)
+            .unwrap()
+            .unwrap();
+        assert!(header.fin);
+        assert_eq!(header.opcode, 0x1);
+        assert_eq!((header.header_len, header.payload_len), (2, 5));
+
+        let header = FrameHeader::parse(&[0x02, 126, 0x01, 0x2c], false, false)
+            .unwrap()
+            .unwrap();
+        assert!(!header.fin);
+        assert_eq!((header.header_len, header.payload_len), (4, 300));
+
+        let header = FrameHeader::parse(&[0x82, 127, 0, 0, 0, 0, 0, 1, 0x01, 0xd0], false, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!((header.header_len, header.payload_len), (10, 66000));
+        assert_eq!(header.frame_len(), 66010);
+    }
+
+    #[test]
+    fn parse_header_rejects_masked() {
+        assert!(matches!(
+            FrameHeader::parse(&[0x81, 0x85], false, false),
+            Err(WebSocketError::ReceivedMaskedFrameError)
+        ));
+    }
+}
@@ -1,23 +1,59 @@
-use rand::{RngCore, SeedableRng};
-use rand_chacha::ChaCha20Rng;
-use regex::Regex;
 use sha1::{Digest, Sha1};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 
+use super::capabilities::WEBSOCKET_VERSION;
+use super::entropy::EntropySource;
+use super::handshake_request::InterimResponse;
+use super::http::{self, HeaderParser};
 use super::parsed_addr::ParsedAddr;
 use super::WebSocket;
 use crate::error::WebSocketError;
 
-const GUUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+pub(crate) const GUUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Checks that a header to be sent in the handshake request is valid,
+/// so that it cannot inject additional headers into the request
+pub(super) fn validate_header(name: &str, value: &str) -> Result<(), WebSocketError> {
+    if http::is_token(name) && http::is_field_value(value) {
+        Ok(())
+    } else {
+        Err(WebSocketError::InvalidHeaderError(name.to_string()))
+    }
+}
+
+/// Checks that a subprotocol to be sent in the handshake request is valid
+pub(super) fn validate_subprotocol(subprotocol: &str) -> Result<(), WebSocketError> {
+    // https://tools.ietf.org/html/rfc6455#section-4.1 (subprotocols must be tokens)
+    if http::is_token(subprotocol) {
+        Ok(())
+    } else {
+        Err(WebSocketError::InvalidHeaderError(
+            "Sec-WebSocket-Protocol".to_string(),
+        ))
+    }
+}
+
+/// Limits on the size of the server's handshake response, configured on the
+/// [`WebSocketBuilder`](super::builder::WebSocketBuilder)
+#[derive(Debug, Clone, Copy)]
+pub(super) struct HandshakeLimits {
+    /// Maximum number of headers in each response
+    pub(super) max_headers: usize,
+    /// Maximum number of bytes in the status lines and headers of all responses
+    pub(super) max_header_bytes: usize,
+}
 
 #[derive(Debug)]
 pub(super) struct Handshake {
     path: String,
     host: String,
     key: String,
-    version: usize,
+    version: u16,
     additional_headers: Vec<(String, String)>,
     subprotocols: Vec<String>,
+    extensions_offer: Option<String>,
+    debug: bool,
+    limits: HandshakeLimits,
 }
 
 impl Handshake {
@@ -25,10 +61,13 @@ impl Handshake {
         parsed_addr: &ParsedAddr,
         additional_handshake_headers: &Vec<(String, String)>,
         subprotocols: &Vec<String>,
+        extensions_offer: Option<&str>,
+        debug: bool,
+        limits: HandshakeLimits,
+        rng: &mut EntropySource,
     ) -> Self {
         // https://tools.ietf.org/html/rfc6455#section-5.3
         let mut rand_bytes = vec![0; 16];
-        let mut rng = ChaCha20Rng::from_entropy();
         rng.fill_bytes(&mut rand_bytes);
         let key = base64::encode(rand_bytes);
         Self {
@@ -36,9 +75,12 @@ impl Handshake {
             host: parsed_addr.host.clone(),
             key,
             // todo: support more versions
-            version: 13,
+            version: WEBSOCKET_VERSION,
             additional_headers: additional_handshake_headers.clone(),
             subprotocols: subprotocols.clone(),
+            extensions_offer: extensions_offer.map(str::to_string),
+            debug,
+            limits,
         }
     }
 
@@ -60,6 +102,13 @@ impl Handshake {
                 self.subprotocols.join(", "),
             ));
         }
+        if let Some(extensions_offer) = &self.extensions_offer {
+            // https://tools.ietf.org/html/rfc6455#section-9.1
+            headers.push((
+                "Sec-WebSocket-Extensions".to_string(),
+                extensions_offer.clone(),
+            ));
+        }
         for header in &self.additional_headers {
             headers.push(header.clone());
         }
@@ -69,12 +118,13 @@ impl Handshake {
             req.push_str(&format!("{}: {}\r\n", field, value));
         }
         req.push_str("\r\n"); // end of request
-        ws.write_half
+        let mut writer = ws.write_half.writer.lock().await;
+        writer
             .stream
             .write_all(req.as_bytes())
             .await
             .map_err(|e| WebSocketError::WriteError(e))?;
-        ws.write_half
+        writer
             .stream
             .flush()
             .await
@@ -83,49 +133,53 @@ impl Handshake {
     }
 
     pub(super) async fn check_response(&self, ws: &mut WebSocket) -> Result<(), WebSocketError> {
+        let mut raw_response = Vec::new();
+        match self.read_response(ws, &mut raw_response).await {
+            Err(e) if self.debug => {
+                // include whatever has been received but not read yet, such as a partial body
+                raw_response.extend_from_slice(ws.read_half.stream.buffer());
+                Err(WebSocketError::HandshakeDebugError {
+                    error: Box::new(e),
+                    raw_response,
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Reads and checks the handshake response, copying the bytes that are read
+    /// into `raw_response`
+    ///
+    /// The response is read through the same `BufReader` that frames are later read from,
+    /// so any frames which arrive along with the response (such as in the same TCP segment)
+    /// stay buffered for the frame reader instead of being lost.
+    async fn read_response(
+        &self,
+        ws: &mut WebSocket,
+        raw_response: &mut Vec<u8>,
+    ) -> Result<(), WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-1.3
         // https://tools.ietf.org/html/rfc6455#section-4.2.2
-        let status_line_regex = Regex::new(r"HTTP/\d+\.\d+ (?P<status_code>\d{3}) .+\r\n").unwrap();
-        let mut status_line = String::new();
-
-        ws.read_half
-            .stream
-            .read_line(&mut status_line)
-            .await
-            .map_err(|e| WebSocketError::ReadError(e))?;
-        let captures = status_line_regex
-            .captures(&status_line)
-            .ok_or(WebSocketError::InvalidHandshakeError)?;
-        let status_code = &captures["status_code"];
-
-        let mut headers = Vec::new();
-        let headers_regex = Regex::new(r"(?P<field>.+?):\s*(?P<value>.*?)\s*\r\n").unwrap();
-        loop {
-            let mut header = String::new();
-            ws.read_half
-                .stream
-                .read_line(&mut header)
-                .await
-                .map_err(|e| WebSocketError::ReadError(e))?;
-            match headers_regex.captures(&header) {
-                Some(captures) => {
-                    let field = &captures["field"];
-                    let value = &captures["value"];
-                    headers.push((field.to_string(), value.to_string()));
-                }
-                None => break, // field is empty, so the header is finished (we got double crlf)
+        let mut interim_responses = Vec::new();
+        let (status_code, headers) = loop {
+            let (status_code, headers) = self.read_head(ws, raw_response).await?;
+            // skip informational responses, such as 100 Continue, before the 101 response
+            // (https://tools.ietf.org/html/rfc7231#section-6.2)
+            if status_code.starts_with('1') && status_code != "101" {
+                interim_responses.push(InterimResponse {
+                    status_code,
+                    headers,
+                });
+            } else {
+                break (status_code, headers);
             }
-        }
+        };
 
         // check status code
         if status_code != "101" {
-            let body = match headers
-                .iter()
-                .find(|(field, _value)| field.to_lowercase() == "content-length")
-            {
-                Some(header) => {
-                    let body_length = header
-                        .1
+            let body = match http::header_value(&headers, "content-length") {
+                Some(content_length) => {
+                    let body_length = content_length
                         .parse::<usize>()
                         .map_err(|_e| WebSocketError::InvalidHandshakeError)?;
                     let mut body = vec![0; body_length];
@@ -134,6 +188,7 @@ impl Handshake {
                         .read_exact(&mut body)
                         .await
                         .map_err(|e| WebSocketError::ReadError(e))?;
+                    raw_response.extend_from_slice(&body);
                     Some(
                         String::from_utf8(body)
                             .map_err(|_e| WebSocketError::InvalidHandshakeError)?,
@@ -149,41 +204,29 @@ impl Handshake {
         }
 
         // check upgrade field
-        let upgrade = headers
-            .iter()
-            .find(|(field, _value)| field.to_lowercase() == "upgrade")
-            .ok_or(WebSocketError::InvalidHandshakeError)?
-            .1
-            .clone();
-        if upgrade.to_lowercase() != "websocket" {
+        if !http::header_has_token(&headers, "upgrade", "websocket") {
             return Err(WebSocketError::InvalidHandshakeError);
         }
 
         // check connection field
-        let connection = headers
-            .iter()
-            .find(|(field, _value)| field.to_lowercase() == "connection")
-            .ok_or(WebSocketError::InvalidHandshakeError)?
-            .1
-            .clone();
-        if connection.to_lowercase() != "upgrade" {
+        if !http::header_has_token(&headers, "connection", "upgrade") {
             return Err(WebSocketError::InvalidHandshakeError);
         }
 
-        // check extensions
-        if let Some(_) = headers
-            .iter()
-            .find(|(field, _value)| field.to_lowercase() == "sec-websocket-extensions")
-        {
-            // extensions not supported
-            return Err(WebSocketError::InvalidHandshakeError);
+        // check extensions: https://tools.ietf.org/html/rfc6455#section-9.1
+        match http::header_list(&headers, "sec-websocket-extensions") {
+            // extensions are passed through to the application without being interpreted
+            Some(extensions) if self.extensions_offer.is_some() => {
+                ws.accepted_extensions = Some(extensions)
+            }
+            // server accepted an extension that was not offered
+            Some(_) => return Err(WebSocketError::InvalidHandshakeError),
+            None => (),
         }
 
         // check subprotocols
-        let possible_subprotocol = headers
-            .iter()
-            .find(|(field, _value)| field.to_lowercase() == "sec-websocket-protocol")
-            .map(|(_field, value)| value.clone());
+        let possible_subprotocol =
+            http::header_value(&headers, "sec-websocket-protocol").map(str::to_string);
         match (possible_subprotocol, self.subprotocols.len()) {
             // server accepted a subprotocol that was not specified
             (Some(_), 0) => return Err(WebSocketError::InvalidHandshakeError),
@@ -200,12 +243,8 @@ impl Handshake {
         }
 
         // validate key
-        let accept_key = headers
-            .iter()
-            .find(|(field, _value)| field.to_lowercase() == "sec-websocket-accept")
-            .ok_or(WebSocketError::InvalidHandshakeError)?
-            .1
-            .clone();
+        let accept_key = http::header_value(&headers, "sec-websocket-accept")
+            .ok_or(WebSocketError::InvalidHandshakeError)?;
         let mut test_key = self.key.clone();
         test_key.push_str(GUUID);
         let hashed: [u8; 20] = Sha1::digest(test_key.as_bytes()).into();
@@ -215,6 +254,173 @@ impl Handshake {
         }
 
         ws.handshake_response_headers = Some(headers);
+        ws.interim_responses = interim_responses;
         Ok(())
     }
+
+    /// Reads the status line and headers of a response, returning the status code
+    /// and the headers
+    async fn read_head(
+        &self,
+        ws: &mut WebSocket,
+        raw_response: &mut Vec<u8>,
+    ) -> Result<(String, Vec<(String, String)>), WebSocketError> {
+        let status_line = self.read_line(ws, raw_response).await?;
+        let status_code = http::parse_status_line(&status_line)?.to_string();
+
+        let mut header_parser = HeaderParser::new(self.limits.max_headers);
+        loop {
+            let line = self.read_line(ws, raw_response).await?;
+            if header_parser.parse_line(&line)? {
+                break;
+            }
+        }
+        let headers = header_parser.into_headers();
+        Ok((status_code, headers))
+    }
+
+    /// Reads a line of the response, without reading more than the limit on the size
+    /// of the response allows
+    async fn read_line(
+        &self,
+        ws: &mut WebSocket,
+        raw_response: &mut Vec<u8>,
+    ) -> Result<String, WebSocketError> {
+        let remaining = self
+            .limits
+            .max_header_bytes
+            .saturating_sub(raw_response.len());
+        let mut line = String::new();
+        let bytes_read = (&mut ws.read_half.stream)
+            .take(remaining as u64)
+            .read_line(&mut line)
+            .await
+            .map_err(|e| WebSocketError::ReadError(e))?;
+        raw_response.extend_from_slice(line.as_bytes());
+        if bytes_read == remaining && !line.ends_with('\n') {
+            return Err(WebSocketError::HandshakeTooLargeError);
+        }
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::test_util::read_handshake;
+    use crate::Frame;
+
+    /// Accepts one connection and sends `before`, the handshake response (with the
+    /// additional `headers`), and `after` in a single write, then ignores everything
+    /// the client sends.
+    async fn coalescing_server(
+        before: &'static [u8],
+        headers: &'static str,
+        after: &'static [u8],
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let (_request, accept) = read_handshake(&mut stream).await.unwrap();
+            let mut response = before.to_vec();
+            response.extend_from_slice(
+                format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                    Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n{}\r\n",
+                    accept, headers
+                )
+                .as_bytes(),
+            );
+            response.extend_from_slice(after);
+            stream.write_all(&response).await.unwrap();
+            let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn frames_sent_with_response_are_not_lost() {
+        let url = coalescing_server(
+            b"",
+            "",
+            &[0x81, 2, b'h', b'i', 0x82, 3, 1, 2, 3, 0x88, 2, 0x03, 0xe8],
+        )
+        .await;
+        let mut ws = WebSocket::connect(&url).await.unwrap();
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_text().unwrap().0, "hi");
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_binary().unwrap().0, &[1, 2, 3]);
+        assert!(matches!(ws.receive().await.unwrap(), Frame::Close { .. }));
+    }
+
+    #[tokio::test]
+    async fn frames_sent_with_interim_and_final_responses_are_not_lost() {
+        let url = coalescing_server(
+            b"HTTP/1.1 100 Continue\r\n\r\n",
+            "",
+            // the second frame is split between segments
+            &[0x81, 2, b'h', b'i', 0x81, 5, b'h'],
+        )
+        .await;
+        let mut ws = WebSocket::connect(&url).await.unwrap();
+        assert_eq!(ws.interim_responses().len(), 1);
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_text().unwrap().0, "hi");
+        assert!(ws.handshake_response_headers().is_some());
+    }
+
+    #[tokio::test]
+    async fn accepted_extensions_are_passed_through() {
+        let headers = "Sec-WebSocket-Extensions: x-foo; level=3\r\n\
+                       Sec-WebSocket-Extensions: x-bar\r\n";
+        let url = coalescing_server(b"", headers, b"").await;
+        let ws = WebSocket::builder()
+            .extensions_offer(Some("x-foo; level=3, x-bar"))
+            .connect(&url)
+            .await
+            .unwrap();
+        assert_eq!(
+            ws.accepted_extensions().as_deref(),
+            Some("x-foo; level=3, x-bar")
+        );
+
+        // extensions which were not offered must not be accepted
+        let url = coalescing_server(b"", headers, b"").await;
+        assert!(matches!(
+            WebSocket::connect(&url).await,
+            Err(WebSocketError::InvalidHandshakeError)
+        ));
+    }
+
+    #[test]
+    fn valid_headers() {
+        assert!(validate_header("X-Foo", "bar").is_ok());
+        assert!(validate_header("Authorization", "Bearer abc.def").is_ok());
+        assert!(validate_header("X-Empty", "").is_ok());
+        assert!(validate_header("X-Tab", "a\tb").is_ok());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(validate_header("X-Foo", "bar\r\nEvil: 1").is_err());
+        assert!(validate_header("X-Foo", "bar\n").is_err());
+        assert!(validate_header("X-Foo", "bar\0").is_err());
+        assert!(validate_header("X-Foo\r\nEvil", "1").is_err());
+        assert!(validate_header("X Foo", "bar").is_err());
+        assert!(validate_header("X-Foo:", "bar").is_err());
+        assert!(validate_header("", "bar").is_err());
+    }
+
+    #[test]
+    fn subprotocols() {
+        assert!(validate_subprotocol("wamp.2.json").is_ok());
+        assert!(validate_subprotocol("chat, evil").is_err());
+        assert!(validate_subprotocol("").is_err());
+    }
 }
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+
+/// The future returned by a [`before_handshake()`](WebSocketBuilder::before_handshake()) hook.
+pub type BeforeHandshakeFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'a>>;
+
+/// Runs after connecting but before the handshake request is sent.
+pub(super) type BeforeHandshake =
+    Arc<dyn for<'a> Fn(&'a mut HandshakeRequest) -> BeforeHandshakeFuture<'a> + Send + Sync>;
+
+/// The WebSocket handshake request that is about to be sent, passed to a
+/// [`before_handshake()`](WebSocketBuilder::before_handshake()) hook so that
+/// it can be inspected and its headers changed.
+#[derive(Debug)]
+pub struct HandshakeRequest {
+    pub(super) url: String,
+    pub(super) path: String,
+    pub(super) peer_addr: Option<SocketAddr>,
+    pub(super) local_addr: Option<SocketAddr>,
+    pub(super) headers: Vec<(String, String)>,
+    pub(super) subprotocols: Vec<String>,
+}
+
+impl HandshakeRequest {
+    /// Returns the URL being connected to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the path (and query string) of the request.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the address of the server, or `None` for `ws+unix` URLs.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the local address of the connection, or `None` if
+    /// a custom [`Transport`](crate::Transport) is used.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Returns the additional headers to be sent in the request (not including the
+    /// headers required by the WebSocket protocol, such as `Sec-WebSocket-Key`).
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the subprotocols to be sent in the request.
+    pub fn subprotocols(&self) -> &[String] {
+        &self.subprotocols
+    }
+
+    /// Adds a header to be sent in the request.
+    ///
+    /// The header is validated in the same way as [`WebSocketBuilder::add_header()`].
+    pub fn add_header(&mut self, header_name: &str, header_value: &str) -> &mut Self {
+        self.headers
+            .push((header_name.to_string(), header_value.to_string()));
+        self
+    }
+
+    /// Removes a header which would be sent in the request.
+    /// Header names are case-insensitive.
+    pub fn remove_header(&mut self, header_name: &str) -> &mut Self {
+        self.headers
+            .retain(|header| !header.0.eq_ignore_ascii_case(header_name));
+        self
+    }
+}
+
+/// An informational (1xx) response, such as `100 Continue`, which the server sent
+/// before its handshake response. These are skipped during the handshake and can be
+/// found using [`WebSocket::interim_responses()`](crate::WebSocket::interim_responses()).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterimResponse {
+    pub(super) status_code: String,
+    pub(super) headers: Vec<(String, String)>,
+}
+
+impl InterimResponse {
+    /// Returns the status code of the response.
+    pub fn status_code(&self) -> &str {
+        &self.status_code
+    }
+
+    /// Returns the headers of the response.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_removed_regardless_of_case() {
+        let mut request = HandshakeRequest {
+            url: "ws://localhost/".to_string(),
+            path: "/".to_string(),
+            peer_addr: None,
+            local_addr: None,
+            headers: Vec::new(),
+            subprotocols: Vec::new(),
+        };
+        request
+            .add_header("Authorization", "Bearer a")
+            .add_header("X-Client", "1")
+            .remove_header("authorization");
+        assert_eq!(
+            request.headers(),
+            &[("X-Client".to_string(), "1".to_string())]
+        );
+    }
+}
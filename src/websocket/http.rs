@@ -0,0 +1,229 @@
+// Parsing for the parts of HTTP/1.1 used by the WebSocket opening handshake
+// (https://tools.ietf.org/html/rfc7230), independent of whether the message
+// is a request or a response.
+
+use crate::error::WebSocketError;
+
+/// Characters other than letters and digits which are allowed in tokens
+const TCHARS: &[u8] = b"!#$%&'*+-.^_`|~";
+
+pub(super) fn is_token(s: &str) -> bool {
+    // https://tools.ietf.org/html/rfc7230#section-3.2.6
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || TCHARS.contains(&b))
+}
+
+pub(super) fn is_field_value(s: &str) -> bool {
+    // https://tools.ietf.org/html/rfc7230#section-3.2
+    // visible characters, spaces, tabs, and non-ASCII text, but no control characters
+    // (in particular CR and LF, which would end the header line)
+    s.bytes()
+        .all(|b| b == b' ' || b == b'\t' || b.is_ascii_graphic() || b >= 0x80)
+}
+
+/// Removes the line ending (CRLF, or a bare LF, which some servers send) from a line,
+/// or returns `None` if the line was not terminated.
+fn strip_line_ending(line: &str) -> Option<&str> {
+    line.strip_suffix('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+/// Parses the status line of a response, returning the status code.
+///
+/// This accepts any HTTP version of the form `HTTP/d.d`, a missing reason phrase,
+/// and a bare LF line ending, since some servers send these.
+pub(super) fn parse_status_line(status_line: &str) -> Result<&str, WebSocketError> {
+    // https://tools.ietf.org/html/rfc7230#section-3.1.2
+    let invalid = || WebSocketError::InvalidStatusLineError(status_line.to_string());
+    let line = strip_line_ending(status_line).ok_or_else(invalid)?;
+    let (version, rest) = line.split_at(line.find(' ').ok_or_else(invalid)?);
+    let is_version = match version.strip_prefix("HTTP/") {
+        Some(number) => {
+            let number = number.as_bytes();
+            number.len() == 3
+                && number[0].is_ascii_digit()
+                && number[1] == b'.'
+                && number[2].is_ascii_digit()
+        }
+        None => false,
+    };
+    // the reason phrase (and the space before it) may be missing
+    let status_code = rest[1..].split(' ').next().unwrap_or("");
+    if is_version && status_code.len() == 3 && status_code.bytes().all(|b| b.is_ascii_digit()) {
+        Ok(status_code)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Parses the header section of a message one line at a time.
+#[derive(Debug)]
+pub(super) struct HeaderParser {
+    headers: Vec<(String, String)>,
+    max_headers: usize,
+}
+
+impl HeaderParser {
+    pub(super) fn new(max_headers: usize) -> Self {
+        Self {
+            headers: Vec::new(),
+            max_headers,
+        }
+    }
+
+    /// Parses a line of the header section, returning true if it was the empty line
+    /// which ends the section.
+    pub(super) fn parse_line(&mut self, line: &str) -> Result<bool, WebSocketError> {
+        // https://tools.ietf.org/html/rfc7230#section-3.2
+        let line = strip_line_ending(line).ok_or(WebSocketError::InvalidHandshakeError)?;
+        if line.is_empty() {
+            return Ok(true);
+        }
+        // obsolete line folding continues the previous header's value
+        // (https://tools.ietf.org/html/rfc7230#section-3.2.4)
+        if line.starts_with(' ') || line.starts_with('\t') {
+            let (_field, value) = self
+                .headers
+                .last_mut()
+                .ok_or(WebSocketError::InvalidHandshakeError)?;
+            let continuation = line.trim_matches(|c| c == ' ' || c == '\t');
+            if !is_field_value(continuation) {
+                return Err(WebSocketError::InvalidHandshakeError);
+            }
+            if !continuation.is_empty() {
+                value.push(' ');
+                value.push_str(continuation);
+            }
+            return Ok(false);
+        }
+        if self.headers.len() == self.max_headers {
+            return Err(WebSocketError::HandshakeTooLargeError);
+        }
+        let (field, value) = line
+            .split_once(':')
+            .ok_or(WebSocketError::InvalidHandshakeError)?;
+        let value = value.trim_matches(|c| c == ' ' || c == '\t');
+        // no whitespace is allowed between the field name and the colon
+        if !is_token(field) || !is_field_value(value) {
+            return Err(WebSocketError::InvalidHandshakeError);
+        }
+        self.headers.push((field.to_string(), value.to_string()));
+        Ok(false)
+    }
+
+    pub(super) fn into_headers(self) -> Vec<(String, String)> {
+        self.headers
+    }
+}
+
+/// Returns the value of the first header with the given name (compared case-insensitively).
+pub(super) fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(field, _value)| field.eq_ignore_ascii_case(name))
+        .map(|(_field, value)| &value[..])
+}
+
+/// Returns the values of all headers with the given name (compared case-insensitively),
+/// combined into one comma-separated list, or None if there are no such headers.
+pub(super) fn header_list(headers: &[(String, String)], name: &str) -> Option<String> {
+    // https://tools.ietf.org/html/rfc7230#section-3.2.2 (repeated headers form one list)
+    let values: Vec<&str> = headers
+        .iter()
+        .filter(|(field, _value)| field.eq_ignore_ascii_case(name))
+        .map(|(_field, value)| &value[..])
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+/// Returns whether any of the headers with the given name contains the token
+/// in its comma-separated list of values (all compared case-insensitively).
+pub(super) fn header_has_token(headers: &[(String, String)], name: &str, token: &str) -> bool {
+    // https://tools.ietf.org/html/rfc7230#section-3.2.2 (repeated headers form one list)
+    // https://tools.ietf.org/html/rfc7230#section-7 (list elements are separated by commas)
+    headers
+        .iter()
+        .filter(|(field, _value)| field.eq_ignore_ascii_case(name))
+        .flat_map(|(_field, value)| value.split(','))
+        .any(|element| element.trim().eq_ignore_ascii_case(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_lines() {
+        let status_code = |line| parse_status_line(line).ok();
+        assert_eq!(
+            status_code("HTTP/1.1 101 Switching Protocols\r\n"),
+            Some("101")
+        );
+        assert_eq!(status_code("HTTP/1.1 101\r\n"), Some("101"));
+        assert_eq!(status_code("HTTP/1.1 101 \r\n"), Some("101"));
+        assert_eq!(status_code("HTTP/1.0 404 Not Found\n"), Some("404"));
+        assert_eq!(status_code("HTTP/1.1 101 Switching Protocols"), None);
+        assert_eq!(status_code("HTTP/1.1 1010\r\n"), None);
+        assert_eq!(status_code("HTTP/11 101\r\n"), None);
+        assert_eq!(status_code("HTTP/1.1\r\n"), None);
+        assert_eq!(status_code("\r\n"), None);
+        assert!(matches!(
+            parse_status_line("SSH-2.0\r\n"),
+            Err(WebSocketError::InvalidStatusLineError(line)) if line == "SSH-2.0\r\n"
+        ));
+    }
+
+    fn parse(lines: &[&str]) -> Result<Vec<(String, String)>, WebSocketError> {
+        let mut parser = HeaderParser::new(3);
+        for line in lines {
+            if parser.parse_line(line)? {
+                return Ok(parser.into_headers());
+            }
+        }
+        panic!("header section was not finished");
+    }
+
+    #[test]
+    fn headers() {
+        let headers = parse(&[
+            "Upgrade: websocket\r\n",
+            "X-Folded:  a\r\n",
+            " \tb\r\n",
+            "Connection:keep-alive, Upgrade \n",
+            "\r\n",
+        ])
+        .unwrap();
+        assert_eq!(header_value(&headers, "upgrade"), Some("websocket"));
+        assert_eq!(header_value(&headers, "X-FOLDED"), Some("a b"));
+        assert_eq!(header_value(&headers, "X-Missing"), None);
+        assert!(header_has_token(&headers, "Connection", "upgrade"));
+        assert!(!header_has_token(&headers, "Connection", "close"));
+    }
+
+    #[test]
+    fn repeated_headers_form_one_list() {
+        let headers = parse(&[
+            "Connection: keep-alive\r\n",
+            "Connection: Upgrade\r\n",
+            "\r\n",
+        ]);
+        assert!(header_has_token(&headers.unwrap(), "connection", "upgrade"));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(parse(&["Upgrade websocket\r\n", "\r\n"]).is_err());
+        assert!(parse(&["Upgrade : websocket\r\n", "\r\n"]).is_err());
+        assert!(parse(&[" folded\r\n", "\r\n"]).is_err());
+        assert!(parse(&["X-A: 1\r\n", "X-B: 2"]).is_err());
+        assert!(matches!(
+            parse(&["A: 1\r\n", "B: 2\r\n", "C: 3\r\n", "D: 4\r\n", "\r\n"]),
+            Err(WebSocketError::HandshakeTooLargeError)
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt::Arguments;
+
+/// How much a WebSocket logs through the [`log`](https://docs.rs/log) crate.
+/// Set with [`WebSocketBuilder::log_verbosity()`](crate::WebSocketBuilder::log_verbosity()).
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogVerbosity {
+    /// Nothing is logged
+    Off,
+    /// Abnormal closures, protocol violations, and handshake failures
+    /// are logged at the warn level
+    #[default]
+    Warn,
+    /// In addition to warnings, normal closures are logged at the info level
+    Info,
+}
+
+/// Logs events if the `log` feature is enabled; otherwise, does nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Logger {
+    #[cfg(feature = "log")]
+    pub(super) verbosity: LogVerbosity,
+}
+
+impl Logger {
+    #[allow(unused_variables)]
+    pub(super) fn warn(&self, args: Arguments) {
+        #[cfg(feature = "log")]
+        if self.verbosity >= LogVerbosity::Warn {
+            log::warn!("{}", args);
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(super) fn info(&self, args: Arguments) {
+        #[cfg(feature = "log")]
+        if self.verbosity >= LogVerbosity::Info {
+            log::info!("{}", args);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display, Error as FmtError, Formatter};
+use std::ops::Deref;
+use std::str::Utf8Error;
+
+use bytes::Bytes;
+use bytestring::ByteString;
+
+use super::frame::{Frame, MessageType};
+#[allow(unused_imports)] // for intra doc links
+use super::{split::WebSocketReadHalf, WebSocket};
+use crate::error::WebSocketError;
+
+/// A complete message, received with [`WebSocket::receive_message()`] or
+/// [`WebSocketReadHalf::receive_message()`].
+///
+/// The payloads of the message's frames are handed out without being copied
+/// when the message is made up of a single frame, and are copied into one buffer
+/// (and validated once, in place) when it is fragmented.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A Text message
+    Text(Utf8Bytes),
+    /// A Binary message
+    Binary(Bytes),
+}
+
+impl Message {
+    /// Returns the text of the message, or None if it is a Binary message.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            Self::Binary(_) => None,
+        }
+    }
+
+    /// Returns the payload of the message as bytes, whichever type of message it is.
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            Self::Text(text) => text.into_bytes(),
+            Self::Binary(bytes) => bytes,
+        }
+    }
+}
+
+/// An immutable string backed by [`Bytes`], so that it can be cloned and sliced
+/// cheaply, and converted to and from bytes without copying.
+/// It is always valid UTF-8.
+///
+/// ```
+/// # use websockets::Utf8Bytes;
+/// # use websockets::bytes::Bytes;
+/// let text = Utf8Bytes::try_from_bytes(Bytes::from_static("héllo".as_bytes())).unwrap();
+/// assert_eq!(text, "héllo");
+/// assert_eq!(text.len(), 6);
+/// assert!(Utf8Bytes::try_from_bytes(Bytes::from_static(&[0xff])).is_err());
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Utf8Bytes(ByteString);
+
+impl Utf8Bytes {
+    /// Creates a string from a static string, without copying it.
+    pub const fn from_static(text: &'static str) -> Self {
+        Self(ByteString::from_static(text))
+    }
+
+    /// Validates that the bytes are UTF-8 in place, then wraps them without copying.
+    pub fn try_from_bytes(bytes: Bytes) -> Result<Self, Utf8Error> {
+        ByteString::try_from(bytes).map(Self)
+    }
+
+    /// Returns the string as a `&str`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the underlying bytes, without copying them.
+    pub fn into_bytes(self) -> Bytes {
+        self.0.into_bytes()
+    }
+}
+
+impl Deref for Utf8Bytes {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Utf8Bytes {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Debug for Utf8Bytes {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Utf8Bytes {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl From<String> for Utf8Bytes {
+    /// Converts the string without copying it.
+    fn from(text: String) -> Self {
+        Self(ByteString::from(text))
+    }
+}
+
+impl From<&'static str> for Utf8Bytes {
+    fn from(text: &'static str) -> Self {
+        Self::from_static(text)
+    }
+}
+
+impl From<Utf8Bytes> for Bytes {
+    fn from(text: Utf8Bytes) -> Self {
+        text.into_bytes()
+    }
+}
+
+impl TryFrom<Bytes> for Utf8Bytes {
+    type Error = Utf8Error;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Utf8Error> {
+        Self::try_from_bytes(bytes)
+    }
+}
+
+impl PartialEq<str> for Utf8Bytes {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Utf8Bytes {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Assembles the frames of a message (https://tools.ietf.org/html/rfc6455#section-5.4)
+#[derive(Debug, Default)]
+pub(super) struct MessageAssembler {
+    /// The type of the message being assembled and its payload so far, if a fragmented
+    /// message has been started
+    fragments: Option<(MessageType, Vec<u8>)>,
+}
+
+impl MessageAssembler {
+    /// Adds a received frame, returning the message once it is complete.
+    /// Control frames are ignored.
+    pub(super) fn push(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        let unfragmented = self.fragments.is_none();
+        let (message_type, payload, continuation, fin) = match frame {
+            // unfragmented messages are handed out as they are (the payload of
+            // a Text frame has already been validated)
+            Frame::Text {
+                payload,
+                continuation: false,
+                fin: true,
+            } if unfragmented => return Ok(Some(Message::Text(Utf8Bytes::from(payload)))),
+            Frame::Binary {
+                payload,
+                continuation: false,
+                fin: true,
+            } if unfragmented => return Ok(Some(Message::Binary(Bytes::from(payload)))),
+            Frame::Text {
+                payload,
+                continuation,
+                fin,
+            } => (MessageType::Text, payload.into_bytes(), continuation, fin),
+            Frame::Binary {
+                payload,
+                continuation,
+                fin,
+            } => (MessageType::Binary, payload, continuation, fin),
+            _ => return Ok(None),
+        };
+        let (message_type, payload) = match (self.fragments.take(), continuation) {
+            (None, false) => (message_type, payload),
+            (Some((message_type, mut message)), true) => {
+                message.extend_from_slice(&payload);
+                (message_type, message)
+            }
+            // a continuation frame without a message to continue, or a new message
+            // before the last one was finished
+            _ => return Err(WebSocketError::InvalidFrameError),
+        };
+        if !fin {
+            self.fragments = Some((message_type, payload));
+            return Ok(None);
+        }
+        let payload = Bytes::from(payload);
+        Ok(Some(match message_type {
+            MessageType::Text => Message::Text(
+                Utf8Bytes::try_from_bytes(payload)
+                    .map_err(|_e| WebSocketError::InvalidFrameError)?,
+            ),
+            MessageType::Binary => Message::Binary(payload),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+
+    #[tokio::test]
+    async fn fragmented_messages_are_assembled() {
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::connect(&server.url()).await.unwrap();
+        ws.send(Frame::text("grüß ".to_string()).set_fin(false))
+            .await
+            .unwrap();
+        ws.send_ping(None).await.unwrap();
+        ws.send(Frame::text("dich".to_string()).set_continuation(true))
+            .await
+            .unwrap();
+        ws.send_binary(vec![1, 2]).await.unwrap();
+
+        let message = ws.receive_message().await.unwrap().unwrap();
+        assert_eq!(message.as_text().unwrap(), "grüß dich");
+        assert_eq!(
+            ws.receive_message().await.unwrap(),
+            Some(Message::Binary(Bytes::from_static(&[1, 2])))
+        );
+    }
+}
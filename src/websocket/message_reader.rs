@@ -0,0 +1,167 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use super::frame::{Frame, MessageType};
+use super::split::WebSocketReadHalf;
+use crate::error::WebSocketError;
+
+type ReceiveFuture<'a> = Pin<
+    Box<
+        dyn Future<Output = (&'a mut WebSocketReadHalf, Result<Frame, WebSocketError>)> + Send + 'a,
+    >,
+>;
+
+/// Reads a single incoming message as a stream of bytes, generated from
+/// [`WebSocketReadHalf::message_reader()`].
+///
+/// The message's frames are received one at a time as the reader is read from,
+/// so a large fragmented message can be streamed (for example, to a file)
+/// without ever holding the entire message in memory:
+///
+/// ```
+/// # use websockets::WebSocket;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+/// let (mut read_half, _write_half) = ws.split();
+/// let mut reader = read_half.message_reader();
+/// let mut file = Vec::new(); // or a tokio::fs::File
+/// tokio::io::copy(&mut reader, &mut file).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Ping and Pong frames received in between the message's frames are handled
+/// as usual by [`receive()`](WebSocketReadHalf::receive()), but are not returned.
+/// If a Close frame is received before the end of the message, reading fails with
+/// an [`UnexpectedEof`](ErrorKind::UnexpectedEof) error and the Close frame
+/// is returned by the next call to [`receive()`](WebSocketReadHalf::receive()).
+pub struct MessageReader<'a> {
+    /// None while a frame is being received
+    read_half: Option<&'a mut WebSocketReadHalf>,
+    receiving: Option<ReceiveFuture<'a>>,
+    message_type: Option<MessageType>,
+    /// Payload of the last received frame
+    payload: Vec<u8>,
+    /// How much of the payload has been read
+    position: usize,
+    /// Whether the final frame of the message has been received
+    finished: bool,
+}
+
+impl Debug for MessageReader<'_> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("MessageReader")
+            .field("message_type", &self.message_type)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+impl<'a> MessageReader<'a> {
+    pub(super) fn new(read_half: &'a mut WebSocketReadHalf) -> Self {
+        Self {
+            read_half: Some(read_half),
+            receiving: None,
+            message_type: None,
+            payload: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns the type of the message, or None if its first frame
+    /// has not been received yet.
+    pub fn message_type(&self) -> Option<MessageType> {
+        self.message_type
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), IoError> {
+        let (message_type, payload, continuation, fin) = match frame {
+            Frame::Text {
+                payload,
+                continuation,
+                fin,
+            } => (MessageType::Text, payload.into_bytes(), continuation, fin),
+            Frame::Binary {
+                payload,
+                continuation,
+                fin,
+            } => (MessageType::Binary, payload, continuation, fin),
+            Frame::Close { .. } | Frame::CloseRaw { .. } => {
+                self.finished = true;
+                if let Some(read_half) = &mut self.read_half {
+                    read_half.pending_frames.push_back(frame);
+                }
+                return Err(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    "received Close frame before the end of the message",
+                ));
+            }
+            // already handled by receive()
+            Frame::Ping { .. } | Frame::Pong { .. } => return Ok(()),
+        };
+        // only the first frame of the message is not a continuation frame
+        // (https://tools.ietf.org/html/rfc6455#section-5.4)
+        if continuation != self.message_type.is_some() {
+            self.finished = true;
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                WebSocketError::InvalidFrameError,
+            ));
+        }
+        self.message_type = Some(message_type);
+        self.payload = payload;
+        self.position = 0;
+        self.finished = fin;
+        Ok(())
+    }
+}
+
+impl AsyncRead for MessageReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.payload.len() {
+                let len = buf.remaining().min(this.payload.len() - this.position);
+                buf.put_slice(&this.payload[this.position..this.position + len]);
+                this.position += len;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                // end of the message
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.receiving.is_none() {
+                let read_half = this
+                    .read_half
+                    .take()
+                    .expect("read half is only taken while receiving");
+                this.receiving = Some(Box::pin(async move {
+                    let result = read_half.receive().await;
+                    (read_half, result)
+                }));
+            }
+            let (read_half, result) = ready!(this.receiving.as_mut().unwrap().as_mut().poll(cx));
+            this.receiving = None;
+            this.read_half = Some(read_half);
+            match result {
+                Ok(frame) => this.handle_frame(frame)?,
+                Err(e) => {
+                    this.finished = true;
+                    return Poll::Ready(Err(IoError::other(e)));
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,73 @@
+pub mod batch;
 pub mod builder;
+pub mod capabilities;
+pub mod close_code;
+pub mod codec;
+pub mod connect_limiter;
+pub mod connection;
+pub mod connection_info;
+pub mod connection_state;
+mod dedup;
+#[cfg(feature = "hickory-dns")]
+pub mod dns;
+pub mod driver;
+pub mod entropy;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fault;
 pub mod frame;
-mod handshake;
+pub(crate) mod handshake;
+pub mod handshake_request;
+mod http;
+pub mod logging;
+pub mod message;
+pub mod message_reader;
+pub mod negotiation;
 mod parsed_addr;
+pub mod recording;
+pub mod resumable;
+pub mod reuse;
 pub mod split;
+pub mod stats;
 mod stream;
+#[cfg(feature = "task")]
+pub mod task;
+#[cfg(any(test, feature = "test-util"))]
+mod throttle;
+pub mod transport;
+#[cfg(feature = "tungstenite")]
+pub mod tungstenite_compat;
+#[cfg(feature = "macros")]
+pub mod typed;
+mod wire_trace;
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::io::AsyncRead;
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
 
 use crate::error::WebSocketError;
 use builder::WebSocketBuilder;
-use frame::Frame;
-use split::{WebSocketReadHalf, WebSocketWriteHalf};
+use close_code::CloseCode;
+use connection_info::ConnectionInfo;
+use connection_state::ConnectionState;
+use driver::{AppHeartbeat, ConnectionDriver, Keepalive};
+use frame::{Frame, OpcodeSet};
+use handshake_request::InterimResponse;
+use message::{Message, MessageAssembler};
+use parsed_addr::ParsedAddr;
+use reuse::ConnectionReuseToken;
+use split::{CloseListener, MessageProgress, SendReceipt, WebSocketReadHalf, WebSocketWriteHalf};
+use stats::{ConnectTimings, Stats};
+
+/// How long to wait for the server's Close frame after the shutdown token is cancelled,
+/// or when closing a connection for reuse
+const SHUTDOWN_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 enum FrameType {
@@ -109,15 +168,28 @@ impl Default for FrameType {
 /// The write half is notified of these events, but it cannot act on them
 /// unless it is flushed. Events can be explicitly [`flush`](WebSocketWriteHalf::flush())ed,
 /// but sending a frame will also flush events. If frames are not being
-/// sent frequently, consider explicitly flushing events.
+/// sent frequently, consider explicitly flushing events, or give the read half an
+/// [`EventPump`](crate::EventPump) with [`WebSocketReadHalf::respond_control()`] so that it flushes
+/// events itself.
 ///
 /// Flushing is done automatically if you are using the the `WebSocket` type by itself.
+///
+/// Alternatively, use [`WebSocket::split_with_driver()`] to also get a [`ConnectionDriver`],
+/// which flushes events (and sends keepalive Pings, if configured) as soon as they occur.
+/// The driver must be spawned for this to happen.
 #[derive(Debug)]
 pub struct WebSocket {
     read_half: WebSocketReadHalf,
     write_half: WebSocketWriteHalf,
     accepted_subprotocol: Option<String>,
+    accepted_extensions: Option<String>,
     handshake_response_headers: Option<Vec<(String, String)>>,
+    interim_responses: Vec<InterimResponse>,
+    connection_info: Option<ConnectionInfo>,
+    health_checks: u64,
+    keepalive: Option<Keepalive>,
+    app_heartbeat: Option<AppHeartbeat>,
+    connect_timings: ConnectTimings,
 }
 
 impl WebSocket {
@@ -137,10 +209,83 @@ impl WebSocket {
     /// If the received frame is a Ping frame, a Pong frame will be sent.
     /// If the received frame is a Close frame, an echoed Close frame
     /// will be sent and the WebSocket will close.
+    ///
+    /// If a [shutdown token](WebSocketBuilder::shutdown_token()) was set and it is cancelled,
+    /// this method performs the closing handshake (sending a Close frame with status code 1001,
+    /// unless a Close frame has already been sent, and waiting up to 5 seconds
+    /// for the server's Close frame) and returns
+    /// [`CancelledError`](WebSocketError::CancelledError).
+    ///
+    /// Frames removed by the [frame filter](WebSocket::set_frame_filter())
+    /// are handled as above, then skipped.
     pub async fn receive(&mut self) -> Result<Frame, WebSocketError> {
-        let received_frame = self.read_half.receive().await?;
-        self.write_half.flush().await?;
-        Ok(received_frame)
+        loop {
+            let received_frame = match self.read_half.receive_and_handle().await {
+                Err(WebSocketError::CancelledError) => {
+                    self.close_after_cancelled().await?;
+                    return Err(WebSocketError::CancelledError);
+                }
+                // close the connection right away
+                Err(WebSocketError::PingFloodError) => {
+                    self.write_half.flush().await?;
+                    return Err(WebSocketError::PingFloodError);
+                }
+                received_frame => received_frame?,
+            };
+            self.write_half.flush().await?;
+            if self.read_half.passes_filter(&received_frame) {
+                return Ok(received_frame);
+            }
+        }
+    }
+
+    /// Receives a complete [`Message`] over the WebSocket connection, reassembling
+    /// fragmented messages. Ping and Pong frames are handled as by
+    /// [`receive()`](WebSocket::receive()) and skipped. Returns `Ok(None)` once
+    /// a Close frame has been received (use [`closed()`](WebSocket::closed())
+    /// to get its status code).
+    ///
+    /// The payload of a Text message is validated as UTF-8 once, in place, and is handed
+    /// out without being copied again.
+    pub async fn receive_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        let mut assembler = MessageAssembler::default();
+        loop {
+            let frame = self.receive().await?;
+            if frame.is_any_close() {
+                return Ok(None);
+            }
+            if let Some(message) = assembler.push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Receives a batch of messages sent in a single Binary frame
+    /// (see [`WebSocketReadHalf::receive_batched()`]).
+    pub async fn receive_batched(&mut self) -> Result<Option<Vec<Bytes>>, WebSocketError> {
+        match self.receive_message().await? {
+            Some(Message::Binary(payload)) => batch::decode_batch(payload).map(Some),
+            Some(Message::Text(_)) => Err(WebSocketError::InvalidBatchError),
+            None => Ok(None),
+        }
+    }
+
+    /// Performs the closing handshake after the shutdown token has been cancelled.
+    async fn close_after_cancelled(&mut self) -> Result<(), WebSocketError> {
+        let sent_close_frame = {
+            let writer = self.write_half.writer.lock().await;
+            if writer.sent_closed {
+                return Ok(());
+            }
+            writer.sent_close_frame
+        };
+        // a Close frame is only sent once (https://tools.ietf.org/html/rfc6455#section-5.5.1)
+        if !sent_close_frame {
+            // https://tools.ietf.org/html/rfc6455#section-7.4.1
+            self.write_half.close(Some((1001, String::new()))).await?;
+        }
+        let _ = time::timeout(SHUTDOWN_CLOSE_TIMEOUT, self.read_half.receive_until_close()).await;
+        self.write_half.shutdown().await
     }
 
     /// Receives a [`Frame`] over the WebSocket connection **without handling incoming frames.**
@@ -155,7 +300,7 @@ impl WebSocket {
     }
 
     /// Sends an already constructed [`Frame`] over the WebSocket connection.
-    pub async fn send(&mut self, frame: Frame) -> Result<(), WebSocketError> {
+    pub async fn send(&mut self, frame: Frame) -> Result<SendReceipt, WebSocketError> {
         self.write_half.send(frame).await
     }
 
@@ -163,7 +308,7 @@ impl WebSocket {
     /// from passed arguments. `continuation` will be `false` and `fin` will be `true`.
     /// To use a custom `continuation` or `fin`, construct a [`Frame`] and use
     /// [`WebSocket::send()`].
-    pub async fn send_text(&mut self, payload: String) -> Result<(), WebSocketError> {
+    pub async fn send_text(&mut self, payload: String) -> Result<SendReceipt, WebSocketError> {
         self.write_half.send_text(payload).await
     }
 
@@ -171,10 +316,52 @@ impl WebSocket {
     /// from passed arguments. `continuation` will be `false` and `fin` will be `true`.
     /// To use a custom `continuation` or `fin`, construct a [`Frame`] and use
     /// [`WebSocket::send()`].
-    pub async fn send_binary(&mut self, payload: Vec<u8>) -> Result<(), WebSocketError> {
+    pub async fn send_binary(&mut self, payload: Vec<u8>) -> Result<SendReceipt, WebSocketError> {
         self.write_half.send_binary(payload).await
     }
 
+    /// Sends several small messages in a single Binary frame
+    /// (see [`WebSocketWriteHalf::send_batched()`]).
+    pub async fn send_batched<I>(&mut self, messages: I) -> Result<SendReceipt, WebSocketError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.write_half.send_batched(messages).await
+    }
+
+    /// Sends everything read from `reader` as a fragmented Binary message, in frames of
+    /// at most `chunk_size` bytes, and returns the number of bytes sent
+    /// (see [`WebSocketWriteHalf::send_reader()`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub async fn send_reader<R>(
+        &mut self,
+        reader: R,
+        chunk_size: usize,
+    ) -> Result<u64, WebSocketError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.write_half.send_reader(reader, chunk_size).await
+    }
+
+    /// Sends the contents of a file as a fragmented Binary message, in frames of
+    /// at most `chunk_size` bytes, and returns the number of bytes sent
+    /// (see [`WebSocketWriteHalf::send_reader()`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub async fn send_file<P>(&mut self, path: P, chunk_size: usize) -> Result<u64, WebSocketError>
+    where
+        P: AsRef<Path>,
+    {
+        self.write_half.send_file(path, chunk_size).await
+    }
+
     /// Sends a Close frame over the WebSocket connection, constructed
     /// from passed arguments, and closes the WebSocket connection.
     /// This method will attempt to wait for an echoed Close frame,
@@ -185,37 +372,167 @@ impl WebSocket {
 
     /// Sends a Ping frame over the WebSocket connection, constructed
     /// from passed arguments.
-    pub async fn send_ping(&mut self, payload: Option<Vec<u8>>) -> Result<(), WebSocketError> {
+    pub async fn send_ping(
+        &mut self,
+        payload: Option<Vec<u8>>,
+    ) -> Result<SendReceipt, WebSocketError> {
         self.write_half.send_ping(payload).await
     }
 
     /// Sends a Pong frame over the WebSocket connection, constructed
     /// from passed arguments.
-    pub async fn send_pong(&mut self, payload: Option<Vec<u8>>) -> Result<(), WebSocketError> {
+    pub async fn send_pong(
+        &mut self,
+        payload: Option<Vec<u8>>,
+    ) -> Result<SendReceipt, WebSocketError> {
         self.write_half.send_pong(payload).await
     }
 
+    /// Checks that the connection is alive by sending a Ping frame and waiting for
+    /// the server to respond with a matching Pong frame. This is intended to be used
+    /// by health checks, since it verifies that the server is actually responding
+    /// rather than just that the TCP connection is open.
+    ///
+    /// If no matching Pong frame is received within `timeout`,
+    /// [`PongTimeoutError`](WebSocketError::PongTimeoutError) is returned.
+    /// If the server closes the connection first,
+    /// [`WebSocketClosedError`](WebSocketError::WebSocketClosedError) is returned.
+    /// Any other frames received while waiting are handled as usual
+    /// and returned by later calls to [`receive()`](WebSocket::receive()),
+    /// even if the returned future is dropped before it completes.
+    pub async fn health_check(&mut self, timeout: Duration) -> Result<(), WebSocketError> {
+        // a unique payload so replies to other pings are not mistaken for this one
+        self.health_checks += 1;
+        let payload = self.health_checks.to_be_bytes().to_vec();
+        self.send_ping(Some(payload.clone())).await?;
+
+        let read_half = &mut self.read_half;
+        let write_half = &mut self.write_half;
+        let result = time::timeout(timeout, async {
+            loop {
+                let frame = read_half.receive_new_and_handle().await?;
+                let answered = matches!(
+                    &frame,
+                    Frame::Pong { payload: Some(received_payload) } if *received_payload == payload
+                );
+                let closed = frame.is_any_close();
+                // kept right away, so that it is not lost if this future is dropped
+                // (pending frames are filtered when they are received)
+                if !answered {
+                    read_half.pending_frames.push_back(frame);
+                }
+                write_half.flush().await?;
+                if answered {
+                    return Ok(());
+                }
+                if closed {
+                    return Err(WebSocketError::WebSocketClosedError);
+                }
+            }
+        })
+        .await;
+        result.unwrap_or(Err(WebSocketError::PongTimeoutError))
+    }
+
     /// Shuts down the WebSocket connection **without sending a Close frame**.
     /// It is recommended to use the [`close()`](WebSocket::close()) method instead.
     pub async fn shutdown(&mut self) -> Result<(), WebSocketError> {
         self.write_half.shutdown().await
     }
 
+    /// Performs the closing handshake (sending a Close frame and waiting for the server's
+    /// Close frame), then returns the connection instead of closing it, so that it can be
+    /// [reused](WebSocketBuilder::reuse_connection()) for another WebSocket session with the
+    /// same server (see [`ConnectionReuseToken`]). Frames received before the server's
+    /// Close frame are discarded.
+    ///
+    /// Fails with [`ConnectionNotReusableError`](WebSocketError::ConnectionNotReusableError)
+    /// if the connection cannot be kept open (including when the server's Close frame
+    /// is not received within 5 seconds), in which case it is closed.
+    pub async fn close_for_reuse(
+        mut self,
+        payload: Option<(u16, String)>,
+    ) -> Result<ConnectionReuseToken, WebSocketError> {
+        let connection_info = self
+            .connection_info
+            .take()
+            .ok_or(WebSocketError::ConnectionNotReusableError)?;
+        self.write_half.close(payload).await?;
+        // the server's Close frame is not echoed, since a Close frame has already been sent
+        time::timeout(SHUTDOWN_CLOSE_TIMEOUT, self.read_half.receive_until_close())
+            .await
+            .map_err(|_| WebSocketError::ConnectionNotReusableError)??;
+        if self.read_half.buffered_bytes() > 0 {
+            return Err(WebSocketError::ConnectionNotReusableError);
+        }
+        let writer = match Arc::try_unwrap(self.write_half.writer) {
+            Ok(writer) => writer.into_inner(),
+            // the connection driver still has the writer
+            Err(_) => return Err(WebSocketError::ConnectionNotReusableError),
+        };
+        if writer.shutdown || writer.sent_closed {
+            return Err(WebSocketError::ConnectionNotReusableError);
+        }
+        let stream = self
+            .read_half
+            .stream
+            .into_inner()
+            .unsplit(writer.stream.into_inner());
+        Ok(ConnectionReuseToken {
+            stream,
+            parsed_addr: ParsedAddr::try_from(connection_info.url())?,
+            addr: connection_info.addr(),
+        })
+    }
+
     /// Splits the WebSocket into a read half and a write half, which can be used separately.
-    /// [Accepted subprotocol](WebSocket::accepted_subprotocol())
-    /// and [handshake response headers](WebSocket::handshake_response_headers()) data
+    /// [Accepted subprotocol](WebSocket::accepted_subprotocol()),
+    /// [accepted extensions](WebSocket::accepted_extensions()),
+    /// [handshake response headers](WebSocket::handshake_response_headers()),
+    /// [interim responses](WebSocket::interim_responses()),
+    /// and [connection info](WebSocket::connection_info()) data
     /// will be lost.
     pub fn split(self) -> (WebSocketReadHalf, WebSocketWriteHalf) {
         (self.read_half, self.write_half)
     }
 
+    /// Splits the WebSocket into a read half, a write half, and a [`ConnectionDriver`].
+    /// The driver flushes events and sends keepalive Pings (and application heartbeats)
+    /// on behalf of the halves,
+    /// so it must be spawned (see the [`ConnectionDriver`] documentation).
+    /// [Accepted subprotocol](WebSocket::accepted_subprotocol()),
+    /// [accepted extensions](WebSocket::accepted_extensions()),
+    /// [handshake response headers](WebSocket::handshake_response_headers()),
+    /// [interim responses](WebSocket::interim_responses()),
+    /// and [connection info](WebSocket::connection_info()) data
+    /// will be lost.
+    pub fn split_with_driver(self) -> (WebSocketReadHalf, WebSocketWriteHalf, ConnectionDriver) {
+        let driver = ConnectionDriver::new(
+            self.write_half.writer.clone(),
+            self.write_half.receiver.clone(),
+            self.write_half.activity.clone(),
+            self.keepalive,
+            self.app_heartbeat,
+            self.read_half.shutdown_token.clone(),
+            self.read_half.logger,
+        );
+        (self.read_half, self.write_half, driver)
+    }
+
     /// Joins together a split read half and write half to reconstruct a WebSocket.
     pub fn join(read_half: WebSocketReadHalf, write_half: WebSocketWriteHalf) -> Self {
         Self {
             read_half,
             write_half,
             accepted_subprotocol: None,
+            accepted_extensions: None,
             handshake_response_headers: None,
+            interim_responses: Vec::new(),
+            connection_info: None,
+            health_checks: 0,
+            keepalive: None,
+            app_heartbeat: None,
+            connect_timings: ConnectTimings::default(),
         }
     }
 
@@ -226,10 +543,141 @@ impl WebSocket {
         &self.accepted_subprotocol
     }
 
+    /// Returns the `Sec-WebSocket-Extensions` header that was returned by the server
+    /// during the handshake, if an offer was set with
+    /// [`WebSocketBuilder::extensions_offer()`] and the server accepted any extensions.
+    /// This data will be lost if the WebSocket is [`split`](WebSocket::split()).
+    pub fn accepted_extensions(&self) -> &Option<String> {
+        // https://tools.ietf.org/html/rfc6455#section-9.1
+        &self.accepted_extensions
+    }
+
     /// Returns the headers that were returned by the server during the handshake.
     /// This data will be lost if the WebSocket is [`split`](WebSocket::split()).
     pub fn handshake_response_headers(&self) -> &Option<Vec<(String, String)>> {
         // https://tools.ietf.org/html/rfc6455#section-4.2.2
         &self.handshake_response_headers
     }
+
+    /// Returns the informational (1xx) responses, such as `100 Continue`, that were
+    /// sent by the server before its handshake response.
+    /// This data will be lost if the WebSocket is [`split`](WebSocket::split()).
+    pub fn interim_responses(&self) -> &[InterimResponse] {
+        &self.interim_responses
+    }
+
+    /// Returns information about the URL that the WebSocket connected to,
+    /// such as its host and port.
+    /// This data will be lost if the WebSocket is [`split`](WebSocket::split()).
+    pub fn connection_info(&self) -> &Option<ConnectionInfo> {
+        &self.connection_info
+    }
+
+    /// Sets which frame types are returned when receiving
+    /// (see [`WebSocketReadHalf::set_frame_filter()`]).
+    pub fn set_frame_filter(&mut self, filter: OpcodeSet) {
+        self.read_half.set_frame_filter(filter)
+    }
+
+    /// Returns the current [frame filter](WebSocket::set_frame_filter()).
+    pub fn frame_filter(&self) -> OpcodeSet {
+        self.read_half.frame_filter()
+    }
+
+    /// Returns the number of bytes which have been read from the connection
+    /// but not yet returned as frames
+    /// (see [`WebSocketReadHalf::buffered_bytes()`]).
+    pub fn buffered_bytes(&self) -> usize {
+        self.read_half.buffered_bytes()
+    }
+
+    /// Returns statistics about the frames sent and received over the connection.
+    pub fn stats(&self) -> Stats {
+        self.read_half.stats()
+    }
+
+    /// Returns how long each phase of connecting took, to find out where the time to
+    /// connect is spent. This data will be lost if the WebSocket is
+    /// [`split`](WebSocket::split()).
+    pub fn connect_timings(&self) -> ConnectTimings {
+        self.connect_timings
+    }
+
+    /// Returns a future which resolves with the status code and reason of the
+    /// server's Close frame once the connection terminates
+    /// (see [`CloseListener::closed()`]). The future does not borrow the WebSocket,
+    /// and [`close_listener()`](WebSocket::close_listener()) returns a handle which
+    /// can be cloned and passed to other tasks to do the same.
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let mut ws = WebSocket::connect("wss://echo.websocket.org").await?;
+    /// let closed = ws.closed();
+    /// ws.close(Some((1000, String::new()))).await?;
+    /// while !ws.receive().await?.is_close() {}
+    /// let (close_code, _reason) = closed.await;
+    /// assert_eq!(close_code.0, 1000);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn closed(&self) -> impl Future<Output = (CloseCode, String)> + Send + 'static {
+        self.read_half.close_listener().closed()
+    }
+
+    /// Returns a [`CloseListener`] for waiting until the connection terminates.
+    pub fn close_listener(&self) -> CloseListener {
+        self.read_half.close_listener()
+    }
+
+    /// Returns a receiver of the [`ConnectionState`] of the connection, which is also
+    /// updated while the connection is [redialed](ConnectionInfo::redial()).
+    /// See the [`ConnectionState`] documentation for an example.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.read_half.connection_state()
+    }
+
+    /// Returns a future which resolves once every frame queued before the barrier
+    /// has been written and flushed to the OS
+    /// (see [`WebSocketWriteHalf::barrier()`]).
+    pub fn barrier(&self) -> impl Future<Output = Result<(), WebSocketError>> + Send + 'static {
+        self.write_half.barrier()
+    }
+
+    /// Returns whether the last frame written has not been flushed to the connection yet
+    /// (see [`WebSocketWriteHalf::is_write_blocked()`]).
+    pub fn is_write_blocked(&self) -> bool {
+        self.write_half.is_write_blocked()
+    }
+
+    /// Waits until the connection is writable again
+    /// (see [`WebSocketWriteHalf::writable()`]).
+    pub async fn writable(&self) -> Result<(), WebSocketError> {
+        self.write_half.writable().await
+    }
+
+    /// Returns when the last frame was received, or when the connection was established
+    /// if no frames have been received.
+    pub fn last_received_at(&self) -> Instant {
+        self.read_half.last_received_at()
+    }
+
+    /// Returns when the last frame was sent, or when the connection was established
+    /// if no frames have been sent.
+    pub fn last_sent_at(&self) -> Instant {
+        self.write_half.last_sent_at()
+    }
+
+    /// Returns a channel which reports the progress of fragmented messages as they are sent
+    /// (see [`WebSocketWriteHalf::send_progress()`]).
+    pub fn send_progress(&self) -> watch::Receiver<Option<MessageProgress>> {
+        self.write_half.send_progress()
+    }
+
+    /// Returns a channel which reports the progress of fragmented messages as they are received
+    /// (see [`WebSocketReadHalf::receive_progress()`]).
+    pub fn receive_progress(&self) -> watch::Receiver<Option<MessageProgress>> {
+        self.read_half.receive_progress()
+    }
 }
@@ -0,0 +1,192 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+
+use url::Url;
+
+use super::builder::WebSocketBuilder;
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+/// An ordered offer of application protocols (or versions of one protocol), negotiated
+/// with the server as subprotocols, which maps the subprotocol the server accepted
+/// back to a value of the application's own type.
+///
+/// Protocols are offered in the order they are added, most preferred first. Version
+/// parameters are added to the query of the URL connected to, for servers which
+/// select the version of their protocol from the URL.
+///
+/// ```no_run
+/// # use websockets::{ProtocolOffer, WebSocket, WebSocketError};
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// enum GraphqlProtocol {
+///     TransportWs,
+///     SubscriptionsTransportWs,
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let (ws, protocol) = ProtocolOffer::new()
+///     .protocol("graphql-transport-ws", GraphqlProtocol::TransportWs)
+///     .protocol("graphql-ws", GraphqlProtocol::SubscriptionsTransportWs)
+///     .version_param("client_version", "2")
+///     .connect(&mut WebSocket::builder(), "wss://api.example.com/graphql")
+///     .await?;
+/// match protocol {
+///     GraphqlProtocol::TransportWs => { /* ... */ }
+///     GraphqlProtocol::SubscriptionsTransportWs => { /* ... */ }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ProtocolOffer<P> {
+    protocols: Vec<(String, P)>,
+    fallback: Option<P>,
+    version_params: Vec<(String, String)>,
+}
+
+impl<P> Debug for ProtocolOffer<P> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        let subprotocols: Vec<_> = self.protocols.iter().map(|(name, _)| name).collect();
+        f.debug_struct("ProtocolOffer")
+            .field("subprotocols", &subprotocols)
+            .field("version_params", &self.version_params)
+            .finish()
+    }
+}
+
+impl<P> Default for ProtocolOffer<P> {
+    fn default() -> Self {
+        Self {
+            protocols: Vec::new(),
+            fallback: None,
+            version_params: Vec::new(),
+        }
+    }
+}
+
+impl<P: Clone> ProtocolOffer<P> {
+    /// Constructs an empty offer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a protocol to the offer, after the protocols already added, which is
+    /// negotiated as `subprotocol` and returned as `protocol` if the server accepts it.
+    pub fn protocol(&mut self, subprotocol: &str, protocol: P) -> &mut Self {
+        self.protocols.push((subprotocol.to_string(), protocol));
+        self
+    }
+
+    /// Sets the protocol returned if the server accepts none of the subprotocols
+    /// (as servers which do not implement subprotocols do).
+    /// A value of None fails connecting with
+    /// [`NoProtocolAcceptedError`](WebSocketError::NoProtocolAcceptedError) instead.
+    /// Defaults to None.
+    pub fn fallback(&mut self, protocol: Option<P>) -> &mut Self {
+        self.fallback = protocol;
+        self
+    }
+
+    /// Adds a query parameter carrying a protocol version (such as `vsn=2.0.0`),
+    /// which is appended to the URL connected to.
+    pub fn version_param(&mut self, name: &str, value: &str) -> &mut Self {
+        self.version_params
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Returns the URL with the version parameters appended to its query.
+    pub fn url(&self, url: &str) -> Result<String, WebSocketError> {
+        let mut url = Url::parse(url).map_err(WebSocketError::ParseError)?;
+        if !self.version_params.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.version_params);
+        }
+        Ok(url.into())
+    }
+
+    /// Adds the subprotocols to the builder and connects to the URL (with the version
+    /// parameters appended), returning the WebSocket along with the protocol that the
+    /// server accepted.
+    ///
+    /// Fails with [`NoProtocolAcceptedError`](WebSocketError::NoProtocolAcceptedError)
+    /// if the server accepted none of the subprotocols and there is no
+    /// [fallback](ProtocolOffer::fallback()).
+    pub async fn connect(
+        &self,
+        builder: &mut WebSocketBuilder,
+        url: &str,
+    ) -> Result<(WebSocket, P), WebSocketError> {
+        for (subprotocol, _) in &self.protocols {
+            builder.add_subprotocol(subprotocol);
+        }
+        let ws = builder.connect(&self.url(url)?).await?;
+        let protocol = self
+            .accepted(ws.accepted_subprotocol().as_deref())
+            .ok_or(WebSocketError::NoProtocolAcceptedError)?;
+        Ok((ws, protocol))
+    }
+
+    /// Returns the protocol for the subprotocol accepted by the server
+    /// (see [`WebSocket::accepted_subprotocol()`]), or the fallback if there is none.
+    pub fn accepted(&self, subprotocol: Option<&str>) -> Option<P> {
+        let accepted = subprotocol.and_then(|subprotocol| {
+            self.protocols
+                .iter()
+                .find(|(name, _)| name == subprotocol)
+                .map(|(_, protocol)| protocol.clone())
+        });
+        accepted.or_else(|| self.fallback.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{scripted_server_with, EchoServer};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Chat {
+        V2,
+        V1,
+        Legacy,
+    }
+
+    #[tokio::test]
+    async fn accepted_subprotocols_are_mapped_to_protocols() {
+        // a server which only supports chat.v1
+        let extra_headers = "Sec-WebSocket-Protocol: chat.v1\r\n".to_string();
+        let (url, mut requests) = scripted_server_with(vec![(extra_headers, b"")]).await;
+
+        let mut offer = ProtocolOffer::new();
+        offer
+            .protocol("chat.v2", Chat::V2)
+            .protocol("chat.v1", Chat::V1)
+            .version_param("vsn", "2");
+        let (_ws, protocol) = offer
+            .connect(&mut WebSocket::builder(), &format!("{}chat?room=1", url))
+            .await
+            .unwrap();
+        assert_eq!(protocol, Chat::V1);
+        // the version parameter is added to the URL
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request[0], "GET /chat?room=1&vsn=2 HTTP/1.1");
+        assert!(request
+            .iter()
+            .any(|line| line == "Sec-WebSocket-Protocol: chat.v2, chat.v1"));
+
+        // the echo server does not accept subprotocols
+        let server = EchoServer::spawn().await;
+        assert!(matches!(
+            offer
+                .connect(&mut WebSocket::builder(), &server.url())
+                .await,
+            Err(WebSocketError::NoProtocolAcceptedError)
+        ));
+        offer.fallback(Some(Chat::Legacy));
+        let (_ws, protocol) = offer
+            .connect(&mut WebSocket::builder(), &server.url())
+            .await
+            .unwrap();
+        assert_eq!(protocol, Chat::Legacy);
+    }
+}
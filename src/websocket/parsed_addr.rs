@@ -1,39 +1,193 @@
 use std::convert::TryFrom;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
 
-use url::Url;
+use tokio::net;
+use url::{Host, Url};
 
 use crate::WebSocketError;
 
+/// A parsed WebSocket URL. The host is not resolved until [`ParsedAddr::resolve()`]
+/// is called, so that it can instead be passed to a proxy to resolve.
 #[derive(Debug)]
 pub(super) struct ParsedAddr {
     pub scheme: String,
     pub host: String,
+    /// The host without the brackets around IPv6 addresses, used for
+    /// name resolution and to verify the server's TLS certificate
+    pub server_name: String,
+    pub port: u16,
     pub path: String,
-    pub addr: SocketAddr,
+    /// The path of the Unix domain socket to connect to, for `ws+unix` URLs
+    #[cfg(unix)]
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl ParsedAddr {
+    /// Resolves the host to a socket address.
+    pub(super) async fn resolve(&self) -> Result<SocketAddr, WebSocketError> {
+        net::lookup_host((&self.server_name[..], self.port))
+            .await
+            .map_err(|e| WebSocketError::SocketAddrError(e))?
+            .next()
+            .ok_or(WebSocketError::ResolutionError)
+    }
 }
 
 impl TryFrom<&str> for ParsedAddr {
     type Error = WebSocketError;
 
     fn try_from(url: &str) -> Result<Self, Self::Error> {
+        #[cfg(unix)]
+        if let Some(unix_url) = url.strip_prefix("ws+unix://") {
+            return parse_unix(unix_url);
+        }
         let parsed_url = Url::parse(url).map_err(|e| WebSocketError::ParseError(e))?;
         let scheme = parsed_url.scheme();
         let host = parsed_url.host_str().ok_or(WebSocketError::HostError)?;
-        let path = parsed_url.path();
+        let server_name = match parsed_url.host() {
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            _ => host.to_string(),
+        };
+        let path = resource_name(&parsed_url);
         let port = parsed_url
             .port_or_known_default()
             .ok_or(WebSocketError::PortError)?;
-        let addr = (host, port)
-            .to_socket_addrs()
-            .map_err(|e| WebSocketError::SocketAddrError(e))?
-            .next()
-            .ok_or(WebSocketError::ResolutionError)?;
         Ok(ParsedAddr {
             scheme: scheme.to_string(),
             host: host.to_string(),
-            path: path.to_string(),
-            addr,
+            server_name,
+            port,
+            path,
+            #[cfg(unix)]
+            unix_socket: None,
         })
     }
 }
+
+/// Parses the rest of a `ws+unix://` URL, which is the path of the socket,
+/// optionally followed by a colon and the resource name (such as `/var/run/app.sock:/events`).
+#[cfg(unix)]
+fn parse_unix(unix_url: &str) -> Result<ParsedAddr, WebSocketError> {
+    let (socket, resource) = unix_url.split_once(':').unwrap_or((unix_url, "/"));
+    if socket.is_empty() {
+        return Err(WebSocketError::HostError);
+    }
+    // the resource name is parsed (and encoded) as the path and query of an ordinary URL
+    let resource = format!("ws://localhost/{}", resource.trim_start_matches('/'));
+    let parsed_url = Url::parse(&resource).map_err(WebSocketError::ParseError)?;
+    Ok(ParsedAddr {
+        scheme: "ws+unix".to_string(),
+        // sent in the Host header, which must not be empty
+        host: "localhost".to_string(),
+        server_name: "localhost".to_string(),
+        port: 80,
+        path: resource_name(&parsed_url),
+        unix_socket: Some(PathBuf::from(socket)),
+    })
+}
+
+/// Returns the resource name (the request-target of the handshake request) for a URL,
+/// which is its path and query.
+fn resource_name(url: &Url) -> String {
+    // https://tools.ietf.org/html/rfc6455#section-3
+    // dot segments (path traversal) have already been removed by the URL parser
+    let mut resource_name = url.path().to_string();
+    if let Some(query) = url.query() {
+        resource_name.push('?');
+        resource_name.push_str(query);
+    }
+    // the URL parser percent-encodes most characters, but make sure nothing which would
+    // break the request line (such as whitespace or control characters) gets through
+    let mut encoded = String::with_capacity(resource_name.len());
+    for byte in resource_name.bytes() {
+        if byte.is_ascii_graphic() {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource_name_of(url: &str) -> String {
+        resource_name(&Url::parse(url).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ip_literals() {
+        let parsed_addr = ParsedAddr::try_from("wss://10.0.0.5/").unwrap();
+        assert_eq!(parsed_addr.host, "10.0.0.5");
+        assert_eq!(parsed_addr.server_name, "10.0.0.5");
+        assert_eq!(
+            parsed_addr.resolve().await.unwrap(),
+            "10.0.0.5:443".parse().unwrap()
+        );
+
+        let parsed_addr = ParsedAddr::try_from("wss://[::1]:8443/").unwrap();
+        assert_eq!(parsed_addr.host, "[::1]");
+        assert_eq!(parsed_addr.server_name, "::1");
+        assert_eq!(
+            parsed_addr.resolve().await.unwrap(),
+            "[::1]:8443".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn parsing_does_not_resolve() {
+        // a host which cannot be resolved, as when it is only known to a proxy
+        let parsed_addr = ParsedAddr::try_from("ws://service.invalid:8080/").unwrap();
+        assert_eq!(parsed_addr.server_name, "service.invalid");
+        assert_eq!(parsed_addr.port, 8080);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_urls() {
+        let parsed_addr = ParsedAddr::try_from("ws+unix:///var/run/app.sock:/events?v=2").unwrap();
+        assert_eq!(
+            parsed_addr.unix_socket,
+            Some(PathBuf::from("/var/run/app.sock"))
+        );
+        assert_eq!(parsed_addr.path, "/events?v=2");
+        assert_eq!(parsed_addr.host, "localhost");
+
+        let parsed_addr = ParsedAddr::try_from("ws+unix://app.sock").unwrap();
+        assert_eq!(parsed_addr.unix_socket, Some(PathBuf::from("app.sock")));
+        assert_eq!(parsed_addr.path, "/");
+
+        assert!(matches!(
+            ParsedAddr::try_from("ws+unix://:/events"),
+            Err(WebSocketError::HostError)
+        ));
+    }
+
+    #[test]
+    fn resource_name_includes_query() {
+        assert_eq!(resource_name_of("ws://example.com"), "/");
+        assert_eq!(resource_name_of("ws://example.com/chat"), "/chat");
+        assert_eq!(
+            resource_name_of("ws://example.com/chat?room=1&user=a#fragment"),
+            "/chat?room=1&user=a"
+        );
+    }
+
+    #[test]
+    fn resource_name_is_encoded() {
+        assert_eq!(resource_name_of("ws://example.com/a b"), "/a%20b");
+        assert_eq!(resource_name_of("ws://example.com/a\tb\r\n"), "/ab");
+        assert_eq!(resource_name_of("ws://example.com/?q=a b"), "/?q=a%20b");
+        assert_eq!(resource_name_of("ws://example.com/\u{e9}"), "/%C3%A9");
+    }
+
+    #[test]
+    fn resource_name_removes_dot_segments() {
+        assert_eq!(resource_name_of("ws://example.com/a/../../etc"), "/etc");
+        assert_eq!(resource_name_of("ws://example.com/a/%2e%2e/b"), "/b");
+    }
+}
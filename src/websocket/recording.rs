@@ -0,0 +1,519 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error as IoError, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::handshake::GUUID;
+use super::transport::{Transport, TransportFuture, TransportStream};
+
+/// Whether a [`RecordedFrame`] was received from or sent to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameDirection {
+    /// Received from the server
+    Inbound,
+    /// Sent to the server
+    Outbound,
+}
+
+impl FrameDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inbound => "inbound",
+            Self::Outbound => "outbound",
+        }
+    }
+}
+
+/// A frame captured by a [`SessionRecorder`], as read back by a [`SessionReplay`].
+///
+/// The payload is recorded as it appears on the wire (unmasked), so the frame is kept
+/// exactly as it was sent or received, including continuation frames and invalid
+/// payloads which could not be represented as a [`Frame`](crate::Frame).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+    timestamp: SystemTime,
+    direction: FrameDirection,
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl RecordedFrame {
+    /// Returns when the frame was sent or received.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// Returns whether the frame was received from or sent to the server.
+    pub fn direction(&self) -> FrameDirection {
+        self.direction
+    }
+
+    /// Returns whether the frame was the final frame of its message.
+    pub fn fin(&self) -> bool {
+        self.fin
+    }
+
+    /// Returns the frame's opcode
+    /// (see [https://tools.ietf.org/html/rfc6455#section-5.2](https://tools.ietf.org/html/rfc6455#section-5.2)).
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    /// Returns the frame's unmasked payload.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Formats the frame as a line of JSON (without the line ending).
+    fn to_json(&self) -> String {
+        let timestamp_us = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        format!(
+            r#"{{"timestamp_us":{},"direction":"{}","fin":{},"opcode":{},"payload":"{}"}}"#,
+            timestamp_us,
+            self.direction.as_str(),
+            self.fin,
+            self.opcode,
+            base64::encode(&self.payload)
+        )
+    }
+
+    /// Parses a line written by [`to_json()`](RecordedFrame::to_json()). None of
+    /// the values contain commas, colons, or escaped characters, so the fields are
+    /// split apart directly instead of with a JSON parser.
+    fn from_json(line: &str) -> Option<Self> {
+        let fields = line.trim().strip_prefix('{')?.strip_suffix('}')?;
+        let (mut timestamp, mut direction, mut fin, mut opcode, mut payload) =
+            (None, None, None, None, None);
+        for field in fields.split(',') {
+            let (key, value) = field.split_once(':')?;
+            let string_value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+            match key {
+                r#""timestamp_us""# => {
+                    let micros = value.parse().ok()?;
+                    timestamp = Some(UNIX_EPOCH + Duration::from_micros(micros));
+                }
+                r#""direction""# => {
+                    direction = match string_value? {
+                        "inbound" => Some(FrameDirection::Inbound),
+                        "outbound" => Some(FrameDirection::Outbound),
+                        _ => return None,
+                    }
+                }
+                r#""fin""# => fin = Some(value.parse().ok()?),
+                r#""opcode""# => opcode = Some(value.parse().ok()?),
+                r#""payload""# => payload = Some(base64::decode(string_value?).ok()?),
+                // ignore fields added by newer versions
+                _ => (),
+            }
+        }
+        Some(Self {
+            timestamp: timestamp?,
+            direction: direction?,
+            fin: fin?,
+            opcode: opcode?,
+            payload: payload?,
+        })
+    }
+
+    /// Encodes the frame as the server would send it (without masking)
+    fn encode(&self, buf: &mut Vec<u8>) {
+        // https://tools.ietf.org/html/rfc6455#section-5.2
+        buf.push(((self.fin as u8) << 7) | self.opcode);
+        match self.payload.len() {
+            len @ 0..=125 => buf.push(len as u8),
+            len @ 126..=0xFFFF => {
+                buf.push(126);
+                buf.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                buf.push(127);
+                buf.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        buf.extend_from_slice(&self.payload);
+    }
+}
+
+/// Records every frame sent and received over a WebSocket connection, with its
+/// timestamp and direction, as one line of JSON per frame. Set with
+/// [`WebSocketBuilder::record_session()`].
+///
+/// Recordings can be read back and replayed with a [`SessionReplay`], which is useful
+/// for debugging a production connection offline:
+///
+/// ```
+/// # use websockets::SessionRecorder;
+/// # use websockets::WebSocket;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let path = std::env::temp_dir().join("recorded_session.jsonl");
+/// let recorder = SessionRecorder::create(&path)?;
+/// let mut ws = WebSocket::builder()
+///     .record_session(recorder.clone())
+///     .connect("wss://echo.websocket.org")
+///     .await?;
+/// ws.send_text("foo".to_string()).await?;
+/// ws.receive().await?;
+/// ws.close(None).await?;
+/// recorder.flush()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Frames are written as they are sent and received, so a slow writer slows down
+/// the connection; buffering the writer (as [`create()`](SessionRecorder::create()) does)
+/// avoids a system call per frame. If writing fails, recording stops, and the error
+/// can be found with [`take_error()`](SessionRecorder::take_error()).
+/// Clones of a recorder share the same writer.
+#[derive(Clone)]
+pub struct SessionRecorder {
+    inner: Arc<StdMutex<RecorderInner>>,
+}
+
+struct RecorderInner {
+    writer: Box<dyn Write + Send>,
+    error: Option<IoError>,
+    failed: bool,
+}
+
+impl Debug for SessionRecorder {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("SessionRecorder")
+    }
+}
+
+impl SessionRecorder {
+    /// Creates a recorder which writes to `writer`.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self {
+            inner: Arc::new(StdMutex::new(RecorderInner {
+                writer: Box::new(writer),
+                error: None,
+                failed: false,
+            })),
+        }
+    }
+
+    /// Creates a recorder which writes to a new file at `path`
+    /// (truncating it if it already exists), through a buffer.
+    pub fn create<P>(path: P) -> Result<Self, IoError>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Flushes the recorder's writer, so that all frames recorded so far
+    /// are written out.
+    pub fn flush(&self) -> Result<(), IoError> {
+        self.inner.lock().unwrap().writer.flush()
+    }
+
+    /// Returns the error which stopped recording, if any. Recording does not resume
+    /// after the error is taken.
+    pub fn take_error(&self) -> Option<IoError> {
+        self.inner.lock().unwrap().error.take()
+    }
+
+    pub(super) fn record(&self, direction: FrameDirection, fin: bool, opcode: u8, payload: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.failed {
+            return;
+        }
+        let frame = RecordedFrame {
+            timestamp: SystemTime::now(),
+            direction,
+            fin,
+            opcode,
+            payload: payload.to_vec(),
+        };
+        if let Err(e) = writeln!(inner.writer, "{}", frame.to_json()) {
+            inner.error = Some(e);
+            inner.failed = true;
+        }
+    }
+}
+
+/// A session recorded by a [`SessionRecorder`], which can be replayed to application
+/// code by using it as the [`Transport`] of a WebSocket.
+///
+/// When connected to, the replay completes the WebSocket handshake in memory and then
+/// sends the recorded inbound frames, in order and without delay, followed by the end
+/// of the stream. Frames sent by the application are discarded, so the application
+/// sees exactly what the server sent during the recorded session:
+///
+/// ```
+/// # use websockets::SessionReplay;
+/// # use websockets::WebSocket;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let path = std::env::temp_dir().join("replayed_session.jsonl");
+/// # websockets::SessionRecorder::create(&path)?;
+/// let replay = SessionReplay::open(&path)?;
+/// let mut ws = WebSocket::builder()
+///     .transport(replay)
+///     // the host is not connected to, but must still resolve
+///     .connect("ws://127.0.0.1/")
+///     .await?;
+/// while let Ok(frame) = ws.receive().await {
+///     println!("{:?}", frame);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionReplay {
+    frames: Vec<RecordedFrame>,
+}
+
+impl SessionReplay {
+    /// Reads a recording from the file at `path`.
+    pub fn open<P>(path: P) -> Result<Self, IoError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a recording from `reader`. Fails with an [`InvalidData`](ErrorKind::InvalidData)
+    /// error if a line is not a recorded frame.
+    pub fn from_reader<R>(reader: R) -> Result<Self, IoError>
+    where
+        R: BufRead,
+    {
+        let mut frames = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame = RecordedFrame::from_json(&line).ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("line {} is not a recorded frame", i + 1),
+                )
+            })?;
+            frames.push(frame);
+        }
+        Ok(Self { frames })
+    }
+
+    /// Returns all of the recorded frames, in the order they were recorded.
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+}
+
+impl Transport for SessionReplay {
+    fn connect(&self, _addr: SocketAddr) -> TransportFuture<'_> {
+        let mut inbound = Vec::new();
+        for frame in &self.frames {
+            if frame.direction == FrameDirection::Inbound {
+                frame.encode(&mut inbound);
+            }
+        }
+        let stream = ReplayStream {
+            request: Vec::new(),
+            output: None,
+            inbound,
+            position: 0,
+            read_waker: None,
+        };
+        Box::pin(async move { Ok(Box::new(stream) as Box<dyn TransportStream>) })
+    }
+}
+
+/// The in-memory server side of a [`SessionReplay`]
+struct ReplayStream {
+    /// The handshake request received so far
+    request: Vec<u8>,
+    /// The handshake response followed by the inbound frames,
+    /// or None until the whole handshake request has been received
+    output: Option<Vec<u8>>,
+    inbound: Vec<u8>,
+    /// How much of the output has been read
+    position: usize,
+    read_waker: Option<Waker>,
+}
+
+impl ReplayStream {
+    /// Builds the handshake response once the whole request has been received
+    fn respond(&mut self) {
+        if !self.request.ends_with(b"\r\n\r\n") {
+            return;
+        }
+        let request = String::from_utf8_lossy(&self.request);
+        let key = request
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _value)| name.eq_ignore_ascii_case("sec-websocket-key"))
+            .map(|(_name, value)| value.trim())
+            .unwrap_or("");
+        // https://tools.ietf.org/html/rfc6455#section-4.2.2
+        let accept = base64::encode(Sha1::digest(format!("{}{}", key, GUUID).as_bytes()));
+        let mut output = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        )
+        .into_bytes();
+        output.append(&mut self.inbound);
+        self.output = Some(output);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for ReplayStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        let output = match &this.output {
+            Some(output) => output,
+            None => {
+                this.read_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+        // reading nothing once the output is exhausted signals the end of the stream
+        let len = buf.remaining().min(output.len() - this.position);
+        buf.put_slice(&output[this.position..this.position + len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ReplayStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        if this.output.is_none() {
+            this.request.extend_from_slice(buf);
+            this.respond();
+        }
+        // frames sent after the handshake are discarded
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Frame, WebSocket};
+
+    /// A writer which can be read back after it is given to a recorder
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<StdMutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_session_is_replayed() {
+        let recording = concat!(
+            r#"{"timestamp_us":1,"direction":"inbound","fin":false,"opcode":1,"payload":"aGVs"}"#,
+            "\n",
+            r#"{"timestamp_us":2,"direction":"outbound","fin":true,"opcode":2,"payload":"AQI="}"#,
+            "\n",
+            r#"{"timestamp_us":3,"direction":"inbound","fin":true,"opcode":0,"payload":"bG8="}"#,
+            "\n",
+            r#"{"timestamp_us":4,"direction":"inbound","fin":true,"opcode":8,"payload":"A+g="}"#,
+            "\n",
+        );
+        let replay = SessionReplay::from_reader(recording.as_bytes()).unwrap();
+        assert_eq!(replay.frames().len(), 4);
+        assert_eq!(replay.frames()[1].direction(), FrameDirection::Outbound);
+        assert_eq!(replay.frames()[1].payload(), &[1, 2]);
+
+        let buffer = SharedBuffer::default();
+        let mut ws = WebSocket::builder()
+            .transport(replay)
+            .record_session(SessionRecorder::new(buffer.clone()))
+            .connect("ws://127.0.0.1/")
+            .await
+            .unwrap();
+        ws.send_binary(vec![1, 2]).await.unwrap();
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(
+            frame.as_text().unwrap(),
+            (&"hel".to_string(), &false, &false)
+        );
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_text().unwrap(), (&"lo".to_string(), &true, &true));
+        assert!(matches!(ws.receive().await.unwrap(), Frame::Close { .. }));
+        assert!(ws.receive().await.is_err());
+
+        // the recording of the replayed session matches the original
+        // (aside from the timestamps, and the Close frame echoed back)
+        let rerecorded = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let rereplay = SessionReplay::from_reader(rerecorded.as_bytes()).unwrap();
+        let summary: Vec<_> = rereplay
+            .frames()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.direction(),
+                    frame.fin(),
+                    frame.opcode(),
+                    frame.payload(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (FrameDirection::Outbound, true, 2, &[1, 2][..]),
+                (FrameDirection::Inbound, false, 1, &b"hel"[..]),
+                (FrameDirection::Inbound, true, 0, &b"lo"[..]),
+                (FrameDirection::Inbound, true, 8, &[3, 232][..]),
+                (FrameDirection::Outbound, true, 8, &[3, 232][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_recordings_are_rejected() {
+        let error = SessionReplay::from_reader("{\"opcode\":1}\n".as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,184 @@
+use super::connection::WebSocketConnection;
+use super::frame::Frame;
+#[allow(unused_imports)] // for intra doc links
+use super::{connection_info::ConnectionInfo, WebSocket};
+use crate::error::WebSocketError;
+
+/// Accumulates a large binary message (such as a file download) which may be interrupted
+/// by a disconnect, so that it can be resumed from where it left off after reconnecting
+/// instead of being restarted from zero.
+///
+/// The data received before an interruption is kept, and [`offset()`](ResumableTransfer::offset())
+/// returns how many bytes of it there are. The application's protocol is responsible
+/// for asking the server to continue from that offset (like an HTTP Range request);
+/// the server then sends the rest of the data as a new binary message, which is appended.
+///
+/// ```no_run
+/// # use websockets::{ResumableTransfer, WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let mut ws = WebSocket::connect("wss://files.example.com").await?;
+/// let connection_info = ws.connection_info().clone().unwrap();
+/// let mut transfer = ResumableTransfer::new();
+/// loop {
+///     // ask for the rest of the file, which is all of it the first time
+///     ws.send_text(format!("GET report.pdf FROM {}", transfer.offset()))
+///         .await?;
+///     let result = loop {
+///         match transfer.receive(&mut ws).await {
+///             Ok(Some(frame)) => println!("received {:?} during the download", frame),
+///             result => break result,
+///         }
+///     };
+///     match result {
+///         Ok(_) => break,
+///         // the partial data is kept, so the download continues where it stopped
+///         Err(_) => ws = connection_info.redial().await?,
+///     }
+/// }
+/// let report = transfer.into_data();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResumableTransfer {
+    data: Vec<u8>,
+    /// Whether a message of the transfer is being received, so continuation frames belong to it
+    in_message: bool,
+    complete: bool,
+    interruptions: usize,
+}
+
+impl ResumableTransfer {
+    /// Creates a transfer with no data received yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a transfer which continues from data that was already received,
+    /// such as partial data saved to disk by an earlier run of the application.
+    pub fn with_data(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of bytes received so far, which is the offset the server
+    /// should resume sending from.
+    pub fn offset(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the data received so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the transfer, returning the data received so far.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns whether the final frame of the data has been received.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Returns the number of times the transfer has been
+    /// [interrupted](ResumableTransfer::interrupt()).
+    pub fn interruptions(&self) -> usize {
+        self.interruptions
+    }
+
+    /// Adds a received frame to the transfer, returning whether it was part of it.
+    /// Binary frames are part of the transfer, except for continuation frames of
+    /// messages which started before an interruption. Other frames are not.
+    pub fn push(&mut self, frame: &Frame) -> bool {
+        let (payload, continuation, fin) = match frame.as_binary() {
+            Some(binary) => binary,
+            None => return false,
+        };
+        if self.complete || *continuation != self.in_message {
+            return false;
+        }
+        self.data.extend_from_slice(payload);
+        self.in_message = !*fin;
+        self.complete = *fin;
+        true
+    }
+
+    /// Marks the transfer as interrupted (such as by a disconnect) in the middle of
+    /// a message, so that the data is resumed with a new message. This is done by
+    /// [`receive()`](ResumableTransfer::receive()) when receiving fails.
+    pub fn interrupt(&mut self) {
+        if self.in_message {
+            self.in_message = false;
+            self.interruptions += 1;
+        }
+    }
+
+    /// Receives frames from the connection and adds them to the transfer until it is
+    /// complete (returning `Ok(None)`), or until a frame which is not part of it is
+    /// received (returning the frame, so that the application can handle it and then
+    /// continue receiving). If receiving fails, the transfer is
+    /// [interrupted](ResumableTransfer::interrupt()) and the error is returned; the data
+    /// received so far is kept.
+    pub async fn receive<C>(&mut self, connection: &mut C) -> Result<Option<Frame>, WebSocketError>
+    where
+        C: WebSocketConnection + ?Sized,
+    {
+        while !self.complete {
+            let frame = match connection.receive().await {
+                Ok(frame) => frame,
+                Err(e) => {
+                    self.interrupt();
+                    return Err(e);
+                }
+            };
+            if !self.push(&frame) {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockWebSocket;
+
+    fn fragment(payload: &[u8], continuation: bool, fin: bool) -> Frame {
+        Frame::binary(payload.to_vec())
+            .set_continuation(continuation)
+            .set_fin(fin)
+    }
+
+    #[tokio::test]
+    async fn interrupted_transfers_resume_from_offset() {
+        let mut ws = MockWebSocket::new();
+        ws.receive_frame(fragment(b"abc", false, false))
+            .receive_frame(fragment(b"def", true, false))
+            .receive_error(WebSocketError::ReadStallTimeoutError);
+        let mut transfer = ResumableTransfer::new();
+        assert!(transfer.receive(&mut ws).await.is_err());
+        assert_eq!(transfer.offset(), 6);
+        assert_eq!(transfer.interruptions(), 1);
+
+        // a stale continuation frame is not part of the resumed transfer
+        let mut ws = MockWebSocket::new();
+        ws.receive_frame(fragment(b"xyz", true, true))
+            .receive_frame(fragment(b"gh", false, false))
+            .receive_frame(Frame::text("progress".to_string()))
+            .receive_frame(fragment(b"i", true, true));
+        assert!(transfer.receive(&mut ws).await.unwrap().is_some());
+        assert_eq!(
+            transfer.receive(&mut ws).await.unwrap(),
+            Some(Frame::text("progress".to_string()))
+        );
+        assert_eq!(transfer.receive(&mut ws).await.unwrap(), None);
+        assert!(transfer.is_complete());
+        assert_eq!(transfer.into_data(), b"abcdefghi");
+    }
+}
@@ -0,0 +1,141 @@
+use std::convert::TryFrom;
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::net::SocketAddr;
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::parsed_addr::ParsedAddr;
+use super::stream::Stream;
+#[allow(unused_imports)] // for intra doc links
+use super::WebSocket;
+
+/// A connection (including its TLS session, for `wss` URLs) which is still open after
+/// its WebSocket session was closed with [`WebSocket::close_for_reuse()`], so that
+/// the next session with the same server can skip the TCP and TLS handshakes.
+/// Passed to [`WebSocketBuilder::reuse_connection()`].
+///
+/// The WebSocket protocol expects the connection to be closed after the closing
+/// handshake, so this only works with servers which accept a new WebSocket handshake
+/// on the same connection.
+///
+/// ```no_run
+/// # use websockets::{WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let ws = WebSocket::connect("wss://ipc.example.com/first").await?;
+/// // ...
+/// let token = ws.close_for_reuse(None).await?;
+/// let ws = WebSocket::builder()
+///     .reuse_connection(token)
+///     .connect("wss://ipc.example.com/second")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ConnectionReuseToken {
+    pub(super) stream: Stream,
+    pub(super) parsed_addr: ParsedAddr,
+    pub(super) addr: Option<SocketAddr>,
+}
+
+impl Debug for ConnectionReuseToken {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("ConnectionReuseToken")
+            .field("scheme", &self.parsed_addr.scheme)
+            .field("host", &self.parsed_addr.host)
+            .field("port", &self.parsed_addr.port)
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl ConnectionReuseToken {
+    /// Returns the address of the server the connection is open to,
+    /// or `None` for `ws+unix` URLs.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Returns whether the connection can be used to connect to the URL,
+    /// which requires the same scheme, host, and port (the path may differ).
+    pub fn matches(&self, url: &str) -> bool {
+        match ParsedAddr::try_from(url) {
+            Ok(parsed_addr) => self.matches_addr(&parsed_addr),
+            Err(_) => false,
+        }
+    }
+
+    pub(super) fn matches_addr(&self, parsed_addr: &ParsedAddr) -> bool {
+        self.parsed_addr.scheme == parsed_addr.scheme
+            && self.parsed_addr.host == parsed_addr.host
+            && self.parsed_addr.port == parsed_addr.port
+            && self.unix_socket_matches(parsed_addr)
+    }
+
+    #[cfg(unix)]
+    fn unix_socket_matches(&self, parsed_addr: &ParsedAddr) -> bool {
+        self.parsed_addr.unix_socket == parsed_addr.unix_socket
+    }
+
+    #[cfg(not(unix))]
+    fn unix_socket_matches(&self, _parsed_addr: &ParsedAddr) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::test_util::{accept_handshake, scripted_server};
+    use crate::{Frame, WebSocket, WebSocketError};
+
+    #[tokio::test]
+    async fn connections_are_reused_for_the_next_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // a server which accepts a new handshake after each closing handshake
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            loop {
+                if accept_handshake(&mut stream, "").await.unwrap().is_empty() {
+                    return;
+                }
+                // the client's masked Close frame without a payload
+                let mut close = [0; 6];
+                stream.read_exact(&mut close).await.unwrap();
+                stream.write_all(b"\x88\x00").await.unwrap();
+            }
+        });
+
+        let ws = WebSocket::connect(&format!("ws://{}/first", addr))
+            .await
+            .unwrap();
+        let token = ws.close_for_reuse(None).await.unwrap();
+        assert!(token.matches(&format!("ws://{}/second", addr)));
+        assert!(!token.matches(&format!("wss://{}/second", addr)));
+
+        let mut ws = WebSocket::builder()
+            .reuse_connection(token)
+            .connect(&format!("ws://{}/second", addr))
+            .await
+            .unwrap();
+        assert_eq!(ws.connect_timings().connect, Default::default());
+        ws.close(None).await.unwrap();
+        assert_eq!(ws.receive().await.unwrap(), Frame::close(None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closing_for_reuse_gives_up_without_a_close_frame() {
+        // a server which never answers the client's Close frame
+        let url = scripted_server(b"").await;
+
+        let ws = WebSocket::connect(&url).await.unwrap();
+        assert!(matches!(
+            ws.close_for_reuse(None).await,
+            Err(WebSocketError::ConnectionNotReusableError)
+        ));
+    }
+}
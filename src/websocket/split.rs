@@ -1,12 +1,36 @@
-use flume::{Receiver, Sender};
-use rand_chacha::ChaCha20Rng;
-use tokio::io::{AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
 
-use super::frame::Frame;
+use bytes::Bytes;
+use flume::{Receiver, Sender, TrySendError};
+use tokio::fs::File;
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf,
+};
+use tokio::sync::{watch, Mutex};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+
+use super::batch;
+use super::close_code::CloseCode;
+use super::connection_state::{ConnectionState, StateTracker};
+use super::dedup::DuplicateFilter;
+use super::entropy::EntropySource;
+use super::frame::{Frame, FrameHeader, MessageType, OpcodeSet};
+use super::logging::Logger;
+use super::message::{Message, MessageAssembler};
+use super::message_reader::MessageReader;
+use super::recording::{FrameDirection, SessionRecorder};
+use super::stats::{self, DropListener, DropReason, Stats};
 use super::stream::Stream;
 use super::FrameType;
 #[allow(unused_imports)] // for intra doc links
-use super::WebSocket;
+use super::{driver::ConnectionDriver, WebSocket};
 use crate::error::WebSocketError;
 
 /// Events sent from the read half to the write half
@@ -14,6 +38,9 @@ use crate::error::WebSocketError;
 pub(super) enum Event {
     SendPongFrame(Frame),
     SendCloseFrameAndShutdown(Frame),
+    /// Close the connection because the server violated a policy,
+    /// whatever the close policy is
+    CloseForPolicyViolation(Frame),
 }
 
 /// The read half of a WebSocket connection, generated from [`WebSocket::split()`].
@@ -21,8 +48,29 @@ pub(super) enum Event {
 #[derive(Debug)]
 pub struct WebSocketReadHalf {
     pub(super) stream: BufReader<ReadHalf<Stream>>,
+    /// Bytes of the next frame which have been read from the stream
+    pub(super) buffer: Vec<u8>,
+    /// Frames which were received and handled, but not yet returned
+    pub(super) pending_frames: VecDeque<Frame>,
     pub(super) last_frame_type: FrameType,
+    pub(super) in_progress_message: Option<InProgressMessage>,
+    pub(super) receive_progress: watch::Sender<Option<MessageProgress>>,
+    pub(super) activity: Arc<Activity>,
+    pub(super) frame_filter: OpcodeSet,
+    pub(super) duplicate_filter: Option<DuplicateFilter>,
+    pub(super) ping_limiter: PingLimiter,
+    pub(super) event_queue_policy: EventQueuePolicy,
+    pub(super) read_stall_timeout: Option<Duration>,
+    pub(super) strict_mode: bool,
+    pub(super) raw_close_reasons: bool,
+    pub(super) shutdown_token: Option<CancellationToken>,
+    pub(super) logger: Logger,
     pub(super) sender: Sender<Event>,
+    /// Flushes events after each frame is received, if set with
+    /// [`respond_control()`](WebSocketReadHalf::respond_control())
+    pub(super) event_pump: Option<EventPump>,
+    pub(super) pause_handle: PauseHandle,
+    pub(super) paused: watch::Receiver<bool>,
 }
 
 impl WebSocketReadHalf {
@@ -32,36 +80,147 @@ impl WebSocketReadHalf {
     /// If the received frame is a Close frame, an event to send a Close frame
     /// will be queued and the WebSocket will close. However, events are not
     /// acted upon unless flushed (see the documentation on the [`WebSocket`](WebSocket#splitting)
-    /// type for more details).
+    /// type for more details), which this method does itself if an [`EventPump`] was set
+    /// with [`respond_control()`](WebSocketReadHalf::respond_control()).
+    ///
+    /// Frames removed by the [frame filter](WebSocketReadHalf::set_frame_filter())
+    /// are handled as above, then skipped.
     pub async fn receive(&mut self) -> Result<Frame, WebSocketError> {
-        let frame = self.receive_without_handling().await?;
+        loop {
+            let result = self.receive_and_handle().await;
+            // respond right away (and close the connection right away after a ping flood)
+            if let (Some(event_pump), Ok(_) | Err(WebSocketError::PingFloodError)) =
+                (&self.event_pump, &result)
+            {
+                event_pump.flush().await?;
+            }
+            let frame = result?;
+            if self.passes_filter(&frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Receives a complete [`Message`] over the WebSocket connection, reassembling
+    /// fragmented messages. Ping and Pong frames are handled as by
+    /// [`receive()`](WebSocketReadHalf::receive()) and skipped. Returns `Ok(None)` once
+    /// a Close frame has been received (use [`close_listener()`](WebSocketReadHalf::close_listener())
+    /// to get its status code).
+    ///
+    /// The payload of a Text message is validated as UTF-8 once, in place, and is handed
+    /// out without being copied again.
+    pub async fn receive_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        let mut assembler = MessageAssembler::default();
+        loop {
+            let frame = self.receive().await?;
+            if frame.is_any_close() {
+                return Ok(None);
+            }
+            if let Some(message) = assembler.push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Receives a batch of messages sent in a single Binary frame, unpacking them with
+    /// [`decode_batch()`](batch::decode_batch()). Returns `Ok(None)` once a Close frame
+    /// has been received, like [`receive_message()`](WebSocketReadHalf::receive_message()).
+    pub async fn receive_batched(&mut self) -> Result<Option<Vec<Bytes>>, WebSocketError> {
+        match self.receive_message().await? {
+            Some(Message::Binary(payload)) => batch::decode_batch(payload).map(Some),
+            Some(Message::Text(_)) => Err(WebSocketError::InvalidBatchError),
+            None => Ok(None),
+        }
+    }
+
+    pub(super) async fn receive_and_handle(&mut self) -> Result<Frame, WebSocketError> {
+        if let Some(frame) = self.pending_frames.pop_front() {
+            return Ok(frame);
+        }
+        self.receive_new_and_handle().await
+    }
+
+    /// Receives a frame from the stream, skipping the pending frames, and handles it.
+    pub(super) async fn receive_new_and_handle(&mut self) -> Result<Frame, WebSocketError> {
+        let shutdown_token = self.shutdown_token.clone();
+        let frame = unless_cancelled(shutdown_token, self.receive_frame()).await?;
         // handle incoming frames
         match &frame {
             // echo ping frame (https://tools.ietf.org/html/rfc6455#section-5.5.2)
             Frame::Ping { payload } => {
-                let pong = Frame::Pong {
-                    payload: payload.clone(),
-                };
-                self.sender
-                    .send(Event::SendPongFrame(pong))
-                    .map_err(|_e| WebSocketError::ChannelError)?;
+                let payload_len = payload.as_ref().map_or(0, Vec::len);
+                if self.ping_limiter.allow(payload_len) {
+                    let pong = Frame::Pong {
+                        payload: payload.clone(),
+                    };
+                    self.queue_pong(pong)?;
+                } else if self.ping_limiter.policy == PingFloodPolicy::Close {
+                    self.logger.warn(format_args!(
+                        "closing websocket connection to {}: too many Ping frames",
+                        self.activity.host
+                    ));
+                    // https://tools.ietf.org/html/rfc6455#section-7.4.1
+                    let close = Frame::close(Some((1008, "too many pings".to_string())));
+                    self.queue_event(Event::CloseForPolicyViolation(close))?;
+                    return Err(WebSocketError::PingFloodError);
+                } else {
+                    self.activity.dropped(DropReason::PingLimit, &frame);
+                }
             }
             // echo close frame and shutdown (https://tools.ietf.org/html/rfc6455#section-1.4)
-            Frame::Close { payload } => {
-                let close = Frame::Close {
-                    payload: payload
-                        .as_ref()
-                        .map(|(status_code, _reason)| (status_code.clone(), String::new())),
+            Frame::Close { .. } | Frame::CloseRaw { .. } => {
+                let status_code = match &frame {
+                    Frame::CloseRaw {
+                        payload: (status_code, _reason),
+                    } => Some(*status_code),
+                    _ => frame.as_close().map(|(status_code, _reason)| *status_code),
                 };
-                self.sender
-                    .send(Event::SendCloseFrameAndShutdown(close))
-                    .map_err(|_e| WebSocketError::ChannelError)?;
+                let close =
+                    Frame::close(status_code.map(|status_code| (status_code, String::new())));
+                self.queue_event(Event::SendCloseFrameAndShutdown(close))?;
             }
+            // let the connection driver know the server is still alive
+            Frame::Pong { .. } => self.activity.received_pong(),
             _ => (),
         }
         Ok(frame)
     }
 
+    /// Queues a Pong frame to be sent. With [`EventQueuePolicy::Coalesce`], a Pong which
+    /// is already queued is replaced instead, since only the most recent Ping needs
+    /// to be responded to (https://tools.ietf.org/html/rfc6455#section-5.5.3).
+    fn queue_pong(&mut self, pong: Frame) -> Result<(), WebSocketError> {
+        if self.event_queue_policy == EventQueuePolicy::Coalesce {
+            let replaced = self
+                .activity
+                .queued_pong
+                .lock()
+                .unwrap()
+                .replace(pong.clone());
+            if let Some(replaced) = replaced {
+                self.activity.dropped(DropReason::CoalescedPong, &replaced);
+                return Ok(());
+            }
+        }
+        let result = self.queue_event(Event::SendPongFrame(pong));
+        if result.is_err() {
+            // nothing was queued to send the Pong, so later Pongs must not be coalesced into it
+            self.activity.queued_pong.lock().unwrap().take();
+        }
+        result
+    }
+
+    fn queue_event(&mut self, event: Event) -> Result<(), WebSocketError> {
+        match self.sender.try_send(event) {
+            Ok(()) => {
+                self.activity.queued_events(self.sender.len());
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => Err(WebSocketError::EventQueueFullError),
+            Err(TrySendError::Disconnected(_)) => Err(WebSocketError::ChannelError),
+        }
+    }
+
     /// Receives a [`Frame`] over the WebSocket connection **without handling incoming frames.**
     /// For example, receiving a Ping frame will not queue a Pong frame to be sent,
     /// and receiving a Close frame will not queue a Close frame to be sent nor close
@@ -69,27 +228,930 @@ impl WebSocketReadHalf {
     ///
     /// To automatically handle incoming frames, use the [`receive()`](WebSocketReadHalf::receive())
     /// method instead.
+    ///
+    /// Frames removed by the [frame filter](WebSocketReadHalf::set_frame_filter())
+    /// are skipped without being handled.
     pub async fn receive_without_handling(&mut self) -> Result<Frame, WebSocketError> {
-        let frame = Frame::read_from_websocket(self).await?;
+        loop {
+            let frame = self.receive_unfiltered().await?;
+            if self.passes_filter(&frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    async fn receive_unfiltered(&mut self) -> Result<Frame, WebSocketError> {
+        if let Some(frame) = self.pending_frames.pop_front() {
+            return Ok(frame);
+        }
+        let shutdown_token = self.shutdown_token.clone();
+        unless_cancelled(shutdown_token, self.receive_frame()).await
+    }
+
+    /// Receives a frame, ignoring the shutdown token.
+    pub(super) async fn receive_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let result = self.read_frame().await;
+        self.log_received(&result);
+        if let Err(WebSocketError::ReadError(_) | WebSocketError::PayloadReadError { .. }) = &result
+        {
+            self.activity
+                .closed(CloseCode::ABNORMAL_CLOSURE, String::new());
+        }
+        result
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let header = self.fill_frame().await?;
+        // take the frame out of the buffer, leaving any bytes of the frames after it
+        let rest = self.buffer.split_off(header.frame_len());
+        let mut payload = std::mem::replace(&mut self.buffer, rest);
+        payload.drain(..header.header_len);
+        self.activity
+            .record(FrameDirection::Inbound, header.fin, header.opcode, &payload);
+        let frame = Frame::decode(
+            &header,
+            payload,
+            &self.last_frame_type,
+            self.raw_close_reasons,
+        )?;
+        self.activity.received(header.payload_len);
         // remember last data frame type in case we get continuation frames (https://tools.ietf.org/html/rfc6455#section-5.2)
         match frame {
             Frame::Text { .. } => self.last_frame_type = FrameType::Text,
             Frame::Binary { .. } => self.last_frame_type = FrameType::Binary,
             _ => (),
         };
+        // keep track of fragmented messages (https://tools.ietf.org/html/rfc6455#section-5.4)
+        match &frame {
+            Frame::Text {
+                payload,
+                continuation,
+                fin,
+            } => self.track_message(MessageType::Text, payload.len(), *continuation, *fin),
+            Frame::Binary {
+                payload,
+                continuation,
+                fin,
+            } => self.track_message(MessageType::Binary, payload.len(), *continuation, *fin),
+            Frame::Close { payload } => self.activity.received_close(payload.as_ref()),
+            Frame::CloseRaw {
+                payload: (status_code, reason),
+            } => self.activity.received_close(Some(&(
+                *status_code,
+                String::from_utf8_lossy(reason).into_owned(),
+            ))),
+            _ => (),
+        };
         Ok(frame)
     }
+
+    fn log_received(&self, result: &Result<Frame, WebSocketError>) {
+        match result {
+            // status codes: https://tools.ietf.org/html/rfc6455#section-7.4.1
+            Ok(Frame::Close {
+                payload: Some((status_code, reason)),
+            }) if *status_code != 1000 && *status_code != 1001 => self.logger.warn(format_args!(
+                "websocket closed abnormally by server with status code {} (reason: {:?})",
+                status_code, reason
+            )),
+            Ok(Frame::CloseRaw {
+                payload: (status_code, reason),
+            }) if *status_code != 1000 && *status_code != 1001 => self.logger.warn(format_args!(
+                "websocket closed abnormally by server with status code {} (raw reason: {:?})",
+                status_code, reason
+            )),
+            Ok(frame) if frame.is_any_close() => {
+                self.logger.info(format_args!("websocket closed by server"))
+            }
+            Ok(_) => (),
+            Err(WebSocketError::ReadError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.logger.warn(format_args!(
+                    "websocket connection closed without a Close frame"
+                ))
+            }
+            Err(WebSocketError::PayloadReadError {
+                received, expected, ..
+            }) => self.logger.warn(format_args!(
+                "websocket connection lost after receiving {} of {} bytes of a frame's payload",
+                received, expected
+            )),
+            Err(
+                e @ WebSocketError::InvalidFrameError
+                | e @ WebSocketError::ReceivedMaskedFrameError
+                | e @ WebSocketError::NonMinimalLengthError
+                | e @ WebSocketError::PayloadTooLargeError
+                | e @ WebSocketError::ReadStallTimeoutError,
+            ) => self
+                .logger
+                .warn(format_args!("server violated websocket protocol: {}", e)),
+            Err(_) => (),
+        }
+    }
+
+    fn track_message(
+        &mut self,
+        message_type: MessageType,
+        payload_len: usize,
+        continuation: bool,
+        fin: bool,
+    ) {
+        if fin {
+            self.in_progress_message = None;
+        } else if !continuation {
+            self.in_progress_message = Some(InProgressMessage {
+                message_type,
+                frames_received: 1,
+                bytes_received: payload_len,
+            });
+        } else if let Some(message) = &mut self.in_progress_message {
+            message.frames_received += 1;
+            message.bytes_received += payload_len;
+        }
+        update_progress(
+            &self.receive_progress,
+            message_type,
+            payload_len,
+            continuation,
+            fin,
+        );
+    }
+
+    /// Sets which frame types are returned by [`receive()`](WebSocketReadHalf::receive())
+    /// and [`receive_without_handling()`](WebSocketReadHalf::receive_without_handling()).
+    /// Frames of other types are skipped, although `receive()` still handles them
+    /// (so Ping frames are still answered with Pong frames). Close frames are always
+    /// returned, so that the end of the connection is not missed.
+    ///
+    /// For example, to only receive data frames:
+    ///
+    /// ```
+    /// # use websockets::{OpcodeSet, WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+    /// let (mut read_half, _write_half) = ws.split();
+    /// read_half.set_frame_filter(OpcodeSet::DATA);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Defaults to [`OpcodeSet::ALL`].
+    pub fn set_frame_filter(&mut self, filter: OpcodeSet) {
+        self.frame_filter = filter;
+    }
+
+    /// Returns the current [frame filter](WebSocketReadHalf::set_frame_filter()).
+    pub fn frame_filter(&self) -> OpcodeSet {
+        self.frame_filter
+    }
+
+    pub(super) fn passes_filter(&self, frame: &Frame) -> bool {
+        if frame.is_any_close() {
+            return true;
+        }
+        let reason = if !self.frame_filter.matches(frame) {
+            DropReason::FrameFilter
+        } else if self
+            .duplicate_filter
+            .as_ref()
+            .is_some_and(|duplicate_filter| duplicate_filter.is_duplicate(frame))
+        {
+            DropReason::DuplicateFilter
+        } else {
+            return true;
+        };
+        self.activity.dropped(reason, frame);
+        false
+    }
+
+    /// Receives frames until the server's Close frame, discarding the frames before it.
+    pub(super) async fn receive_until_close(&mut self) -> Result<(), WebSocketError> {
+        loop {
+            let frame = self.receive_frame().await?;
+            if frame.is_any_close() {
+                return Ok(());
+            }
+            self.activity
+                .dropped(DropReason::ReceivedWhileClosing, &frame);
+        }
+    }
+
+    /// Returns a channel which reports the progress of fragmented messages as they are
+    /// received, updated each time a fragment is received. Messages made up
+    /// of a single frame are not reported.
+    ///
+    /// This can be used to show a progress bar for large messages.
+    pub fn receive_progress(&self) -> watch::Receiver<Option<MessageProgress>> {
+        self.receive_progress.subscribe()
+    }
+
+    /// Returns the progress of the fragmented message currently being received,
+    /// or None if the last received data frame was the final frame of its message.
+    ///
+    /// This can be used to detect a fragmented message that has stalled
+    /// (see [`Frame`](Frame#fragmentation) for more details about fragmentation).
+    pub fn in_progress_message(&self) -> Option<InProgressMessage> {
+        self.in_progress_message
+    }
+
+    /// Sets an [`EventPump`] (from [`WebSocketWriteHalf::event_pump()`]) which
+    /// [`receive()`](WebSocketReadHalf::receive()) (and the methods built on it) uses to
+    /// flush events after each frame, so that Pong frames are sent and Close frames are
+    /// echoed even if the write half is never used. A value of None leaves events
+    /// to be flushed by the write half.
+    ///
+    /// ```
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+    /// let (mut read_half, write_half) = ws.split();
+    /// read_half.respond_control(Some(write_half.event_pump()));
+    /// // the write half can be parked in another task, and Pings are still answered
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn respond_control(&mut self, event_pump: Option<EventPump>) {
+        self.event_pump = event_pump;
+    }
+
+    /// Returns a [`MessageReader`], which reads the next incoming message as a stream of bytes
+    /// (receiving its frames one at a time) instead of as [`Frame`]s.
+    pub fn message_reader(&mut self) -> MessageReader<'_> {
+        MessageReader::new(self)
+    }
+
+    /// Returns the number of bytes which have been read from the connection
+    /// but not yet returned as frames. This includes data buffered for the next frame
+    /// (or frames), but not frames which have already been parsed.
+    ///
+    /// This can be used to check whether data is still waiting to be received
+    /// before shutting down, or to monitor whether a consumer is falling behind.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffer.len() + self.stream.buffer().len()
+    }
+
+    /// Returns when the last frame was received, or when the connection was established
+    /// if no frames have been received. This is shared with the write half.
+    pub fn last_received_at(&self) -> Instant {
+        self.activity.last_received_at()
+    }
+
+    /// Returns when the last frame was sent, or when the connection was established
+    /// if no frames have been sent. This is shared with the write half.
+    pub fn last_sent_at(&self) -> Instant {
+        self.activity.last_sent_at()
+    }
+
+    /// Returns statistics about the frames sent and received over the connection.
+    /// These are shared with the write half.
+    pub fn stats(&self) -> Stats {
+        self.activity.stats(self.sender.len())
+    }
+
+    /// Returns a [`CloseListener`] for waiting until the connection terminates.
+    pub fn close_listener(&self) -> CloseListener {
+        self.activity.close_listener()
+    }
+
+    /// Returns a receiver of the [`ConnectionState`] of the connection.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.activity.connection_state()
+    }
+
+    /// Waits until an entire frame has been received, without consuming it.
+    /// Once this method returns, the next call to [`receive()`](WebSocketReadHalf::receive())
+    /// will return the frame without waiting for more data.
+    ///
+    /// This method is cancel safe, as are [`receive()`](WebSocketReadHalf::receive())
+    /// and [`receive_without_handling()`](WebSocketReadHalf::receive_without_handling()):
+    /// if the future is dropped before it completes (for example, in a `tokio::select!`),
+    /// any data that was already read is kept for the next call.
+    pub async fn ready(&mut self) -> Result<(), WebSocketError> {
+        if !self.pending_frames.is_empty() {
+            return Ok(());
+        }
+        let shutdown_token = self.shutdown_token.clone();
+        unless_cancelled(shutdown_token, self.fill_frame()).await?;
+        Ok(())
+    }
+
+    /// Reads from the stream until the buffer contains an entire frame,
+    /// returning the frame's header.
+    async fn fill_frame(&mut self) -> Result<FrameHeader, WebSocketError> {
+        // only stop between frames so a partially read frame is never left behind
+        while *self.paused.borrow() && self.buffer.is_empty() {
+            self.paused
+                .changed()
+                .await
+                .map_err(|_e| WebSocketError::ChannelError)?;
+        }
+        loop {
+            let header = FrameHeader::parse(&self.buffer, false, self.strict_mode)?;
+            if let Some(header) = header {
+                if self.buffer.len() >= header.frame_len() {
+                    return Ok(header);
+                }
+                self.buffer.reserve(header.frame_len() - self.buffer.len());
+            }
+            // only a partially received frame can stall
+            let stall_timeout = self.read_stall_timeout.filter(|_| !self.buffer.is_empty());
+            // reading into the buffer is cancel safe, since the buffer is kept between calls
+            let read = self.stream.read_buf(&mut self.buffer);
+            let result = match stall_timeout {
+                Some(read_stall_timeout) => time::timeout(read_stall_timeout, read)
+                    .await
+                    .map_err(|_e| WebSocketError::ReadStallTimeoutError)?,
+                None => read.await,
+            };
+            let result = match result {
+                Ok(0) => Err(IoError::from(ErrorKind::UnexpectedEof)),
+                result => result,
+            };
+            if let Err(source) = result {
+                // errors in the middle of a payload say how much of it was received
+                return Err(match header {
+                    Some(header) => WebSocketError::PayloadReadError {
+                        source,
+                        received: self.buffer.len() - header.header_len,
+                        expected: header.payload_len,
+                    },
+                    None => WebSocketError::ReadError(source),
+                });
+            }
+        }
+    }
+
+    /// Pauses reading from the WebSocket connection. Subsequent calls to
+    /// [`receive()`](WebSocketReadHalf::receive()) will wait, without reading from the
+    /// underlying socket, until reading is [resumed](WebSocketReadHalf::resume()).
+    /// This lets TCP flow control apply backpressure to the server instead
+    /// of incoming data being buffered in memory.
+    ///
+    /// A frame which is already being received when reading is paused will still be received.
+    /// To resume reading from another task, use a [`PauseHandle`].
+    pub fn pause(&self) {
+        self.pause_handle.pause();
+    }
+
+    /// Resumes reading from the WebSocket connection after it was [paused](WebSocketReadHalf::pause()).
+    pub fn resume(&self) {
+        self.pause_handle.resume();
+    }
+
+    /// Returns whether reading from the WebSocket connection is paused.
+    pub fn is_paused(&self) -> bool {
+        self.pause_handle.is_paused()
+    }
+
+    /// Returns a [`PauseHandle`], which can be used to pause and resume reading
+    /// while the read half is in use (for example, while it is waiting in
+    /// [`receive()`](WebSocketReadHalf::receive())).
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause_handle.clone()
+    }
 }
 
-/// The write half of a WebSocket connection, generated from [`WebSocket::split()`].
-/// This half can only send frames.
+/// Waits for the future to complete, unless the shutdown token is cancelled first,
+/// in which case [`CancelledError`](WebSocketError::CancelledError) is returned.
+async fn unless_cancelled<T>(
+    shutdown_token: Option<CancellationToken>,
+    future: impl Future<Output = Result<T, WebSocketError>>,
+) -> Result<T, WebSocketError> {
+    match shutdown_token {
+        Some(shutdown_token) => tokio::select! {
+            biased;
+            _ = shutdown_token.cancelled() => Err(WebSocketError::CancelledError),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
+/// Progress of a fragmented message that is being received,
+/// generated from [`WebSocketReadHalf::in_progress_message()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InProgressMessage {
+    /// The type of the message
+    pub message_type: MessageType,
+    /// The number of frames of the message received so far
+    pub frames_received: usize,
+    /// The number of payload bytes of the message received so far
+    pub bytes_received: usize,
+}
+
+/// Progress of a fragmented message that is being sent or received, reported through
+/// [`WebSocketWriteHalf::send_progress()`] and [`WebSocketReadHalf::receive_progress()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageProgress {
+    /// The type of the message
+    pub message_type: MessageType,
+    /// The number of frames of the message transferred so far
+    pub frames: usize,
+    /// The number of payload bytes of the message transferred so far
+    pub bytes: usize,
+    /// Whether the final frame of the message has been transferred
+    pub finished: bool,
+}
+
+/// A handle for waiting until a WebSocket connection terminates, generated from
+/// [`WebSocketReadHalf::close_listener()`] or [`WebSocketWriteHalf::close_listener()`].
+/// It can be cloned cheaply and does not keep the connection open, so supervisory tasks
+/// can observe termination without owning either half.
+#[derive(Debug, Clone)]
+pub struct CloseListener(watch::Receiver<Option<(CloseCode, String)>>);
+
+impl CloseListener {
+    /// Returns a future which resolves with the status code and reason of the Close frame
+    /// received from the server, once it has been received. If the connection terminates
+    /// without a Close frame (the stream ends or fails while reading, or both halves are
+    /// dropped), the future resolves with 1006 (Abnormal Closure). Close frames without
+    /// a payload resolve with 1005 (No Status Received).
+    pub fn closed(&self) -> impl Future<Output = (CloseCode, String)> + Send + 'static {
+        let mut receiver = self.0.clone();
+        async move {
+            match receiver.wait_for(Option::is_some).await {
+                Ok(closed) => closed.clone().unwrap(),
+                Err(_) => (CloseCode::ABNORMAL_CLOSURE, String::new()),
+            }
+        }
+    }
+
+    /// Returns the status code and reason the connection terminated with,
+    /// or `None` if it has not terminated yet.
+    pub fn close_status(&self) -> Option<(CloseCode, String)> {
+        self.0.borrow().clone()
+    }
+}
+
+/// The number of bytes written to the connection for a sent frame, returned by
+/// [`WebSocketWriteHalf::send()`] and the other methods that send a single frame,
+/// for metering bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendReceipt {
+    /// The length of the entire frame (header and payload), in bytes
+    pub frame_bytes: usize,
+    /// The length of the frame's payload, in bytes
+    pub payload_bytes: usize,
+}
+
+/// Updates the progress of the current fragmented message after a data frame
+/// has been transferred.
+fn update_progress(
+    progress: &watch::Sender<Option<MessageProgress>>,
+    message_type: MessageType,
+    payload_len: usize,
+    continuation: bool,
+    fin: bool,
+) {
+    if !continuation && fin {
+        // not fragmented
+        return;
+    }
+    progress.send_modify(|progress| match progress {
+        Some(progress) if continuation && !progress.finished => {
+            progress.frames += 1;
+            progress.bytes += payload_len;
+            progress.finished = fin;
+        }
+        _ => {
+            *progress = Some(MessageProgress {
+                message_type,
+                frames: 1,
+                bytes: payload_len,
+                finished: fin,
+            })
+        }
+    });
+}
+
+/// A handle used to pause and resume reading from a [`WebSocketReadHalf`],
+/// generated from [`WebSocketReadHalf::pause_handle()`].
+/// See [`WebSocketReadHalf::pause()`] for more details.
+#[derive(Debug, Clone)]
+pub struct PauseHandle(pub(super) Arc<watch::Sender<bool>>);
+
+impl PauseHandle {
+    pub(super) fn new() -> (Self, watch::Receiver<bool>) {
+        let (sender, receiver) = watch::channel(false);
+        (Self(Arc::new(sender)), receiver)
+    }
+
+    /// Pauses reading from the WebSocket connection.
+    /// See [`WebSocketReadHalf::pause()`] for more details.
+    pub fn pause(&self) {
+        // only fails if the read half has been dropped, in which case there is nothing to pause
+        let _ = self.0.send(true);
+    }
+
+    /// Resumes reading from the WebSocket connection.
+    pub fn resume(&self) {
+        let _ = self.0.send(false);
+    }
+
+    /// Returns whether reading from the WebSocket connection is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// When frames were last sent and received, and statistics about them,
+/// shared between the halves
 #[derive(Debug)]
-pub struct WebSocketWriteHalf {
+pub(super) struct Activity {
+    last_received_at: StdMutex<Instant>,
+    last_sent_at: StdMutex<Instant>,
+    stats: StdMutex<Stats>,
+    /// The host connected to, which the process-wide close code statistics are kept by
+    host: String,
+    recorder: Option<SessionRecorder>,
+    /// The status code and reason the connection terminated with, once it has
+    closed: watch::Sender<Option<(CloseCode, String)>>,
+    state: StateTracker,
+    /// Whether a frame has been written without its flush completing
+    write_blocked: AtomicBool,
+    /// The Pong frame to send for the queued [`Event::SendPongFrame`], replaced
+    /// by the read half if more Pings are received before it is sent
+    /// (with [`EventQueuePolicy::Coalesce`])
+    pub(super) queued_pong: StdMutex<Option<Frame>>,
+    /// The number of Pong frames received, which the connection driver watches
+    /// for responses to its keepalive Pings
+    pongs: watch::Sender<u64>,
+    on_dropped: Option<DropListener>,
+}
+
+impl Activity {
+    pub(super) fn new(
+        host: String,
+        recorder: Option<SessionRecorder>,
+        state: StateTracker,
+        on_dropped: Option<DropListener>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            last_received_at: StdMutex::new(now),
+            last_sent_at: StdMutex::new(now),
+            stats: StdMutex::new(Stats::default()),
+            host,
+            recorder,
+            closed: watch::channel(None).0,
+            state,
+            write_blocked: AtomicBool::new(false),
+            queued_pong: StdMutex::new(None),
+            pongs: watch::channel(0).0,
+            on_dropped,
+        }
+    }
+
+    /// Records a frame with the [session recorder](crate::WebSocketBuilder::record_session()), if any
+    pub(super) fn record(&self, direction: FrameDirection, fin: bool, opcode: u8, payload: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(direction, fin, opcode, payload);
+        }
+    }
+
+    pub(super) fn received(&self, payload_len: usize) {
+        *self.last_received_at.lock().unwrap() = Instant::now();
+        self.stats.lock().unwrap().record_received(payload_len);
+    }
+
+    pub(super) fn received_close(&self, payload: Option<&(u16, String)>) {
+        stats::record_close_code(
+            &self.host,
+            payload.map(|(status_code, _reason)| *status_code),
+        );
+        // https://tools.ietf.org/html/rfc6455#section-7.1.5
+        match payload {
+            Some((status_code, reason)) => self.closed(CloseCode(*status_code), reason.clone()),
+            None => self.closed(CloseCode::NO_STATUS_RECEIVED, String::new()),
+        }
+    }
+
+    /// Reports that the connection has terminated, unless it already has been
+    pub(super) fn closed(&self, close_code: CloseCode, reason: String) {
+        self.state.set(ConnectionState::Closed);
+        self.closed.send_if_modified(|closed| {
+            if closed.is_some() {
+                return false;
+            }
+            *closed = Some((close_code, reason));
+            true
+        });
+    }
+
+    fn close_listener(&self) -> CloseListener {
+        CloseListener(self.closed.subscribe())
+    }
+
+    pub(super) fn set_write_blocked(&self, write_blocked: bool) {
+        self.write_blocked.store(write_blocked, Ordering::Relaxed);
+    }
+
+    fn is_write_blocked(&self) -> bool {
+        self.write_blocked.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_state(&self, state: ConnectionState) {
+        self.state.set(state);
+    }
+
+    fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub(super) fn sent(&self, payload_len: usize) {
+        *self.last_sent_at.lock().unwrap() = Instant::now();
+        self.stats.lock().unwrap().record_sent(payload_len);
+    }
+
+    /// Counts a dropped frame, and reports it to the
+    /// [drop callback](crate::WebSocketBuilder::on_dropped()), if any
+    pub(super) fn dropped(&self, reason: DropReason, frame: &Frame) {
+        self.stats.lock().unwrap().record_dropped(reason);
+        if let Some(on_dropped) = &self.on_dropped {
+            (on_dropped.0)(reason, frame);
+        }
+    }
+
+    fn received_pong(&self) {
+        self.pongs.send_modify(|pongs| *pongs += 1);
+    }
+
+    /// Returns a receiver which is notified whenever a Pong frame is received.
+    pub(super) fn pong_listener(&self) -> watch::Receiver<u64> {
+        self.pongs.subscribe()
+    }
+
+    fn last_received_at(&self) -> Instant {
+        *self.last_received_at.lock().unwrap()
+    }
+
+    fn last_sent_at(&self) -> Instant {
+        *self.last_sent_at.lock().unwrap()
+    }
+
+    /// Records the number of events queued for the write half, after one was queued
+    fn queued_events(&self, queued_events: usize) {
+        self.stats
+            .lock()
+            .unwrap()
+            .record_queued_events(queued_events);
+    }
+
+    fn stats(&self, queued_events: usize) -> Stats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.set_queued_events(queued_events);
+        stats
+    }
+}
+
+/// What the WebSocket does when it receives a Close frame from the server.
+/// Set with [`WebSocketBuilder::close_policy()`](crate::WebSocketBuilder::close_policy()).
+///
+/// The policy takes effect when incoming events are flushed (see the documentation on the
+/// [`WebSocket`](WebSocket#splitting) type for more details about events).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClosePolicy {
+    /// Echo the server's Close frame (unless a Close frame has already been sent)
+    /// and shut down the WebSocket immediately
+    #[default]
+    ImmediateShutdown,
+    /// Like `ImmediateShutdown`, but if a fragmented message is being sent, wait until its
+    /// final frame has been sent before echoing the Close frame and shutting down
+    /// (see [https://tools.ietf.org/html/rfc6455#section-5.5.1](https://tools.ietf.org/html/rfc6455#section-5.5.1))
+    DrainThenShutdown,
+    /// Do nothing, leaving it to the application to call
+    /// [`close()`](WebSocketWriteHalf::close()) and [`shutdown()`](WebSocketWriteHalf::shutdown())
+    Manual,
+}
+
+/// What the WebSocket does when the server sends Ping frames faster than allowed by
+/// [`WebSocketBuilder::max_pongs_per_second()`](crate::WebSocketBuilder::max_pongs_per_second())
+/// or larger than allowed by
+/// [`WebSocketBuilder::max_ping_payload()`](crate::WebSocketBuilder::max_ping_payload()).
+/// Set with [`WebSocketBuilder::ping_flood_policy()`](crate::WebSocketBuilder::ping_flood_policy()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PingFloodPolicy {
+    /// Return the Ping frames as usual, but do not respond to them with Pong frames
+    #[default]
+    DropExcess,
+    /// Close the connection with status code 1008 (Policy Violation), and fail receiving
+    /// with [`PingFloodError`](WebSocketError::PingFloodError)
+    Close,
+}
+
+/// How the read half queues events (such as Pong frames to send in response to Pings)
+/// for the write half, and what happens once
+/// [`WebSocketBuilder::max_queued_events()`](crate::WebSocketBuilder::max_queued_events())
+/// events are queued.
+/// Set with [`WebSocketBuilder::event_queue_policy()`](crate::WebSocketBuilder::event_queue_policy()).
+///
+/// The number of events queued is reported by [`Stats::queued_events()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventQueuePolicy {
+    /// Queue at most one Pong frame, replacing its payload with that of the most recent
+    /// Ping (see [https://tools.ietf.org/html/rfc6455#section-5.5.3](https://tools.ietf.org/html/rfc6455#section-5.5.3)).
+    /// The queue then only fills up if the server keeps sending
+    /// Close frames, or if it has room for fewer than 2 events, in which case receiving
+    /// fails with [`EventQueueFullError`](WebSocketError::EventQueueFullError)
+    #[default]
+    Coalesce,
+    /// Queue an event for every frame which needs one, and fail receiving with
+    /// [`EventQueueFullError`](WebSocketError::EventQueueFullError) once the queue is full
+    Error,
+}
+
+/// Decides which Ping frames are responded to, so that a server flooding Pings cannot
+/// make the client spend its CPU time and bandwidth on Pongs
+#[derive(Debug, Clone, Default)]
+pub(super) struct PingLimiter {
+    pub(super) max_per_second: Option<u32>,
+    pub(super) max_payload: Option<usize>,
+    pub(super) policy: PingFloodPolicy,
+    /// When the current one second window started, and how many Pings have been
+    /// responded to in it
+    window: Option<(Instant, u32)>,
+}
+
+impl PingLimiter {
+    /// Returns whether a Ping with the payload length should be responded to,
+    /// counting it if so.
+    fn allow(&mut self, payload_len: usize) -> bool {
+        if self.max_payload.is_some_and(|max| payload_len > max) {
+            return false;
+        }
+        let max_per_second = match self.max_per_second {
+            Some(max_per_second) => max_per_second,
+            None => return true,
+        };
+        let now = Instant::now();
+        let answered = match &mut self.window {
+            Some((started, answered)) if now - *started < Duration::from_secs(1) => answered,
+            window => &mut window.insert((now, 0)).1,
+        };
+        if *answered >= max_per_second {
+            return false;
+        }
+        *answered += 1;
+        true
+    }
+}
+
+/// A handle which flushes events from the read half (sending Pong frames and echoing
+/// Close frames) without the write half, generated from
+/// [`WebSocketWriteHalf::event_pump()`].
+///
+/// This is for when only the read half is used, such as when the write half is parked
+/// in another task which rarely sends. Pass it to
+/// [`WebSocketReadHalf::respond_control()`] to have the read half flush events itself.
+#[derive(Debug, Clone)]
+pub struct EventPump {
+    writer: Arc<Mutex<Writer>>,
+    receiver: Receiver<Event>,
+}
+
+impl EventPump {
+    /// Flushes events from the read half (see [`WebSocketWriteHalf::flush()`]).
+    /// Waits for any frame being sent by the write half to finish first.
+    pub async fn flush(&self) -> Result<(), WebSocketError> {
+        flush_events(&self.writer, &self.receiver).await
+    }
+}
+
+/// Acts on the events queued by the read half.
+async fn flush_events(
+    writer: &Mutex<Writer>,
+    receiver: &Receiver<Event>,
+) -> Result<(), WebSocketError> {
+    let mut writer = writer.lock().await;
+    while let Ok(event) = receiver.try_recv() {
+        writer.handle_event(event).await?;
+    }
+    Ok(())
+}
+
+/// The write side of a WebSocket connection, shared between the
+/// [`WebSocketWriteHalf`] and the [`ConnectionDriver`], if there is one.
+#[derive(Debug)]
+pub(super) struct Writer {
     pub(super) shutdown: bool,
     pub(super) sent_closed: bool,
     pub(super) stream: BufWriter<WriteHalf<Stream>>,
-    pub(super) rng: ChaCha20Rng,
+    pub(super) rng: EntropySource,
+    /// Whether sent frames are masked, which is only disabled by
+    /// [`WebSocketBuilder::danger_disable_masking()`](super::builder::WebSocketBuilder::danger_disable_masking())
+    pub(super) mask_frames: bool,
+    pub(super) activity: Arc<Activity>,
+    pub(super) close_policy: ClosePolicy,
+    /// Whether a Close frame has been sent
+    pub(super) sent_close_frame: bool,
+    /// Whether a fragmented message has been started but its final frame not yet sent
+    pub(super) sending_fragmented_message: bool,
+    /// Echoed Close frame waiting for a fragmented message to be finished
+    pub(super) deferred_close: Option<Frame>,
+}
+
+impl Writer {
+    /// Acts on an event from the read half.
+    pub(super) async fn handle_event(&mut self, event: Event) -> Result<(), WebSocketError> {
+        match event {
+            Event::SendPongFrame(frame) => {
+                // send the most recent Pong, if later ones were coalesced into this event
+                let queued_pong = self.activity.queued_pong.lock().unwrap().take();
+                let frame = queued_pong.unwrap_or(frame);
+                if self.is_closed() {
+                    self.activity.dropped(DropReason::Closed, &frame);
+                    return Ok(());
+                }
+                frame.send(self).await?;
+            }
+            // read half will always send this event if it has received a close frame
+            Event::SendCloseFrameAndShutdown(frame) => {
+                if self.is_closed() {
+                    self.activity.dropped(DropReason::Closed, &frame);
+                    return Ok(());
+                }
+                match self.close_policy {
+                    ClosePolicy::Manual => (),
+                    // we have sent and received a close frame, so we will shutdown
+                    _ if self.sent_close_frame => self.shutdown().await?,
+                    ClosePolicy::DrainThenShutdown if self.sending_fragmented_message => {
+                        self.deferred_close = Some(frame)
+                    }
+                    _ => {
+                        frame.send(self).await?;
+                        self.shutdown().await?;
+                    }
+                }
+            }
+            Event::CloseForPolicyViolation(frame) => {
+                if self.is_closed() {
+                    self.activity.dropped(DropReason::Closed, &frame);
+                    return Ok(());
+                }
+                if !self.sent_close_frame {
+                    frame.send(self).await?;
+                }
+                self.shutdown().await?;
+            }
+        };
+        Ok(())
+    }
+
+    /// Returns whether frames can no longer be sent.
+    fn is_closed(&self) -> bool {
+        self.shutdown || self.sent_closed
+    }
+
+    /// Sends the deferred Close frame and shuts down, once the fragmented message
+    /// that it was waiting for has been finished.
+    pub(super) async fn send_deferred_close(&mut self) -> Result<(), WebSocketError> {
+        if self.sending_fragmented_message {
+            return Ok(());
+        }
+        if let Some(frame) = self.deferred_close.take() {
+            frame.send(self).await?;
+            self.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn shutdown(&mut self) -> Result<(), WebSocketError> {
+        self.stream
+            .shutdown()
+            .await
+            .map_err(|e| WebSocketError::ShutdownError(e))?;
+        // indicates that a closed frame has been sent, so no more frames should be sent,
+        // but the underlying stream is not technically closed (closing the stream
+        // would prevent a Close frame from being received by the read half)
+        self.sent_closed = true;
+        self.activity.set_state(ConnectionState::Closed);
+        Ok(())
+    }
+
+    /// Flushes the bytes buffered for the stream, such as those of a frame whose send
+    /// was cancelled before it was flushed.
+    pub(super) async fn flush_stream(&mut self) -> Result<(), WebSocketError> {
+        if self.shutdown {
+            return Err(WebSocketError::WebSocketClosedError);
+        }
+        self.stream
+            .flush()
+            .await
+            .map_err(WebSocketError::WriteError)?;
+        self.activity.set_write_blocked(false);
+        Ok(())
+    }
+}
+
+/// The write half of a WebSocket connection, generated from [`WebSocket::split()`].
+/// This half can only send frames.
+#[derive(Debug)]
+pub struct WebSocketWriteHalf {
+    pub(super) writer: Arc<Mutex<Writer>>,
     pub(super) receiver: Receiver<Event>,
+    pub(super) send_progress: watch::Sender<Option<MessageProgress>>,
+    pub(super) activity: Arc<Activity>,
 }
 
 impl WebSocketWriteHalf {
@@ -99,24 +1161,16 @@ impl WebSocketWriteHalf {
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
     pub async fn flush(&mut self) -> Result<(), WebSocketError> {
-        while let Ok(event) = self.receiver.try_recv() {
-            if self.shutdown {
-                break;
-            }
-            match event {
-                Event::SendPongFrame(frame) => self.send_without_events_check(frame).await?,
-                Event::SendCloseFrameAndShutdown(frame) => {
-                    // read half will always send this event if it has received a close frame,
-                    // but if we have sent one already, then we have sent and received a close
-                    // frame, so we will shutdown
-                    if self.sent_closed {
-                        self.send_without_events_check(frame).await?;
-                        self.shutdown().await?;
-                    }
-                }
-            };
+        flush_events(&self.writer, &self.receiver).await
+    }
+
+    /// Returns an [`EventPump`], which flushes events like
+    /// [`flush()`](WebSocketWriteHalf::flush()) without borrowing the write half.
+    pub fn event_pump(&self) -> EventPump {
+        EventPump {
+            writer: self.writer.clone(),
+            receiver: self.receiver.clone(),
         }
-        Ok(())
     }
 
     /// Sends an already constructed [`Frame`] over the WebSocket connection.
@@ -124,21 +1178,119 @@ impl WebSocketWriteHalf {
     /// This method will flush incoming events.
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
-    pub async fn send(&mut self, frame: Frame) -> Result<(), WebSocketError> {
+    pub async fn send(&mut self, frame: Frame) -> Result<SendReceipt, WebSocketError> {
         self.flush().await?;
-        if self.shutdown || self.sent_closed {
+        let mut writer = self.writer.lock().await;
+        if writer.shutdown || writer.sent_closed {
             return Err(WebSocketError::WebSocketClosedError);
         }
-        self.send_without_events_check(frame).await
+        let data_frame_info = frame.data_frame_info();
+        let receipt = frame.send(&mut writer).await?;
+        writer.send_deferred_close().await?;
+        if let Some((message_type, payload_len, continuation, fin)) = data_frame_info {
+            update_progress(
+                &self.send_progress,
+                message_type,
+                payload_len,
+                continuation,
+                fin,
+            );
+        }
+        Ok(receipt)
     }
 
-    /// Sends an already constructed [`Frame`] over the WebSocket connection
-    /// without flushing incoming events from the read half.
-    /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
-    /// about events.
-    async fn send_without_events_check(&mut self, frame: Frame) -> Result<(), WebSocketError> {
-        frame.send(self).await?;
-        Ok(())
+    /// Returns a future which resolves once every frame queued before the barrier
+    /// (including Pong and Close frames sent by the [`ConnectionDriver`], if there is one)
+    /// has been written and flushed to the OS.
+    ///
+    /// The future does not borrow the write half, so it can be held while more frames
+    /// are sent. Frames are written in the order they are queued, so the barrier
+    /// resolves before any frame sent after it was first polled.
+    pub fn barrier(&self) -> impl Future<Output = Result<(), WebSocketError>> + Send + 'static {
+        let writer = self.writer.clone();
+        async move { writer.lock().await.flush_stream().await }
+    }
+
+    /// Returns whether the last frame written has not been flushed to the connection yet,
+    /// which means that the connection is congested (for example, the TCP window is full
+    /// because the server is reading slowly), or that sending it was cancelled.
+    /// Senders can check this to shed load instead of queueing more frames behind it.
+    /// This is shared with the [`ConnectionDriver`] and the read half's automatic responses.
+    pub fn is_write_blocked(&self) -> bool {
+        self.activity.is_write_blocked()
+    }
+
+    /// Waits until the connection is writable again: if a frame is
+    /// [blocked](WebSocketWriteHalf::is_write_blocked()), this waits for the send in
+    /// progress to finish, or flushes the frame if its send was cancelled.
+    /// Resolves immediately otherwise.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use tokio::time;
+    /// # use websockets::{WebSocket, WebSocketError};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), WebSocketError> {
+    /// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+    /// let (_read_half, mut write_half) = ws.split();
+    /// for tick in 0.. {
+    ///     let update = format!("tick {}", tick);
+    ///     // give up on updates that cannot be sent within 100 ms
+    ///     if time::timeout(Duration::from_millis(100), write_half.send_text(update))
+    ///         .await
+    ///         .is_err()
+    ///     {
+    ///         // drop updates until the connection catches up
+    ///         write_half.writable().await?;
+    ///     }
+    /// #   break;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn writable(&self) -> Result<(), WebSocketError> {
+        if !self.is_write_blocked() {
+            return Ok(());
+        }
+        self.writer.lock().await.flush_stream().await
+    }
+
+    /// Returns a channel which reports the progress of fragmented messages as they are
+    /// sent (such as by [`send_reader()`](WebSocketWriteHalf::send_reader())), updated
+    /// each time a fragment is sent. Messages made up of a single frame are not reported.
+    ///
+    /// This can be used to show a progress bar for large messages.
+    pub fn send_progress(&self) -> watch::Receiver<Option<MessageProgress>> {
+        self.send_progress.subscribe()
+    }
+
+    /// Returns when the last frame was received, or when the connection was established
+    /// if no frames have been received. This is shared with the read half.
+    pub fn last_received_at(&self) -> Instant {
+        self.activity.last_received_at()
+    }
+
+    /// Returns when the last frame was sent (including frames sent by the
+    /// [`ConnectionDriver`]), or when the connection was established if no frames have
+    /// been sent. This is shared with the read half.
+    pub fn last_sent_at(&self) -> Instant {
+        self.activity.last_sent_at()
+    }
+
+    /// Returns statistics about the frames sent and received over the connection.
+    /// These are shared with the read half.
+    pub fn stats(&self) -> Stats {
+        self.activity.stats(self.receiver.len())
+    }
+
+    /// Returns a [`CloseListener`] for waiting until the connection terminates.
+    pub fn close_listener(&self) -> CloseListener {
+        self.activity.close_listener()
+    }
+
+    /// Returns a receiver of the [`ConnectionState`] of the connection.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.activity.connection_state()
     }
 
     /// Sends a Text frame over the WebSocket connection, constructed
@@ -149,7 +1301,7 @@ impl WebSocketWriteHalf {
     /// This method will flush incoming events.
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
-    pub async fn send_text(&mut self, payload: String) -> Result<(), WebSocketError> {
+    pub async fn send_text(&mut self, payload: String) -> Result<SendReceipt, WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-5.6
         self.send(Frame::text(payload)).await
     }
@@ -162,23 +1314,103 @@ impl WebSocketWriteHalf {
     /// This method will flush incoming events.
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
-    pub async fn send_binary(&mut self, payload: Vec<u8>) -> Result<(), WebSocketError> {
+    pub async fn send_binary(&mut self, payload: Vec<u8>) -> Result<SendReceipt, WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-5.6
         self.send(Frame::binary(payload)).await
     }
 
+    /// Sends several small messages in a single Binary frame, packed with
+    /// [`encode_batch()`](batch::encode_batch()), to reduce the overhead of sending them
+    /// one frame at a time. The server must expect batches.
+    ///
+    /// This method will flush incoming events.
+    /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
+    /// about events.
+    pub async fn send_batched<I>(&mut self, messages: I) -> Result<SendReceipt, WebSocketError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.send_binary(batch::encode_batch(messages)?).await
+    }
+
+    /// Sends everything read from `reader` as a fragmented Binary message, in frames of
+    /// at most `chunk_size` bytes, and returns the number of bytes sent.
+    /// At most one chunk is held in memory at a time, so this can send messages
+    /// that are too large to fit in memory.
+    ///
+    /// If reading fails, [`ReadSourceError`](WebSocketError::ReadSourceError) is returned
+    /// and the message is left unfinished, so the connection should be closed.
+    ///
+    /// This method will flush incoming events.
+    /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
+    /// about events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub async fn send_reader<R>(
+        &mut self,
+        mut reader: R,
+        chunk_size: usize,
+    ) -> Result<u64, WebSocketError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        assert!(chunk_size > 0, "chunk size must be greater than 0");
+        let mut bytes_sent = 0;
+        let mut continuation = false;
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            while chunk.len() < chunk_size {
+                let bytes_read = (&mut reader)
+                    .take((chunk_size - chunk.len()) as u64)
+                    .read_to_end(&mut chunk)
+                    .await
+                    .map_err(WebSocketError::ReadSourceError)?;
+                if bytes_read == 0 {
+                    break;
+                }
+            }
+            // a chunk that is not full means the reader is done, but a full chunk
+            // may be followed by an empty final frame
+            let fin = chunk.len() < chunk_size;
+            bytes_sent += chunk.len() as u64;
+            // https://tools.ietf.org/html/rfc6455#section-5.4
+            self.send(Frame::Binary {
+                payload: chunk,
+                continuation,
+                fin,
+            })
+            .await?;
+            if fin {
+                return Ok(bytes_sent);
+            }
+            continuation = true;
+        }
+    }
+
+    /// Sends the contents of a file as a fragmented Binary message, in frames of
+    /// at most `chunk_size` bytes, and returns the number of bytes sent
+    /// (see [`send_reader()`](WebSocketWriteHalf::send_reader())).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub async fn send_file<P>(&mut self, path: P, chunk_size: usize) -> Result<u64, WebSocketError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(path)
+            .await
+            .map_err(WebSocketError::ReadSourceError)?;
+        self.send_reader(file, chunk_size).await
+    }
+
     /// Shuts down the WebSocket connection **without sending a Close frame**.
     /// It is recommended to use the [`close()`](WebSocketWriteHalf::close()) method instead.
     pub async fn shutdown(&mut self) -> Result<(), WebSocketError> {
-        self.stream
-            .shutdown()
-            .await
-            .map_err(|e| WebSocketError::ShutdownError(e))?;
-        // indicates that a closed frame has been sent, so no more frames should be sent,
-        // but the underlying stream is not technically closed (closing the stream
-        // would prevent a Close frame from being received by the read half)
-        self.sent_closed = true;
-        Ok(())
+        self.writer.lock().await.shutdown().await
     }
 
     /// Sends a Close frame over the WebSocket connection, constructed
@@ -193,7 +1425,7 @@ impl WebSocketWriteHalf {
     /// about events.
     pub async fn close(&mut self, payload: Option<(u16, String)>) -> Result<(), WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-5.5.1
-        self.send(Frame::Close { payload }).await?;
+        self.send(Frame::close(payload)).await?;
         // self.shutdown().await?;
         Ok(())
     }
@@ -204,7 +1436,10 @@ impl WebSocketWriteHalf {
     /// This method will flush incoming events.
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
-    pub async fn send_ping(&mut self, payload: Option<Vec<u8>>) -> Result<(), WebSocketError> {
+    pub async fn send_ping(
+        &mut self,
+        payload: Option<Vec<u8>>,
+    ) -> Result<SendReceipt, WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-5.5.2
         self.send(Frame::Ping { payload }).await
     }
@@ -215,7 +1450,10 @@ impl WebSocketWriteHalf {
     /// This method will flush incoming events.
     /// See the documentation on the [`WebSocket`](WebSocket#splitting) type for more details
     /// about events.
-    pub async fn send_pong(&mut self, payload: Option<Vec<u8>>) -> Result<(), WebSocketError> {
+    pub async fn send_pong(
+        &mut self,
+        payload: Option<Vec<u8>>,
+    ) -> Result<SendReceipt, WebSocketError> {
         // https://tools.ietf.org/html/rfc6455#section-5.5.3
         self.send(Frame::Pong { payload }).await
     }
@@ -224,6 +1462,8 @@ impl WebSocketWriteHalf {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{closing_server, scripted_server};
+    use crate::WebSocket;
 
     #[test]
     fn assert_send_sync()
@@ -232,4 +1472,252 @@ mod tests {
         WebSocketWriteHalf: Send + Sync,
     {
     }
+
+    #[tokio::test]
+    async fn non_minimal_lengths_are_rejected_in_strict_mode() {
+        // "hi" with its length encoded in 16 bits
+        let frames = b"\x81\x7e\x00\x02hi";
+
+        let mut ws = WebSocket::connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        assert_eq!(ws.receive().await.unwrap().as_text().unwrap().0, "hi");
+
+        let mut ws = WebSocket::builder()
+            .strict_mode(true)
+            .connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::NonMinimalLengthError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn close_listener_resolves_with_close_frame() {
+        // a Close frame with status code 1001 and reason "bye"
+        let mut ws = WebSocket::connect(&scripted_server(b"\x88\x05\x03\xe9bye").await)
+            .await
+            .unwrap();
+        let listener = ws.close_listener();
+        assert_eq!(listener.close_status(), None);
+        let supervisor = tokio::spawn(listener.closed());
+        assert!(ws.receive().await.unwrap().is_close());
+        assert_eq!(
+            supervisor.await.unwrap(),
+            (CloseCode::GOING_AWAY, "bye".to_string())
+        );
+        assert_eq!(ws.closed().await.0, CloseCode::GOING_AWAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_sends_block_writes_until_flushed() {
+        let server = crate::test_util::EchoServer::spawn().await;
+        let mut ws = WebSocket::builder()
+            .throttle(Some(100), Duration::ZERO)
+            .connect(&server.url())
+            .await
+            .unwrap();
+        assert!(!ws.is_write_blocked());
+
+        // writing 500 bytes at 100 bytes per second takes about 5 seconds
+        let send = ws.send_binary(vec![0; 500]);
+        assert!(tokio::time::timeout(Duration::from_secs(1), send)
+            .await
+            .is_err());
+        assert!(ws.is_write_blocked());
+        ws.writable().await.unwrap();
+        assert!(!ws.is_write_blocked());
+        assert_eq!(
+            ws.receive().await.unwrap().as_binary().unwrap().0.len(),
+            500
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_are_sent_unmasked_when_masking_is_disabled() {
+        let server = crate::test_util::EchoServer::spawn().await;
+        let mut ws = WebSocket::builder()
+            .danger_disable_masking(true)
+            .connect(&server.url())
+            .await
+            .unwrap();
+        // a 2 byte header and the payload, without a masking key
+        assert_eq!(ws.send_text("hi".to_string()).await.unwrap().frame_bytes, 4);
+        assert_eq!(ws.receive().await.unwrap(), Frame::text("hi".to_string()));
+    }
+
+    #[tokio::test]
+    async fn ping_floods_close_the_connection() {
+        let frames = b"\x89\x00\x89\x00\x89\x00";
+
+        let mut ws = WebSocket::builder()
+            .max_pongs_per_second(Some(2))
+            .ping_flood_policy(PingFloodPolicy::Close)
+            .connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        let state = ws.connection_state();
+        assert_eq!(ws.receive().await.unwrap(), Frame::Ping { payload: None });
+        assert_eq!(ws.receive().await.unwrap(), Frame::Ping { payload: None });
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::PingFloodError)
+        ));
+        assert_eq!(*state.borrow(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn queued_pongs_are_coalesced() {
+        let frames = b"\x89\x01a\x89\x01b\x89\x01c";
+
+        let ws = WebSocket::connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        let (mut read_half, mut write_half) = ws.split();
+        for _ in 0..3 {
+            read_half.receive().await.unwrap();
+        }
+        assert_eq!(read_half.stats().queued_events(), 1);
+        write_half.flush().await.unwrap();
+        let stats = write_half.stats();
+        assert_eq!(stats.queued_events(), 0);
+        assert_eq!(stats.peak_queued_events(), 1);
+        // one Pong, for the last Ping
+        assert_eq!(stats.sent().frames(), 1);
+
+        let ws = WebSocket::builder()
+            .max_queued_events(2)
+            .event_queue_policy(EventQueuePolicy::Error)
+            .connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        let (mut read_half, _write_half) = ws.split();
+        read_half.receive().await.unwrap();
+        read_half.receive().await.unwrap();
+        assert!(matches!(
+            read_half.receive().await,
+            Err(WebSocketError::EventQueueFullError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered_after_the_event_queue_was_full() {
+        // the Close frame fills the queue, so the Pong for the first Ping cannot be queued
+        let frames = b"\x88\x00\x89\x01a\x89\x01b";
+
+        let ws = WebSocket::builder()
+            .max_queued_events(1)
+            .close_policy(ClosePolicy::Manual)
+            .connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        let (mut read_half, mut write_half) = ws.split();
+        assert_eq!(read_half.receive().await.unwrap(), Frame::close(None));
+        assert!(matches!(
+            read_half.receive().await,
+            Err(WebSocketError::EventQueueFullError)
+        ));
+        write_half.flush().await.unwrap();
+        read_half.receive().await.unwrap();
+        write_half.flush().await.unwrap();
+        // the Pong for the second Ping
+        assert_eq!(write_half.stats().sent().frames(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_halves_respond_to_pings_with_an_event_pump() {
+        let ws = WebSocket::connect(&scripted_server(b"\x89\x01a").await)
+            .await
+            .unwrap();
+        let (mut read_half, write_half) = ws.split();
+        read_half.respond_control(Some(write_half.event_pump()));
+        read_half.receive().await.unwrap();
+        // the Pong was sent without the write half being flushed
+        assert_eq!(read_half.stats().queued_events(), 0);
+        assert_eq!(read_half.stats().sent().frames(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_frames_without_a_payload_pass_the_filter() {
+        let ws = WebSocket::connect(&scripted_server(b"\x89\x00\x81\x02hi\x88\x00").await)
+            .await
+            .unwrap();
+        let (mut read_half, _write_half) = ws.split();
+        read_half.set_frame_filter(OpcodeSet::DATA);
+        assert_eq!(
+            read_half.receive().await.unwrap(),
+            Frame::text("hi".to_string())
+        );
+        assert_eq!(read_half.receive().await.unwrap(), Frame::close(None));
+    }
+
+    #[tokio::test]
+    async fn dropped_frames_are_counted_and_reported() {
+        let frames = b"\x89\x01a\x89\x01b\x81\x02hi";
+
+        let dropped = Arc::new(StdMutex::new(Vec::new()));
+        let on_dropped = dropped.clone();
+        let ws = WebSocket::builder()
+            .on_dropped(move |reason, frame| {
+                on_dropped.lock().unwrap().push((reason, frame.clone()))
+            })
+            .connect(&scripted_server(frames).await)
+            .await
+            .unwrap();
+        let (mut read_half, mut write_half) = ws.split();
+        read_half.set_frame_filter(OpcodeSet::DATA);
+        assert_eq!(
+            read_half.receive().await.unwrap(),
+            Frame::text("hi".to_string())
+        );
+        // the queued Pong can no longer be sent
+        write_half.shutdown().await.unwrap();
+        write_half.flush().await.unwrap();
+
+        let ping = |payload: &[u8]| Frame::Ping {
+            payload: Some(payload.to_vec()),
+        };
+        let pong = |payload: &[u8]| Frame::Pong {
+            payload: Some(payload.to_vec()),
+        };
+        assert_eq!(
+            *dropped.lock().unwrap(),
+            vec![
+                (DropReason::FrameFilter, ping(b"a")),
+                (DropReason::CoalescedPong, pong(b"a")),
+                (DropReason::FrameFilter, ping(b"b")),
+                (DropReason::Closed, pong(b"b")),
+            ]
+        );
+        let stats = read_half.stats();
+        assert_eq!(stats.dropped(DropReason::FrameFilter), 2);
+        assert_eq!(stats.total_dropped(), 4);
+    }
+
+    #[tokio::test]
+    async fn read_errors_report_how_much_payload_was_received() {
+        // a Binary frame with a 10 byte payload, cut off after 4 bytes
+        let mut ws = WebSocket::connect(&closing_server(b"\x82\x0a1234").await)
+            .await
+            .unwrap();
+        match ws.receive().await {
+            Err(WebSocketError::PayloadReadError {
+                source,
+                received: 4,
+                expected: 10,
+            }) => assert_eq!(source.kind(), ErrorKind::UnexpectedEof),
+            result => panic!("expected payload read error, got {:?}", result),
+        }
+
+        // cut off in the middle of the header
+        let mut ws = WebSocket::connect(&closing_server(b"\x82\x7e\x01").await)
+            .await
+            .unwrap();
+        assert!(matches!(
+            ws.receive().await,
+            Err(WebSocketError::ReadError(_))
+        ));
+    }
 }
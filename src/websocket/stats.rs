@@ -0,0 +1,387 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use super::frame::Frame;
+#[allow(unused_imports)] // for intra doc links
+use super::{builder::WebSocketBuilder, WebSocket};
+
+/// The number of Close frames received by every WebSocket in the process,
+/// by host and status code
+static CLOSE_CODES: StdMutex<BTreeMap<(String, Option<u16>), u64>> = StdMutex::new(BTreeMap::new());
+
+/// The number of buckets in a [`FrameSizeHistogram`]: one for empty payloads,
+/// and one for each power of two up to 2^63
+const BUCKETS: usize = 65;
+
+/// How long each phase of connecting took, generated from [`WebSocket::connect_timings()`].
+///
+/// The phases run one after another, so their sum is the time it took to connect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectTimings {
+    /// Resolving the host's address (including SRV lookups, and connection attempts to
+    /// SRV targets which failed before one succeeded)
+    pub resolve: Duration,
+    /// Opening the connection with TCP (or the custom [`Transport`](crate::Transport))
+    pub connect: Duration,
+    /// Exchanging the [plaintext preamble](crate::WebSocketBuilder::plaintext_preamble()),
+    /// if there is one
+    pub preamble: Duration,
+    /// The TLS handshake, for `wss` URLs
+    pub tls: Duration,
+    /// The WebSocket handshake, including the
+    /// [`before_handshake()`](crate::WebSocketBuilder::before_handshake()) hook
+    pub handshake: Duration,
+}
+
+impl ConnectTimings {
+    /// Returns the time it took to connect, from the start of resolving the host's address
+    /// to the end of the WebSocket handshake.
+    pub fn total(&self) -> Duration {
+        self.resolve + self.connect + self.preamble + self.tls + self.handshake
+    }
+}
+
+/// Statistics about the frames sent and received over a WebSocket connection,
+/// and the events queued between its halves, generated from [`WebSocket::stats()`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    sent: FrameSizeHistogram,
+    received: FrameSizeHistogram,
+    queued_events: usize,
+    peak_queued_events: usize,
+    dropped: BTreeMap<DropReason, u64>,
+}
+
+impl Stats {
+    /// Returns the distribution of the payload sizes of frames sent
+    /// (including frames sent automatically, such as Pong frames).
+    pub fn sent(&self) -> &FrameSizeHistogram {
+        &self.sent
+    }
+
+    /// Returns the distribution of the payload sizes of frames received.
+    pub fn received(&self) -> &FrameSizeHistogram {
+        &self.received
+    }
+
+    /// Returns the number of events (such as Pong frames to send in response to Pings)
+    /// which the read half has queued and the write half has not yet acted on, when the
+    /// statistics were generated. A number which keeps growing means the write half
+    /// is not being [flushed](crate::WebSocketWriteHalf::flush()) often enough
+    /// (see [`EventQueuePolicy`](crate::EventQueuePolicy)).
+    pub fn queued_events(&self) -> usize {
+        self.queued_events
+    }
+
+    /// Returns the most events which have been queued at once.
+    pub fn peak_queued_events(&self) -> usize {
+        self.peak_queued_events
+    }
+
+    /// Returns the number of frames dropped for a reason (see [`DropReason`]).
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.dropped.get(&reason).copied().unwrap_or(0)
+    }
+
+    /// Returns the total number of frames dropped, for any reason.
+    pub fn total_dropped(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+
+    pub(super) fn set_queued_events(&mut self, queued_events: usize) {
+        self.queued_events = queued_events;
+    }
+
+    pub(super) fn record_queued_events(&mut self, queued_events: usize) {
+        self.peak_queued_events = self.peak_queued_events.max(queued_events);
+    }
+
+    pub(super) fn record_dropped(&mut self, reason: DropReason) {
+        *self.dropped.entry(reason).or_insert(0) += 1;
+    }
+
+    pub(super) fn record_sent(&mut self, payload_len: usize) {
+        self.sent.record(payload_len);
+    }
+
+    pub(super) fn record_received(&mut self, payload_len: usize) {
+        self.received.record(payload_len);
+    }
+}
+
+/// Why a frame was dropped by the library, instead of being returned to the application
+/// or sent. Drops are counted in [`Stats::dropped()`], and reported to the callback set
+/// with [`WebSocketBuilder::on_dropped()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DropReason {
+    /// A received frame was removed by the
+    /// [frame filter](crate::WebSocketReadHalf::set_frame_filter())
+    FrameFilter,
+    /// A received frame was removed as a
+    /// [duplicate](WebSocketBuilder::suppress_duplicates())
+    DuplicateFilter,
+    /// A received Ping frame was not responded to, because it exceeded the
+    /// [limits on Pongs](WebSocketBuilder::max_pongs_per_second())
+    PingLimit,
+    /// A queued Pong frame was replaced by the Pong for a later Ping
+    /// (see [`EventQueuePolicy::Coalesce`](crate::EventQueuePolicy::Coalesce))
+    CoalescedPong,
+    /// A frame was received while waiting for the server's Close frame after closing
+    /// (such as in [`WebSocket::close_for_reuse()`]), and discarded
+    ReceivedWhileClosing,
+    /// A Pong or Close frame was not sent in response to the server, because the
+    /// connection had already been closed (by the closing handshake, or by
+    /// [`shutdown()`](crate::WebSocketWriteHalf::shutdown()))
+    Closed,
+}
+
+/// Called with each frame dropped by the library
+type OnDropped = Arc<dyn Fn(DropReason, &Frame) + Send + Sync>;
+
+/// The callback set with [`WebSocketBuilder::on_dropped()`]
+#[derive(Clone)]
+pub(super) struct DropListener(pub(super) OnDropped);
+
+impl Debug for DropListener {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("DropListener")
+    }
+}
+
+/// An exponential histogram of frame payload sizes.
+///
+/// Bucket 0 counts empty payloads, and bucket `i` (for `i` from 1 to 64) counts payloads
+/// of at least 2^(`i` - 1) bytes and less than 2^`i` bytes. This shows, for example,
+/// whether traffic is made up of many tiny frames (which may benefit from batching)
+/// or a few large ones (which may benefit from fragmentation).
+///
+/// ```
+/// # use websockets::{WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let mut ws = WebSocket::connect("wss://echo.websocket.org").await?;
+/// ws.send_text("hello".to_string()).await?;
+/// for (sizes, count) in ws.stats().sent().buckets() {
+///     println!("{:?} bytes: {} frames", sizes, count);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSizeHistogram {
+    counts: [u64; BUCKETS],
+}
+
+impl Default for FrameSizeHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+        }
+    }
+}
+
+impl FrameSizeHistogram {
+    /// Returns the total number of frames counted.
+    pub fn frames(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the number of frames counted in each bucket, indexed by bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Returns the range of payload sizes in each non-empty bucket
+    /// along with the number of frames counted in it.
+    pub fn buckets(&self) -> impl Iterator<Item = (RangeInclusive<u64>, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (Self::bucket_range(bucket), *count))
+    }
+
+    /// Returns the range of payload sizes counted by a bucket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is greater than 64.
+    pub fn bucket_range(bucket: usize) -> RangeInclusive<u64> {
+        assert!(bucket < BUCKETS, "bucket out of range");
+        match bucket {
+            0 => 0..=0,
+            64 => 1 << 63..=u64::MAX,
+            _ => 1 << (bucket - 1)..=(1 << bucket) - 1,
+        }
+    }
+
+    fn record(&mut self, payload_len: usize) {
+        let bucket = 64 - (payload_len as u64).leading_zeros() as usize;
+        self.counts[bucket] += 1;
+    }
+}
+
+/// Counts of the Close frames received by every WebSocket over the lifetime of the process,
+/// by host and status code, generated from [`close_code_stats()`].
+///
+/// Since the counts are kept for the whole process, they include every connection made
+/// by reconnecting wrappers and [redials](crate::ConnectionInfo::redial()). This allows,
+/// for example, a spike of 1011 (Internal Error) closures from one upstream to be
+/// spotted by exporting the counts to a monitoring system:
+///
+/// ```
+/// # use websockets::close_code_name;
+/// for (host, status_code, count) in websockets::close_code_stats().counts() {
+///     let name = status_code.and_then(close_code_name).unwrap_or("other");
+///     println!(
+///         "websocket_close_codes_total{{host={:?},code=\"{:?}\",name={:?}}} {}",
+///         host, status_code, name, count
+///     );
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloseCodeStats {
+    counts: BTreeMap<(String, Option<u16>), u64>,
+}
+
+impl CloseCodeStats {
+    /// Returns the host, status code, and number of Close frames received, for each
+    /// combination of host and status code which has been received, ordered by host
+    /// and then by status code. A status code of `None` counts Close frames which
+    /// had no payload (reported to applications as 1005, No Status Received).
+    pub fn counts(&self) -> impl Iterator<Item = (&str, Option<u16>, u64)> + '_ {
+        self.counts
+            .iter()
+            .map(|((host, status_code), count)| (&host[..], *status_code, *count))
+    }
+
+    /// Returns the number of Close frames received with a status code, from any host.
+    pub fn count(&self, status_code: Option<u16>) -> u64 {
+        self.counts()
+            .filter(|(_, code, _)| *code == status_code)
+            .map(|(_, _, count)| count)
+            .sum()
+    }
+
+    /// Returns the total number of Close frames received.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+/// Returns the number of Close frames received by every WebSocket in the process,
+/// by host and status code (see [`CloseCodeStats`]).
+pub fn close_code_stats() -> CloseCodeStats {
+    CloseCodeStats {
+        counts: CLOSE_CODES.lock().unwrap().clone(),
+    }
+}
+
+pub(super) fn record_close_code(host: &str, status_code: Option<u16>) {
+    *CLOSE_CODES
+        .lock()
+        .unwrap()
+        .entry((host.to_string(), status_code))
+        .or_insert(0) += 1;
+}
+
+/// Returns the name of a status code registered with IANA
+/// (see [https://www.iana.org/assignments/websocket/websocket.xhtml#close-code-number](https://www.iana.org/assignments/websocket/websocket.xhtml#close-code-number)),
+/// or `None` if the code is not registered (including codes for private use).
+pub fn close_code_name(status_code: u16) -> Option<&'static str> {
+    // https://tools.ietf.org/html/rfc6455#section-7.4.1
+    let name = match status_code {
+        1000 => "Normal Closure",
+        1001 => "Going Away",
+        1002 => "Protocol Error",
+        1003 => "Unsupported Data",
+        1005 => "No Status Received",
+        1006 => "Abnormal Closure",
+        1007 => "Invalid Frame Payload Data",
+        1008 => "Policy Violation",
+        1009 => "Message Too Big",
+        1010 => "Mandatory Extension",
+        1011 => "Internal Error",
+        1012 => "Service Restart",
+        1013 => "Try Again Later",
+        1014 => "Bad Gateway",
+        1015 => "TLS Handshake",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_counted_in_power_of_two_buckets() {
+        let mut histogram = FrameSizeHistogram::default();
+        for payload_len in [0, 1, 2, 3, 4, 125, 126, 65536] {
+            histogram.record(payload_len);
+        }
+        assert_eq!(histogram.frames(), 8);
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (0..=0, 1),
+                (1..=1, 1),
+                (2..=3, 2),
+                (4..=7, 1),
+                (64..=127, 2),
+                (65536..=131071, 1),
+            ]
+        );
+        assert_eq!(FrameSizeHistogram::bucket_range(64), 1 << 63..=u64::MAX);
+    }
+
+    #[test]
+    fn close_codes_are_counted_by_host() {
+        // other tests may record close codes concurrently, so only hosts
+        // unique to this test are checked
+        record_close_code("close-codes-a.test", Some(1011));
+        record_close_code("close-codes-a.test", Some(1011));
+        record_close_code("close-codes-b.test", Some(1011));
+        record_close_code("close-codes-b.test", None);
+        let stats = close_code_stats();
+        let counts: Vec<_> = stats
+            .counts()
+            .filter(|(host, _, _)| host.starts_with("close-codes-"))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("close-codes-a.test", Some(1011), 2),
+                ("close-codes-b.test", None, 1),
+                ("close-codes-b.test", Some(1011), 1),
+            ]
+        );
+        assert!(stats.count(Some(1011)) >= 3);
+        assert_eq!(close_code_name(1011), Some("Internal Error"));
+        assert_eq!(close_code_name(4000), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_phases_are_timed() {
+        let server = crate::test_util::EchoServer::spawn().await;
+        let ws = WebSocket::builder()
+            .throttle(None, Duration::from_millis(300))
+            .connect(&server.url())
+            .await
+            .unwrap();
+        let timings = ws.connect_timings();
+        // the server's handshake response is delayed by the latency
+        assert!(timings.handshake >= Duration::from_millis(300));
+        assert_eq!(timings.tls, Duration::ZERO);
+        assert_eq!(timings.preamble, Duration::ZERO);
+        assert_eq!(
+            timings.total(),
+            timings.resolve + timings.connect + timings.handshake
+        );
+    }
+}
@@ -1,35 +1,85 @@
+#[cfg(feature = "tls")]
 use native_tls::TlsConnector as NativeTlsTlsConnector;
 use std::io::Error as IoError;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(feature = "tls")]
 use tokio_native_tls::{TlsConnector as TokioTlsConnector, TlsStream};
 
+#[cfg(any(test, feature = "test-util"))]
+use super::fault::FaultyStream;
+#[cfg(any(test, feature = "test-util"))]
+use super::throttle::ThrottledStream;
+use super::transport::TransportStream;
+use super::wire_trace::TracedStream;
+#[cfg(feature = "tls")]
 use crate::error::WebSocketError;
 
 #[derive(Debug)]
 pub(super) enum Stream {
     Plain(TcpStream),
-    Tls(TlsStream<TcpStream>),
+    /// A connection to a Unix domain socket, for `ws+unix` URLs
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// A stream from a custom [`Transport`](super::transport::Transport)
+    Custom(Box<dyn TransportStream>),
+    /// A TLS session over another stream, such as a TCP connection
+    /// or a stream from a custom transport
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream<Stream>>),
+    /// Another stream whose traffic is copied to a
+    /// [wire trace](super::builder::WebSocketBuilder::wire_trace())
+    Traced(Box<TracedStream>),
+    /// Another stream which faults are injected into by a
+    /// [fault injector](super::builder::WebSocketBuilder::fault_injector())
+    #[cfg(any(test, feature = "test-util"))]
+    Faulty(Box<FaultyStream>),
+    /// Another stream whose bandwidth is limited and whose received bytes are delayed by a
+    /// [throttle](super::builder::WebSocketBuilder::throttle())
+    #[cfg(any(test, feature = "test-util"))]
+    Throttled(Box<ThrottledStream>),
 }
 
 impl Stream {
+    #[cfg(feature = "tls")]
     pub(super) async fn into_tls(
         self,
         host: &str,
         tls_connector: NativeTlsTlsConnector,
     ) -> Result<Self, WebSocketError> {
         match self {
-            Self::Plain(tcp_stream) => {
+            Self::Tls(_) => Ok(self),
+            stream => {
                 let connector: TokioTlsConnector = tls_connector.into();
                 let tls_stream = connector
-                    .connect(host, tcp_stream)
+                    .connect(host, stream)
                     .await
                     .map_err(|e| WebSocketError::TlsConnectionError(e))?;
-                Ok(Stream::Tls(tls_stream))
+                Ok(Stream::Tls(Box::new(tls_stream)))
             }
-            Self::Tls(_) => Ok(self),
+        }
+    }
+
+    /// Returns the local address of the underlying TCP connection,
+    /// or `None` if a custom transport or a Unix domain socket is used.
+    pub(super) fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Plain(tcp_stream) => tcp_stream.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+            Self::Custom(_) => None,
+            #[cfg(feature = "tls")]
+            Self::Tls(tls_stream) => tls_stream.get_ref().get_ref().get_ref().local_addr(),
+            Self::Traced(traced_stream) => traced_stream.get_ref().local_addr(),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Faulty(faulty_stream) => faulty_stream.get_ref().local_addr(),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Throttled(throttled_stream) => throttled_stream.get_ref().local_addr(),
         }
     }
 
@@ -56,7 +106,18 @@ impl AsyncRead for Stream {
     ) -> Poll<Result<(), std::io::Error>> {
         match self.get_mut() {
             Self::Plain(tcp_stream) => Pin::new(tcp_stream).poll_read(cx, buf),
-            Self::Tls(tls_stream) => Pin::new(tls_stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(unix_stream) => Pin::new(unix_stream).poll_read(cx, buf),
+            Self::Custom(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(tls_stream) => Pin::new(tls_stream.as_mut()).poll_read(cx, buf),
+            Self::Traced(traced_stream) => Pin::new(traced_stream.as_mut()).poll_read(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Faulty(faulty_stream) => Pin::new(faulty_stream.as_mut()).poll_read(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Throttled(throttled_stream) => {
+                Pin::new(throttled_stream.as_mut()).poll_read(cx, buf)
+            }
         }
     }
 }
@@ -69,21 +130,83 @@ impl AsyncWrite for Stream {
     ) -> Poll<Result<usize, IoError>> {
         match self.get_mut() {
             Self::Plain(tcp_stream) => Pin::new(tcp_stream).poll_write(cx, buf),
-            Self::Tls(tls_stream) => Pin::new(tls_stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(unix_stream) => Pin::new(unix_stream).poll_write(cx, buf),
+            Self::Custom(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(tls_stream) => Pin::new(tls_stream.as_mut()).poll_write(cx, buf),
+            Self::Traced(traced_stream) => Pin::new(traced_stream.as_mut()).poll_write(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Faulty(faulty_stream) => Pin::new(faulty_stream.as_mut()).poll_write(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Throttled(throttled_stream) => {
+                Pin::new(throttled_stream.as_mut()).poll_write(cx, buf)
+            }
         }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
         match self.get_mut() {
             Self::Plain(tcp_stream) => Pin::new(tcp_stream).poll_flush(cx),
-            Self::Tls(tls_stream) => Pin::new(tls_stream).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(unix_stream) => Pin::new(unix_stream).poll_flush(cx),
+            Self::Custom(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(tls_stream) => Pin::new(tls_stream.as_mut()).poll_flush(cx),
+            Self::Traced(traced_stream) => Pin::new(traced_stream.as_mut()).poll_flush(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Faulty(faulty_stream) => Pin::new(faulty_stream.as_mut()).poll_flush(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Throttled(throttled_stream) => Pin::new(throttled_stream.as_mut()).poll_flush(cx),
         }
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
         match self.get_mut() {
             Self::Plain(tcp_stream) => Pin::new(tcp_stream).poll_shutdown(cx),
-            Self::Tls(tls_stream) => Pin::new(tls_stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(unix_stream) => Pin::new(unix_stream).poll_shutdown(cx),
+            Self::Custom(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(tls_stream) => Pin::new(tls_stream.as_mut()).poll_shutdown(cx),
+            Self::Traced(traced_stream) => Pin::new(traced_stream.as_mut()).poll_shutdown(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Faulty(faulty_stream) => Pin::new(faulty_stream.as_mut()).poll_shutdown(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            Self::Throttled(throttled_stream) => {
+                Pin::new(throttled_stream.as_mut()).poll_shutdown(cx)
+            }
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use tokio::net::{TcpStream, UnixListener};
+
+    use crate::test_util::EchoServer;
+    use crate::WebSocket;
+
+    #[tokio::test]
+    async fn ws_unix_urls_connect_over_unix_sockets() {
+        let server = EchoServer::spawn().await;
+        let path = std::env::temp_dir().join(format!("websockets-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        // forward the connection to the echo server
+        tokio::spawn(async move {
+            let (mut unix_stream, _) = listener.accept().await.unwrap();
+            let mut tcp_stream = TcpStream::connect(server.addr()).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut unix_stream, &mut tcp_stream).await;
+        });
+
+        let url = format!("ws+unix://{}:/echo", path.display());
+        let mut ws = WebSocket::connect(&url).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let connection_info = ws.connection_info().as_ref().unwrap();
+        assert_eq!(connection_info.path(), "/echo");
+        assert_eq!(connection_info.addr(), None);
+        ws.send_text("hello".to_string()).await.unwrap();
+        assert_eq!(ws.receive().await.unwrap().as_text().unwrap().0, "hello");
+    }
+}
@@ -0,0 +1,282 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use super::builder::WebSocketBuilder;
+use super::connection_state::ConnectionState;
+use super::driver::ConnectionDriver;
+use super::frame::Frame;
+use super::message::Message;
+use super::split::{SendReceipt, WebSocketReadHalf, WebSocketWriteHalf};
+#[allow(unused_imports)] // for intra doc links
+use super::{connection_info::ConnectionInfo, WebSocket};
+use crate::error::WebSocketError;
+
+/// How long to wait before the first attempt to reconnect
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// The longest wait between attempts to reconnect, which doubles after each failed attempt
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// A WebSocket connection managed by a spawned task (available with the `task` feature),
+/// which connects, runs a handler for each connection, and reconnects when
+/// the connection is lost, until it is shut down.
+///
+/// The handler is a function returning a future, which is given a [`TaskContext`] for
+/// sending and receiving over the connection. It is run again (with a new context) each
+/// time the connection is re-established. If the handler returns `Ok(())`, the task
+/// finishes; if it returns an error, or the server stops responding to
+/// [keepalive](WebSocketBuilder::keepalive_interval()) Pings, the task
+/// [redials](ConnectionInfo::redial()) the connection, waiting 1 second before the
+/// first attempt and doubling the wait after each failed attempt, up to 1 minute.
+///
+/// ```no_run
+/// # use websockets::{Message, WebSocketError, WebSocketTask};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let task = WebSocketTask::spawn("wss://feed.example.com", |mut ctx| async move {
+///     ctx.send_text("subscribe".to_string()).await?;
+///     while let Some(message) = ctx.receive().await? {
+///         if let Message::Text(text) = message {
+///             println!("received {}", text);
+///         }
+///     }
+///     // the server closed the connection, so reconnect
+///     Err(WebSocketError::WebSocketClosedError)
+/// });
+/// // ...
+/// task.shutdown();
+/// task.join().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WebSocketTask {
+    handle: JoinHandle<Result<(), WebSocketError>>,
+    shutdown_token: CancellationToken,
+    state: watch::Receiver<ConnectionState>,
+}
+
+impl Debug for WebSocketTask {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.debug_struct("WebSocketTask")
+            .field("state", &*self.state.borrow())
+            .field("finished", &self.handle.is_finished())
+            .finish()
+    }
+}
+
+impl WebSocketTask {
+    /// Spawns a task which connects to the URL with the default settings and runs
+    /// `handler` for each connection. Must be called from within a Tokio runtime.
+    pub fn spawn<F, Fut>(url: &str, handler: F) -> Self
+    where
+        F: Fn(TaskContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), WebSocketError>> + Send + 'static,
+    {
+        Self::spawn_with(WebSocket::builder(), url, handler)
+    }
+
+    /// Spawns a task which connects to the URL with the settings of `builder`
+    /// (such as its [keepalive interval](WebSocketBuilder::keepalive_interval()))
+    /// and runs `handler` for each connection. Must be called from within a Tokio runtime.
+    ///
+    /// If the builder has a [shutdown token](WebSocketBuilder::shutdown_token()),
+    /// cancelling it also shuts down the task.
+    pub fn spawn_with<F, Fut>(mut builder: WebSocketBuilder, url: &str, handler: F) -> Self
+    where
+        F: Fn(TaskContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), WebSocketError>> + Send + 'static,
+    {
+        let shutdown_token = match builder.shutdown_token.as_ref() {
+            Some(shutdown_token) => shutdown_token.child_token(),
+            None => CancellationToken::new(),
+        };
+        builder.shutdown_token(shutdown_token.clone());
+        let state = builder.connection_state();
+        let url = url.to_string();
+        let task_shutdown_token = shutdown_token.clone();
+        let handle =
+            tokio::spawn(async move { run(builder, url, handler, task_shutdown_token).await });
+        Self {
+            handle,
+            shutdown_token,
+            state,
+        }
+    }
+
+    /// Returns a receiver of the [`ConnectionState`] of the task's connection,
+    /// which follows it across reconnects (see [`WebSocket::connection_state()`]).
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Shuts down the task: the closing handshake is performed if the connection is open,
+    /// the handler's pending and future receives fail with
+    /// [`CancelledError`](WebSocketError::CancelledError), and the task stops reconnecting.
+    pub fn shutdown(&self) {
+        self.shutdown_token.cancel();
+    }
+
+    /// Waits for the task to finish, returning `Ok(())` if the handler finished
+    /// or the task was shut down, or the error of the first connection attempt
+    /// if it failed (in which case the task does not reconnect).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handler panicked.
+    pub async fn join(self) -> Result<(), WebSocketError> {
+        match self.handle.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// Connects, and runs the handler for each connection until it finishes
+/// or the task is shut down.
+async fn run<F, Fut>(
+    mut builder: WebSocketBuilder,
+    url: String,
+    handler: F,
+    shutdown_token: CancellationToken,
+) -> Result<(), WebSocketError>
+where
+    F: Fn(TaskContext) -> Fut,
+    Fut: Future<Output = Result<(), WebSocketError>>,
+{
+    let logger = builder.logger;
+    let mut ws = builder.connect(&url).await?;
+    // only connections made by this library have connection info
+    let connection_info = ws.connection_info().clone().unwrap();
+    let mut reconnects = 0;
+    loop {
+        let (read_half, write_half, driver) = ws.split_with_driver();
+        let context = TaskContext {
+            read_half,
+            write_half,
+            reconnects,
+        };
+        let result = drive(handler(context), driver).await;
+        if shutdown_token.is_cancelled() {
+            return Ok(());
+        }
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => logger.warn(format_args!(
+                "websocket task for {} is reconnecting after an error: {}",
+                connection_info.host(),
+                e
+            )),
+        }
+
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        ws = loop {
+            tokio::select! {
+                _ = time::sleep(delay) => (),
+                _ = shutdown_token.cancelled() => return Ok(()),
+            }
+            match connection_info.redial().await {
+                Ok(ws) => break ws,
+                Err(_) => delay = (delay * 2).min(MAX_RECONNECT_DELAY),
+            }
+        };
+        reconnects += 1;
+    }
+}
+
+/// Runs the handler along with the connection's driver, so that the handler
+/// is stopped if the driver fails (such as when a keepalive Ping times out)
+async fn drive<Fut>(handler: Fut, driver: ConnectionDriver) -> Result<(), WebSocketError>
+where
+    Fut: Future<Output = Result<(), WebSocketError>>,
+{
+    tokio::pin!(handler);
+    tokio::select! {
+        biased;
+        result = &mut handler => result,
+        result = driver => match result {
+            Ok(()) => handler.await,
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// A connection of a [`WebSocketTask`], given to its handler.
+///
+/// Events (such as Pongs to send in response to Pings) are flushed by the task,
+/// and keepalive Pings are sent by it if they are configured.
+#[derive(Debug)]
+pub struct TaskContext {
+    read_half: WebSocketReadHalf,
+    write_half: WebSocketWriteHalf,
+    reconnects: u64,
+}
+
+impl TaskContext {
+    /// Receives a complete message (see [`WebSocketReadHalf::receive_message()`]).
+    /// Returns `Ok(None)` once the server has closed the connection.
+    pub async fn receive(&mut self) -> Result<Option<Message>, WebSocketError> {
+        self.read_half.receive_message().await
+    }
+
+    /// Sends an already constructed [`Frame`].
+    pub async fn send(&mut self, frame: Frame) -> Result<SendReceipt, WebSocketError> {
+        self.write_half.send(frame).await
+    }
+
+    /// Sends a Text frame.
+    pub async fn send_text(&mut self, payload: String) -> Result<SendReceipt, WebSocketError> {
+        self.write_half.send_text(payload).await
+    }
+
+    /// Sends a Binary frame.
+    pub async fn send_binary(&mut self, payload: Vec<u8>) -> Result<SendReceipt, WebSocketError> {
+        self.write_half.send_binary(payload).await
+    }
+
+    /// Returns the number of times the task has reconnected before this connection,
+    /// which is 0 for the first connection. This can be used to restore state
+    /// (such as subscriptions) after reconnecting.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Returns the read half and the write half of the connection,
+    /// for anything not covered by the other methods.
+    pub fn halves(&mut self) -> (&mut WebSocketReadHalf, &mut WebSocketWriteHalf) {
+        (&mut self.read_half, &mut self.write_half)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+
+    #[tokio::test(start_paused = true)]
+    async fn handlers_are_run_again_after_reconnecting() {
+        let server = EchoServer::spawn().await;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let task = WebSocketTask::spawn(&server.url(), move |mut ctx| {
+            let sender = sender.clone();
+            async move {
+                ctx.send_text(format!("hello {}", ctx.reconnects())).await?;
+                let message = ctx.receive().await?.unwrap();
+                sender.send(message.as_text().unwrap().to_string()).unwrap();
+                match ctx.reconnects() {
+                    // pretend the connection was lost
+                    0 => Err(WebSocketError::WebSocketClosedError),
+                    _ => ctx.receive().await.map(|_| ()),
+                }
+            }
+        });
+        assert_eq!(receiver.recv().await.unwrap(), "hello 0");
+        assert_eq!(receiver.recv().await.unwrap(), "hello 1");
+        task.shutdown();
+        task.join().await.unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::Error as IoError;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Instant, Sleep};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::stream::Stream;
+
+/// The most bytes read from the underlying stream at once
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+/// The most chunks read ahead of the application, so that memory is bounded
+const MAX_QUEUED_CHUNKS: usize = 64;
+
+/// The bandwidth and latency set with [`WebSocketBuilder::throttle()`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Throttle {
+    pub(super) bytes_per_sec: Option<u64>,
+    pub(super) latency: Duration,
+}
+
+impl Throttle {
+    /// Returns the most bytes transferred in one read or write, so that frames are
+    /// delivered in parts over slow connections (a tenth of a second's worth of bytes)
+    fn max_transfer(&self) -> usize {
+        match self.bytes_per_sec {
+            Some(bytes_per_sec) => (bytes_per_sec / 10).max(1) as usize,
+            None => usize::MAX,
+        }
+    }
+
+    /// Returns how long transferring `len` bytes takes at the bandwidth
+    fn transfer_time(&self, len: usize) -> Duration {
+        match self.bytes_per_sec {
+            Some(bytes_per_sec) => Duration::from_secs_f64(len as f64 / bytes_per_sec as f64),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Bytes read from the underlying stream, which may be delivered at `ready_at`.
+/// An empty chunk marks the end of the stream.
+#[derive(Debug)]
+struct Chunk {
+    ready_at: Instant,
+    bytes: Result<Vec<u8>, IoError>,
+}
+
+/// A stream which limits the bandwidth of another stream and delays the bytes received
+/// from it, to reproduce slow networks in tests
+#[derive(Debug)]
+pub(super) struct ThrottledStream {
+    stream: Stream,
+    throttle: Throttle,
+    received: VecDeque<Chunk>,
+    /// Whether the underlying stream has ended or failed, so it is not read anymore
+    read_done: bool,
+    /// When the bandwidth allows the next bytes to be read or written
+    next_read_at: Instant,
+    next_write_at: Instant,
+    read_sleep: Pin<Box<Sleep>>,
+    write_sleep: Pin<Box<Sleep>>,
+}
+
+impl ThrottledStream {
+    pub(super) fn new(stream: Stream, throttle: Throttle) -> Self {
+        let now = Instant::now();
+        Self {
+            stream,
+            throttle,
+            received: VecDeque::new(),
+            read_done: false,
+            next_read_at: now,
+            next_write_at: now,
+            read_sleep: Box::pin(time::sleep_until(now)),
+            write_sleep: Box::pin(time::sleep_until(now)),
+        }
+    }
+
+    pub(super) fn get_ref(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Reads everything available from the underlying stream into the queue of
+    /// received chunks, timestamping each chunk with when it may be delivered.
+    fn poll_receive(&mut self, cx: &mut Context<'_>) {
+        while !self.read_done && self.received.len() < MAX_QUEUED_CHUNKS {
+            let mut bytes = vec![0; READ_CHUNK_SIZE];
+            let mut buf = ReadBuf::new(&mut bytes);
+            let bytes = match Pin::new(&mut self.stream).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) => {
+                    let len = buf.filled().len();
+                    bytes.truncate(len);
+                    self.read_done = len == 0;
+                    Ok(bytes)
+                }
+                Poll::Ready(Err(e)) => {
+                    self.read_done = true;
+                    Err(e)
+                }
+                Poll::Pending => return,
+            };
+            self.received.push_back(Chunk {
+                ready_at: Instant::now() + self.throttle.latency,
+                bytes,
+            });
+        }
+    }
+}
+
+impl AsyncRead for ThrottledStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        this.poll_receive(cx);
+        let ready_at = match this.received.front() {
+            Some(chunk) => chunk.ready_at.max(this.next_read_at),
+            // the underlying stream failed, and its error has been returned
+            None if this.read_done => return Poll::Ready(Ok(())),
+            None => return Poll::Pending,
+        };
+        if ready_at > Instant::now() {
+            this.read_sleep.as_mut().reset(ready_at);
+            ready!(this.read_sleep.as_mut().poll(cx));
+        }
+
+        let chunk = this.received.front_mut().unwrap();
+        let bytes = match &mut chunk.bytes {
+            // the end of the stream is left in the queue, so every later read also ends
+            Ok(bytes) if bytes.is_empty() => return Poll::Ready(Ok(())),
+            Ok(bytes) => bytes,
+            Err(_) => {
+                let chunk = this.received.pop_front().unwrap();
+                return Poll::Ready(chunk.bytes.map(|_| ()));
+            }
+        };
+        let len = bytes
+            .len()
+            .min(buf.remaining())
+            .min(this.throttle.max_transfer());
+        buf.put_slice(&bytes[..len]);
+        bytes.drain(..len);
+        if bytes.is_empty() {
+            this.received.pop_front();
+        }
+        this.next_read_at = Instant::now() + this.throttle.transfer_time(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ThrottledStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        if this.next_write_at > Instant::now() {
+            this.write_sleep.as_mut().reset(this.next_write_at);
+            ready!(this.write_sleep.as_mut().poll(cx));
+        }
+        let len = buf.len().min(this.throttle.max_transfer());
+        let written = ready!(Pin::new(&mut this.stream).poll_write(cx, &buf[..len]))?;
+        this.next_write_at = Instant::now() + this.throttle.transfer_time(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::EchoServer;
+    use crate::WebSocket;
+
+    #[tokio::test(start_paused = true)]
+    async fn bandwidth_and_latency_are_simulated() {
+        let server = EchoServer::spawn().await;
+        let mut ws = WebSocket::builder()
+            .throttle(Some(1_000), Duration::from_millis(500))
+            .connect(&server.url())
+            .await
+            .unwrap();
+
+        let started = Instant::now();
+        ws.send_binary(vec![0; 2_000]).await.unwrap();
+        // sending 2000 bytes at 1000 bytes per second takes about 2 seconds
+        assert!(started.elapsed() >= Duration::from_millis(1_900));
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_binary().unwrap().0.len(), 2_000);
+        // and so does receiving them, after the latency
+        assert!(started.elapsed() >= Duration::from_millis(4_400));
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+
+/// A connection established by a [`Transport`], over which the WebSocket handshake
+/// (and TLS, for `wss` URLs) is performed.
+///
+/// This trait is implemented for every type that implements [`AsyncRead`], [`AsyncWrite`],
+/// [`Unpin`], [`Send`], and [`Sync`].
+pub trait TransportStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T> TransportStream for T where T: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl Debug for dyn TransportStream {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("TransportStream")
+    }
+}
+
+/// The future returned by [`Transport::connect()`].
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TransportStream>, IoError>> + Send + 'a>>;
+
+/// The future returned by a [plaintext preamble](WebSocketBuilder::plaintext_preamble()).
+pub type PreambleFuture<'a> = Pin<Box<dyn Future<Output = Result<(), IoError>> + Send + 'a>>;
+
+/// Exchanges bytes on the plain stream before TLS and the WebSocket handshake.
+pub(super) type Preamble =
+    Arc<dyn for<'a> Fn(&'a mut dyn TransportStream) -> PreambleFuture<'a> + Send + Sync>;
+
+/// Establishes the connections that WebSockets are built on, which is TCP by default.
+/// Set with [`WebSocketBuilder::transport()`].
+///
+/// Implementing this trait allows WebSockets to run over other streams (such as
+/// QUIC streams, SSH channels, or instrumented wrappers around TCP) while reusing
+/// the handshake and framing of this library.
+///
+/// ```
+/// # use std::net::SocketAddr;
+/// # use tokio::net::TcpStream;
+/// # use websockets::{Transport, TransportFuture, WebSocket, WebSocketError};
+/// struct LoggingTransport;
+///
+/// impl Transport for LoggingTransport {
+///     fn connect(&self, addr: SocketAddr) -> TransportFuture<'_> {
+///         Box::pin(async move {
+///             println!("connecting to {}", addr);
+///             let stream = TcpStream::connect(addr).await?;
+///             stream.set_nodelay(true)?;
+///             Ok(Box::new(stream) as _)
+///         })
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let mut ws = WebSocket::builder()
+///     .transport(LoggingTransport)
+///     .connect("wss://echo.websocket.org")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Transport: Send + Sync {
+    /// Connects to the address that the URL's host was resolved to.
+    fn connect(&self, addr: SocketAddr) -> TransportFuture<'_>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{self, AsyncWriteExt, BufReader};
+
+    use super::*;
+    use crate::test_util::accept_handshake;
+    use crate::WebSocket;
+
+    /// Connects to an in-memory server which completes the handshake
+    /// and then sends a Text frame.
+    struct InMemoryTransport {
+        connections: Arc<AtomicUsize>,
+    }
+
+    impl Transport for InMemoryTransport {
+        fn connect(&self, _addr: SocketAddr) -> TransportFuture<'_> {
+            self.connections.fetch_add(1, Ordering::SeqCst);
+            let (client, server) = io::duplex(1024);
+            tokio::spawn(async move {
+                let mut server = BufReader::new(server);
+                accept_handshake(&mut server, "").await.unwrap();
+                server.write_all(&[0x81, 2, b'h', b'i']).await.unwrap();
+                let _ = io::copy(&mut server, &mut io::sink()).await;
+            });
+            Box::pin(async move { Ok(Box::new(client) as Box<dyn TransportStream>) })
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_transport_is_used() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let mut ws = WebSocket::builder()
+            .transport(InMemoryTransport {
+                connections: connections.clone(),
+            })
+            .connect("ws://127.0.0.1:9/")
+            .await
+            .unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        let frame = ws.receive().await.unwrap();
+        assert_eq!(frame.as_text().unwrap().0, "hi");
+    }
+}
@@ -0,0 +1,354 @@
+//! Interoperability with tungstenite, available with the `tungstenite` feature.
+
+use std::convert::TryFrom;
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Sink, Stream};
+use tungstenite::protocol::CloseFrame;
+use tungstenite::Message;
+
+use super::driver::ConnectionDriver;
+use super::frame::Frame;
+use super::split::{WebSocketReadHalf, WebSocketWriteHalf};
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+type ReceiveFuture =
+    Pin<Box<dyn Future<Output = (WebSocketReadHalf, Result<Message, WebSocketError>)> + Send>>;
+type SendFuture =
+    Pin<Box<dyn Future<Output = (WebSocketWriteHalf, Result<(), WebSocketError>)> + Send>>;
+
+/// Wraps a [`WebSocket`] in the interface of tokio-tungstenite's `WebSocketStream`,
+/// so that code written against tokio-tungstenite can use this client with
+/// minimal changes.
+///
+/// The adapter is a [`Stream`] of tungstenite [`Message`]s and a [`Sink`] of them,
+/// so it is used with the `next()` and `send()` methods from
+/// [`StreamExt`](futures::StreamExt) and [`SinkExt`](futures::SinkExt)
+/// (and can be split with `StreamExt::split()`):
+///
+/// ```
+/// # use futures::{SinkExt, StreamExt};
+/// # use tungstenite::Message;
+/// # use websockets::{TungsteniteAdapter, WebSocket, WebSocketError};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let ws = WebSocket::connect("wss://echo.websocket.org").await?;
+/// let mut ws = TungsteniteAdapter::new(ws);
+/// ws.send(Message::text("foo")).await?;
+/// while let Some(message) = ws.next().await {
+///     println!("{:?}", message?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// As with tokio-tungstenite, fragmented messages are reassembled before
+/// they are returned, Ping frames are answered automatically, and the stream ends
+/// after a Close message is returned. Closing the sink sends a Close frame.
+pub struct TungsteniteAdapter {
+    /// None while a receive is in progress
+    read_half: Option<WebSocketReadHalf>,
+    receiving: Option<ReceiveFuture>,
+    /// None while a send is in progress
+    write_half: Option<WebSocketWriteHalf>,
+    sending: Option<SendFuture>,
+    /// Sends Pong and Close frames in response to received frames
+    driver: Option<ConnectionDriver>,
+    received_close: bool,
+    sent_close: bool,
+}
+
+impl Debug for TungsteniteAdapter {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("TungsteniteAdapter")
+    }
+}
+
+impl TungsteniteAdapter {
+    /// Wraps a WebSocket. Any [keepalive](crate::WebSocketBuilder::keepalive_interval())
+    /// settings are kept, but keepalive Pings are only sent while the adapter is polled.
+    pub fn new(ws: WebSocket) -> Self {
+        let (read_half, write_half, driver) = ws.split_with_driver();
+        Self {
+            read_half: Some(read_half),
+            receiving: None,
+            write_half: Some(write_half),
+            sending: None,
+            driver: Some(driver),
+            received_close: false,
+            sent_close: false,
+        }
+    }
+
+    /// Polls the driver so that events from the read half are acted upon.
+    fn poll_driver(&mut self, cx: &mut Context<'_>) -> Result<(), WebSocketError> {
+        if let Some(driver) = &mut self.driver {
+            if let Poll::Ready(result) = Pin::new(driver).poll(cx) {
+                self.driver = None;
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the send in progress, if any, to finish.
+    fn poll_sending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), WebSocketError>> {
+        self.poll_driver(cx)?;
+        let sending = match &mut self.sending {
+            Some(sending) => sending,
+            None => return Poll::Ready(Ok(())),
+        };
+        let (write_half, result) = match sending.as_mut().poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.sending = None;
+        self.write_half = Some(write_half);
+        Poll::Ready(result)
+    }
+}
+
+impl Stream for TungsteniteAdapter {
+    type Item = Result<Message, WebSocketError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.received_close {
+            return Poll::Ready(None);
+        }
+        if let Err(e) = self.poll_driver(cx) {
+            return Poll::Ready(Some(Err(e)));
+        }
+        if self.receiving.is_none() {
+            let mut read_half = match self.read_half.take() {
+                Some(read_half) => read_half,
+                None => return Poll::Ready(None),
+            };
+            self.receiving = Some(Box::pin(async move {
+                let result = receive_message(&mut read_half).await;
+                (read_half, result)
+            }));
+        }
+        let (read_half, result) = match self.receiving.as_mut().unwrap().as_mut().poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.receiving = None;
+        self.read_half = Some(read_half);
+        if let Ok(Message::Close(_)) = result {
+            self.received_close = true;
+        }
+        Poll::Ready(Some(result))
+    }
+}
+
+impl Sink<Message> for TungsteniteAdapter {
+    type Error = WebSocketError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_sending(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        let frame = Frame::try_from(message)?;
+        if frame.is_any_close() {
+            self.sent_close = true;
+        }
+        // poll_ready() must have returned Ready, so no send is in progress
+        let mut write_half = self
+            .write_half
+            .take()
+            .ok_or(WebSocketError::WebSocketClosedError)?;
+        self.sending = Some(Box::pin(async move {
+            let result = write_half.send(frame).await.map(|_receipt| ());
+            (write_half, result)
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_sending(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.as_mut().poll_sending(cx) {
+            Poll::Ready(Ok(())) => (),
+            poll => return poll,
+        }
+        if self.sent_close {
+            return Poll::Ready(Ok(()));
+        }
+        // https://tools.ietf.org/html/rfc6455#section-5.5.1
+        self.as_mut().start_send(Message::Close(None))?;
+        self.poll_sending(cx)
+    }
+}
+
+/// Receives frames until a complete message has been received
+/// (https://tools.ietf.org/html/rfc6455#section-5.4)
+async fn receive_message(read_half: &mut WebSocketReadHalf) -> Result<Message, WebSocketError> {
+    let mut message = match read_half.receive().await? {
+        Frame::Text {
+            payload,
+            fin: false,
+            ..
+        } => Frame::text(payload),
+        Frame::Binary {
+            payload,
+            fin: false,
+            ..
+        } => Frame::binary(payload),
+        frame => return Message::try_from(frame),
+    };
+    loop {
+        match read_half.receive().await? {
+            Frame::Text {
+                payload: fragment,
+                fin,
+                ..
+            } => {
+                if let Frame::Text { payload, .. } = &mut message {
+                    payload.push_str(&fragment);
+                }
+                if fin {
+                    return Message::try_from(message);
+                }
+            }
+            Frame::Binary {
+                payload: mut fragment,
+                fin,
+                ..
+            } => {
+                if let Frame::Binary { payload, .. } = &mut message {
+                    payload.append(&mut fragment);
+                }
+                if fin {
+                    return Message::try_from(message);
+                }
+            }
+            // a Close frame ends the connection, so the partial message is discarded
+            frame @ (Frame::Close { .. } | Frame::CloseRaw { .. }) => {
+                return Message::try_from(frame)
+            }
+            // Ping and Pong frames interleaved with the fragments are handled by the driver
+            _ => (),
+        }
+    }
+}
+
+impl TryFrom<Frame> for Message {
+    type Error = WebSocketError;
+
+    /// Converts a Frame into a tungstenite Message. Fails with
+    /// [`MessageConversionError`](WebSocketError::MessageConversionError)
+    /// if the frame is a fragment of a larger message, since tungstenite
+    /// Messages are always complete, or if it is a Close frame with a
+    /// [raw reason](Frame::close_raw()).
+    fn try_from(frame: Frame) -> Result<Self, Self::Error> {
+        match frame {
+            Frame::Text {
+                payload,
+                continuation: false,
+                fin: true,
+            } => Ok(Message::Text(payload.into())),
+            Frame::Binary {
+                payload,
+                continuation: false,
+                fin: true,
+            } => Ok(Message::Binary(payload.into())),
+            Frame::Text { .. } | Frame::Binary { .. } => {
+                Err(WebSocketError::MessageConversionError)
+            }
+            // tungstenite Close reasons are UTF-8
+            Frame::CloseRaw { .. } => Err(WebSocketError::MessageConversionError),
+            Frame::Close { payload } => {
+                Ok(Message::Close(payload.map(|(code, reason)| CloseFrame {
+                    code: code.into(),
+                    reason: reason.into(),
+                })))
+            }
+            Frame::Ping { payload } => Ok(Message::Ping(payload.unwrap_or_default().into())),
+            Frame::Pong { payload } => Ok(Message::Pong(payload.unwrap_or_default().into())),
+        }
+    }
+}
+
+impl TryFrom<Message> for Frame {
+    type Error = WebSocketError;
+
+    /// Converts a tungstenite Message into a Frame. Fails with
+    /// [`MessageConversionError`](WebSocketError::MessageConversionError)
+    /// if the message is a raw tungstenite frame.
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        match message {
+            Message::Text(payload) => Ok(Frame::text(payload.as_str().to_string())),
+            Message::Binary(payload) => Ok(Frame::binary(payload.to_vec())),
+            Message::Close(close_frame) => Ok(Frame::close(close_frame.map(|close_frame| {
+                (
+                    close_frame.code.into(),
+                    close_frame.reason.as_str().to_string(),
+                )
+            }))),
+            Message::Ping(payload) => Ok(Frame::ping(non_empty(payload.to_vec()))),
+            Message::Pong(payload) => Ok(Frame::pong(non_empty(payload.to_vec()))),
+            Message::Frame(_) => Err(WebSocketError::MessageConversionError),
+        }
+    }
+}
+
+/// Empty control frame payloads are represented as None,
+/// the same as when they are received
+fn non_empty(payload: Vec<u8>) -> Option<Vec<u8>> {
+    if payload.is_empty() {
+        None
+    } else {
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+
+    use super::*;
+    use crate::test_util::recording_server;
+
+    #[test]
+    fn round_trip() {
+        let frames = vec![
+            Frame::text("foo".to_string()),
+            Frame::binary(vec![1, 2, 3]),
+            Frame::close(Some((1000, "bye".to_string()))),
+            Frame::close(None),
+            Frame::ping(Some(vec![4])),
+            Frame::pong(None),
+        ];
+        for frame in frames {
+            let message = Message::try_from(frame.clone()).unwrap();
+            let converted = Frame::try_from(message).unwrap();
+            assert_eq!(format!("{:?}", converted), format!("{:?}", frame));
+        }
+    }
+
+    #[test]
+    fn fragments_are_rejected() {
+        let fragment = Frame::text("foo".to_string()).set_fin(false);
+        assert!(matches!(
+            Message::try_from(fragment),
+            Err(WebSocketError::MessageConversionError)
+        ));
+    }
+    #[tokio::test]
+    async fn only_one_close_frame_is_sent() {
+        let (url, opcodes) = recording_server().await;
+        let mut ws = TungsteniteAdapter::new(WebSocket::connect(&url).await.unwrap());
+        ws.send(Message::Close(None)).await.unwrap();
+        ws.close().await.unwrap();
+        ws.close().await.unwrap();
+        drop(ws);
+        assert_eq!(opcodes.await.unwrap(), vec![0x8]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Typed JSON message protocols, available with the `macros` feature.
+
+use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::driver::ConnectionDriver;
+use super::frame::Frame;
+use super::split::{SendReceipt, WebSocketReadHalf, WebSocketWriteHalf};
+use super::WebSocket;
+use crate::error::WebSocketError;
+
+/// A WebSocket client which sends and receives typed messages, serialized as JSON.
+/// `In` is the type of messages received from the server, and `Out` is the type
+/// of messages sent to the server.
+///
+/// This is usually generated for a protocol's message enums with the
+/// [`#[websocket_protocol]`](crate::websocket_protocol) macro:
+///
+/// ```no_run
+/// # use websockets::WebSocketError;
+/// #[websockets::websocket_protocol]
+/// mod chat {
+///     #[derive(Debug)]
+///     #[serde(tag = "type")]
+///     pub enum Inbound {
+///         Joined { user: String },
+///         Message { user: String, text: String },
+///     }
+///
+///     #[derive(Debug)]
+///     #[serde(tag = "type")]
+///     pub enum Outbound {
+///         Say { text: String },
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), WebSocketError> {
+/// let (mut client, driver) = chat::connect("wss://chat.example.com").await?;
+/// tokio::spawn(driver);
+/// client
+///     .send(&chat::Outbound::Say {
+///         text: "hello".to_string(),
+///     })
+///     .await?;
+/// while let Some(message) = client.receive().await? {
+///     println!("{:?}", message);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Outgoing messages are sent as single Text frames. Incoming Text and Binary messages
+/// are reassembled from their frames before they are deserialized, and Ping and Pong
+/// frames are handled by the [`ConnectionDriver`] returned alongside the client,
+/// which must be spawned. Serialization errors are returned as
+/// [`SerializeError`](WebSocketError::SerializeError) and
+/// [`DeserializeError`](WebSocketError::DeserializeError).
+pub struct TypedWebSocket<In, Out> {
+    read_half: WebSocketReadHalf,
+    write_half: WebSocketWriteHalf,
+    // fn pointers so that the client is Send and Sync regardless of the message types
+    _messages: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> Debug for TypedWebSocket<In, Out> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("TypedWebSocket")
+    }
+}
+
+impl<In, Out> TypedWebSocket<In, Out>
+where
+    In: DeserializeOwned,
+    Out: Serialize,
+{
+    /// Wraps a WebSocket, returning the client and the [`ConnectionDriver`]
+    /// for the connection, which must be spawned
+    /// (see [`WebSocket::split_with_driver()`]).
+    pub fn new(ws: WebSocket) -> (Self, ConnectionDriver) {
+        let (read_half, write_half, driver) = ws.split_with_driver();
+        let client = Self {
+            read_half,
+            write_half,
+            _messages: PhantomData,
+        };
+        (client, driver)
+    }
+
+    /// Serializes a message as JSON and sends it in a Text frame.
+    pub async fn send(&mut self, message: &Out) -> Result<SendReceipt, WebSocketError> {
+        let payload = serde_json::to_string(message).map_err(WebSocketError::SerializeError)?;
+        self.write_half.send_text(payload).await
+    }
+
+    /// Receives the next message and deserializes it from JSON,
+    /// or returns `None` if the server closed the connection.
+    pub async fn receive(&mut self) -> Result<Option<In>, WebSocketError> {
+        // https://tools.ietf.org/html/rfc6455#section-5.4
+        let mut message = Vec::new();
+        loop {
+            match self.read_half.receive().await? {
+                Frame::Text { payload, fin, .. } => {
+                    message.extend_from_slice(payload.as_bytes());
+                    if fin {
+                        break;
+                    }
+                }
+                Frame::Binary {
+                    mut payload, fin, ..
+                } => {
+                    message.append(&mut payload);
+                    if fin {
+                        break;
+                    }
+                }
+                Frame::Close { .. } | Frame::CloseRaw { .. } => return Ok(None),
+                // answered by the driver
+                Frame::Ping { .. } | Frame::Pong { .. } => (),
+            }
+        }
+        serde_json::from_slice(&message)
+            .map(Some)
+            .map_err(WebSocketError::DeserializeError)
+    }
+
+    /// Sends a Close frame over the WebSocket connection
+    /// (see [`WebSocketWriteHalf::close()`]).
+    pub async fn close(&mut self, payload: Option<(u16, String)>) -> Result<(), WebSocketError> {
+        self.write_half.close(payload).await
+    }
+
+    /// Returns the read half and write half of the underlying WebSocket,
+    /// for sending and receiving untyped frames.
+    pub fn into_inner(self) -> (WebSocketReadHalf, WebSocketWriteHalf) {
+        (self.read_half, self.write_half)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::test_util::scripted_server;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Greeting(String),
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages_are_deserialized() {
+        // {"Greeting":"hi"} split across a Text frame and a continuation frame,
+        // followed by invalid JSON and a Close frame
+        let url =
+            scripted_server(b"\x01\x0b{\"Greeting\"\x80\x06:\"hi\"}\x81\x03foo\x88\x02\x03\xe8")
+                .await;
+        let ws = WebSocket::connect(&url).await.unwrap();
+        let (mut client, driver) = TypedWebSocket::<Message, Message>::new(ws);
+        tokio::spawn(driver);
+        assert_eq!(
+            client.receive().await.unwrap(),
+            Some(Message::Greeting("hi".to_string()))
+        );
+        assert!(matches!(
+            client.receive().await,
+            Err(WebSocketError::DeserializeError(_))
+        ));
+        assert_eq!(client.receive().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::{Debug, Error as FmtError, Formatter, Write as FmtWrite};
+use std::io::{Error as IoError, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[allow(unused_imports)] // for intra doc links
+use super::builder::WebSocketBuilder;
+use super::recording::FrameDirection;
+use super::stream::Stream;
+
+/// The writer set with [`WebSocketBuilder::wire_trace()`], shared by every connection
+/// made with the builder's settings (including redialed connections).
+#[derive(Clone)]
+pub(super) struct WireTrace(Arc<StdMutex<TraceState>>);
+
+impl Debug for WireTrace {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        f.write_str("WireTrace")
+    }
+}
+
+struct TraceState {
+    /// None once writing to it has failed
+    writer: Option<Pin<Box<dyn AsyncWrite + Send>>>,
+    /// Formatted output which has not been written yet
+    pending: Vec<u8>,
+}
+
+impl WireTrace {
+    pub(super) fn new<W>(writer: W) -> Self
+    where
+        W: AsyncWrite + Send + 'static,
+    {
+        Self(Arc::new(StdMutex::new(TraceState {
+            writer: Some(Box::pin(writer)),
+            pending: Vec::new(),
+        })))
+    }
+
+    fn record(&self, direction: FrameDirection, bytes: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        if state.writer.is_some() {
+            state
+                .pending
+                .extend_from_slice(hex_dump(direction, bytes).as_bytes());
+        }
+    }
+
+    /// Writes the pending output and flushes the writer. Errors stop the trace
+    /// instead of failing the connection.
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        let TraceState { writer, pending } = &mut *state;
+        let result = match writer {
+            Some(writer) => loop {
+                if pending.is_empty() {
+                    break writer.as_mut().poll_flush(cx);
+                }
+                match writer.as_mut().poll_write(cx, pending) {
+                    Poll::Ready(Ok(0)) => break Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                    Poll::Ready(Ok(n)) => {
+                        pending.drain(..n);
+                    }
+                    Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            },
+            None => return Poll::Ready(()),
+        };
+        if let Poll::Ready(Err(_)) = result {
+            *writer = None;
+            pending.clear();
+        }
+        result.map(|_| ())
+    }
+}
+
+/// Formats bytes in the style of `hexdump -C`, under a line with their direction
+fn hex_dump(direction: FrameDirection, bytes: &[u8]) -> String {
+    let mut dump = match direction {
+        FrameDirection::Inbound => format!("<< received {} bytes\n", bytes.len()),
+        FrameDirection::Outbound => format!(">> sent {} bytes\n", bytes.len()),
+    };
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", i * 16);
+        for column in 0..16 {
+            // an extra space separates the two groups of eight bytes
+            if column == 8 {
+                dump.push(' ');
+            }
+            match line.get(column) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(dump, "  |{}|", ascii);
+    }
+    dump.push('\n');
+    dump
+}
+
+/// A stream which copies everything read from and written to it into a [`WireTrace`]
+#[derive(Debug)]
+pub(super) struct TracedStream {
+    stream: Stream,
+    trace: WireTrace,
+}
+
+impl TracedStream {
+    pub(super) fn new(stream: Stream, trace: WireTrace) -> Self {
+        Self { stream, trace }
+    }
+
+    pub(super) fn get_ref(&self) -> &Stream {
+        &self.stream
+    }
+}
+
+impl AsyncRead for TracedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = &buf.filled()[filled..];
+            if !read.is_empty() {
+                this.trace.record(FrameDirection::Inbound, read);
+                // write the trace without waiting for it, so that reading is not slowed down
+                let _ = this.trace.poll_flush(cx);
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for TracedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.trace.record(FrameDirection::Outbound, &buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        ready!(this.trace.poll_flush(cx));
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        ready!(this.trace.poll_flush(cx));
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{SessionReplay, WebSocket};
+
+    #[test]
+    fn bytes_are_dumped_as_hex_and_ascii() {
+        let dump = hex_dump(FrameDirection::Outbound, b"GET / HTTP/1.1\r\nHost: a\r\n");
+        assert_eq!(
+            dump,
+            ">> sent 25 bytes\n\
+            00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n\
+            00000010  48 6f 73 74 3a 20 61 0d  0a                       |Host: a..|\n\n"
+        );
+        let dump = hex_dump(FrameDirection::Inbound, &[0x81, 0x02, b'h', b'i']);
+        assert_eq!(
+            dump,
+            "<< received 4 bytes\n\
+            00000000  81 02 68 69                                       |..hi|\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn traffic_is_traced() {
+        let recording =
+            r#"{"timestamp_us":1,"direction":"inbound","fin":true,"opcode":1,"payload":"aGk="}"#;
+        let (trace_writer, mut trace_reader) = tokio::io::duplex(64 * 1024);
+        let mut ws = WebSocket::builder()
+            .transport(SessionReplay::from_reader(recording.as_bytes()).unwrap())
+            .wire_trace(trace_writer)
+            .connect("ws://127.0.0.1/")
+            .await
+            .unwrap();
+        ws.receive().await.unwrap();
+        drop(ws);
+
+        let mut trace = String::new();
+        trace_reader.read_to_string(&mut trace).await.unwrap();
+        assert!(trace.starts_with(">> sent "));
+        assert!(trace.contains("|GET / HTTP/1.1..|"));
+        assert!(trace.contains("<< received "));
+        assert!(trace.contains("|HTTP/1.1 101 Swi|"));
+        assert!(trace.contains("81 02 68 69"));
+    }
+}